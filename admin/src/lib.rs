@@ -78,8 +78,16 @@ pub fn run() -> Result<(), String> {
 			dest_options.sync_wal = false;
 			dest_options.sync_data = false;
 
-			parity_db::migrate(&db_path, dest_options, args.overwrite, &args.force_columns)
-				.map_err(|e| format!("Migration error: {e:?}"))?;
+			let migration_options =
+				parity_db::MigrationOptions { deterministic: args.deterministic };
+			parity_db::migrate_with_options(
+				&db_path,
+				dest_options,
+				args.overwrite,
+				&args.force_columns,
+				&migration_options,
+			)
+			.map_err(|e| format!("Migration error: {e:?}"))?;
 
 			if args.overwrite && std::fs::metadata(&args.dest_path).is_ok() {
 				std::fs::remove_dir_all(&args.dest_path)
@@ -242,6 +250,11 @@ pub struct Migrate {
 	#[clap(long)]
 	pub overwrite: bool,
 
+	/// Insert values in key order, so that migrating
+	/// the same data produces identical tables.
+	#[clap(long)]
+	pub deterministic: bool,
+
 	/// Clear destination folder before migration.
 	#[clap(long)]
 	pub clear_dest: bool,
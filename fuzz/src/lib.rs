@@ -168,6 +168,26 @@ pub trait DbSimulator {
 			stats: false,
 			salt: Some([0; 32]),
 			compression_threshold: HashMap::new(),
+			encryption: HashMap::new(),
+			reuse_read_buffers: false,
+			dsync_writes: false,
+			prefetch_depth: HashMap::new(),
+			iter_read_ahead: HashMap::new(),
+			grow_step_bytes: HashMap::new(),
+			access_pattern: HashMap::new(),
+			header_durability: HashMap::new(),
+			max_chain_depth: HashMap::new(),
+			max_value_len: HashMap::new(),
+			slot_reuse_grace: HashMap::new(),
+			verify_writes: false,
+			value_write_barrier: false,
+			trusted_open: false,
+			validate_free_lists_on_open: false,
+			replace_freed_as_insert: false,
+			parallel_enact: false,
+			max_log_overlay_bytes: None,
+			direct_io: false,
+			mmap_reads: false,
 			always_flush: true,
 			with_background_thread: false,
 		};
@@ -12,7 +12,7 @@ use crate::{
 	parking_lot::RwLock,
 	table::{
 		key::{TableKey, TableKeyQuery},
-		Entry as ValueTableEntry, Value, ValueTable,
+		Entry as ValueTableEntry, TableId, TableManifest, Value, ValueTable,
 	},
 	Operation,
 };
@@ -160,6 +160,7 @@ impl BTreeTable {
 					.get(&id)
 					.copied()
 					.unwrap_or(DEFAULT_COMPRESSION_THRESHOLD),
+				metadata.version,
 			),
 		})
 	}
@@ -243,6 +244,34 @@ impl BTreeTable {
 		Ok(())
 	}
 
+	pub fn verify_plan(&self, action: LogAction, log: &mut LogReader) -> Result<()> {
+		match action {
+			LogAction::InsertValue(record) =>
+				self.tables.read()[record.table.size_tier() as usize].verify_plan(record.index, log),
+			_ => Err(Error::Corruption("Unexpected log action".to_string())),
+		}
+	}
+
+	pub fn read_value_plan(&self, action: LogAction, log: &mut LogReader) -> Result<Vec<u8>> {
+		match action {
+			LogAction::InsertValue(record) =>
+				self.tables.read()[record.table.size_tier() as usize].read_plan(record.index, log),
+			_ => Err(Error::Corruption("Unexpected log action".to_string())),
+		}
+	}
+
+	pub fn enact_values(&self, table: TableId, entries: &[(u64, Vec<u8>)]) -> Result<()> {
+		self.tables.read()[table.size_tier() as usize].enact_entries(entries)
+	}
+
+	pub fn skip_plan(&self, action: LogAction, log: &mut LogReader) -> Result<()> {
+		match action {
+			LogAction::InsertValue(record) =>
+				self.tables.read()[record.table.size_tier() as usize].skip_plan(record.index, log),
+			_ => Err(Error::Corruption("Unexpected log action".to_string())),
+		}
+	}
+
 	pub fn validate_plan(&self, action: LogAction, log: &mut LogReader) -> Result<()> {
 		let tables = self.tables.upgradable_read();
 		match action {
@@ -273,6 +302,26 @@ impl BTreeTable {
 		Ok(())
 	}
 
+	pub fn verify_free_lists(&self, log: &impl LogQuery) -> Result<()> {
+		let tables = self.tables.read();
+		crate::column::verify_free_lists(tables.iter(), log)
+	}
+
+	pub fn persist_free_lists(&self) -> Result<()> {
+		let tables = self.tables.read();
+		for t in tables.iter() {
+			t.persist_free_list()?;
+		}
+		Ok(())
+	}
+
+	pub fn write_manifest_entries(&self, manifest: &mut TableManifest) {
+		let tables = self.tables.read();
+		for t in tables.iter() {
+			t.write_manifest_entry(manifest);
+		}
+	}
+
 	fn write_plan_remove_node(
 		tables: TablesRef,
 		writer: &mut LogWriter,
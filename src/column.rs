@@ -3,23 +3,27 @@
 
 use crate::{
 	btree::BTreeTable,
-	compress::Compress,
+	compress::{Compress, NO_COMPRESSION},
 	db::{check::CheckDisplay, Operation, RcValue},
 	display::hex,
-	error::{Error, Result},
+	encrypt::Cipher,
+	error::{try_io, Error, Result},
+	file::Durability,
 	index::{Address, IndexTable, PlanOutcome, TableId as IndexTableId},
 	log::{Log, LogAction, LogOverlays, LogQuery, LogReader, LogWriter},
 	options::{ColumnOptions, Metadata, Options, DEFAULT_COMPRESSION_THRESHOLD},
-	parking_lot::{RwLock, RwLockUpgradableReadGuard, RwLockWriteGuard},
+	parking_lot::{Mutex, RwLock, RwLockUpgradableReadGuard, RwLockWriteGuard},
 	stats::{ColumnStatSummary, ColumnStats},
 	table::{
 		key::{TableKey, TableKeyQuery},
-		TableId as ValueTableId, Value, ValueTable, SIZE_TIERS,
+		EntrySizing, TableConfig, TableId as ValueTableId, TableManifest, Value, ValueTable,
+		SIZE_TIERS,
 	},
 	Key,
 };
 use std::{
-	collections::VecDeque,
+	collections::{BTreeMap, VecDeque},
+	convert::TryInto,
 	sync::{
 		atomic::{AtomicU64, Ordering},
 		Arc,
@@ -29,6 +33,7 @@ use std::{
 const MIN_INDEX_BITS: u8 = 16;
 // Measured in index entries
 const MAX_REINDEX_BATCH: usize = 8192;
+const SIDECAR_ENTRY_SIZE: usize = 8 + 32;
 
 pub type ColId = u8;
 pub type Salt = [u8; 32];
@@ -82,6 +87,74 @@ struct Reindex {
 	progress: AtomicU64,
 }
 
+// Key sidecar file (optional)
+// [COUNT: 8][ADDRESS: 8, KEY: 32]*COUNT[CHECKSUM: 4]
+// ADDRESS - value address of the indexed entry.
+// KEY - full key of the entry.
+// CHECKSUM - CRC-32 of all preceding bytes.
+#[derive(Debug)]
+struct KeySidecar {
+	path: std::path::PathBuf,
+	// Keys by value address. `None` until rebuilt from the index.
+	keys: Option<BTreeMap<u64, Key>>,
+}
+
+impl KeySidecar {
+	fn file_name(col: ColId) -> String {
+		// Shares the index file prefix, so that column files are handled together.
+		format!("index_{col:02}_keys")
+	}
+
+	fn load(path: &std::path::Path) -> Result<Option<BTreeMap<u64, Key>>> {
+		let data = match std::fs::read(path) {
+			Ok(data) => data,
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+			Err(e) => return Err(Error::Io(e)),
+		};
+		if data.len() < 12 || (data.len() - 12) % SIDECAR_ENTRY_SIZE != 0 {
+			return Ok(None)
+		}
+		let (body, checksum) = data.split_at(data.len() - 4);
+		if crc32fast::hash(body).to_le_bytes() != checksum {
+			return Ok(None)
+		}
+		let count = u64::from_le_bytes(body[0..8].try_into().unwrap());
+		if count != ((body.len() - 8) / SIDECAR_ENTRY_SIZE) as u64 {
+			return Ok(None)
+		}
+		let keys = body[8..]
+			.chunks_exact(SIDECAR_ENTRY_SIZE)
+			.map(|e| (u64::from_le_bytes(e[0..8].try_into().unwrap()), e[8..].try_into().unwrap()))
+			.collect();
+		Ok(Some(keys))
+	}
+
+	fn save(&self) -> Result<()> {
+		let Some(keys) = &self.keys else { return Ok(()) };
+		let mut data = Vec::with_capacity(12 + keys.len() * SIDECAR_ENTRY_SIZE);
+		data.extend_from_slice(&(keys.len() as u64).to_le_bytes());
+		for (address, key) in keys {
+			data.extend_from_slice(&address.to_le_bytes());
+			data.extend_from_slice(key);
+		}
+		data.extend_from_slice(&crc32fast::hash(&data).to_le_bytes());
+		let file = try_io!(std::fs::File::create(&self.path));
+		try_io!(std::io::Write::write_all(&mut &file, &data));
+		try_io!(file.sync_data());
+		Ok(())
+	}
+
+	// Saved keys are only valid until the column is modified. Make sure stale keys are never
+	// loaded after a crash.
+	fn invalidate(&self) -> Result<()> {
+		if let Ok(file) = std::fs::OpenOptions::new().write(true).open(&self.path) {
+			try_io!(file.set_len(0));
+			try_io!(file.sync_data());
+		}
+		Ok(())
+	}
+}
+
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 pub enum Column {
@@ -99,10 +172,12 @@ pub struct HashColumn {
 	uniform_keys: bool,
 	collect_stats: bool,
 	ref_counted: bool,
+	append_only: bool,
 	salt: Salt,
 	stats: ColumnStats,
 	compression: Compress,
 	db_version: u32,
+	key_sidecar: Option<Mutex<KeySidecar>>,
 }
 
 #[derive(Clone, Copy)]
@@ -122,6 +197,17 @@ pub struct ValueIterState {
 	pub value: Vec<u8>,
 }
 
+/// Value that could not be read or decompressed during iteration.
+#[derive(Debug)]
+pub struct CorruptedValueInfo {
+	/// Size tier of the value table.
+	pub size_tier: u8,
+	/// Slot index in the value table.
+	pub index: u64,
+	/// Read or decompression error.
+	pub error: Error,
+}
+
 // Only used for DB validation and migration.
 pub struct CorruptedIndexEntryInfo {
 	pub chunk_index: u64,
@@ -146,6 +232,23 @@ enum IterStateOrCorrupted {
 	Corrupted(CorruptedIndexEntryInfo),
 }
 
+/// Fail with `Error::Corruption` on the first value table with a broken removed entries list.
+pub fn verify_free_lists<'a>(
+	tables: impl Iterator<Item = &'a ValueTable>,
+	log: &impl LogQuery,
+) -> Result<()> {
+	for t in tables {
+		let report = t.verify_free_list(log)?;
+		if let Some(anomaly) = report.anomaly {
+			return Err(Error::Corruption(format!(
+				"{}: Bad free list after {} entries: {:?}",
+				t.id, report.len, anomaly
+			)))
+		}
+	}
+	Ok(())
+}
+
 #[inline]
 pub fn hash_key(key: &[u8], salt: &Salt, uniform: bool, db_version: u32) -> Key {
 	use blake2::{
@@ -198,49 +301,163 @@ pub struct ReindexBatch {
 }
 
 impl HashColumn {
+	/// Check that the values set by `changes` fit their value tables, before anything is queued.
+	pub fn validate_changes(&self, changes: &[Operation<Key, RcValue>]) -> Result<()> {
+		let tables = self.tables.read();
+		let values = self.as_ref(&tables.value);
+		// Compression never makes a stored value longer, and only longer values are rejected.
+		// Values that fit uncompressed are not compressed for the check.
+		let entries: Vec<_> = changes
+			.iter()
+			.filter_map(|change| match change {
+				Operation::Set(key, value) => Some((*key, value.value().as_slice())),
+				_ => None,
+			})
+			.filter(|(key, value)| {
+				let key = TableKey::Partial(*key);
+				let (_, tier) = Column::compress(&NO_COMPRESSION, &key, value, values.tables);
+				values.tables[tier].check_value_len(&key, value.len()).is_err()
+			})
+			.collect();
+		Column::validate_batch(&entries, values)?;
+		Ok(())
+	}
+
 	pub fn get(&self, key: &Key, log: &impl LogQuery) -> Result<Option<Value>> {
+		self.get_strict(key, log, false)
+	}
+
+	/// Same as `get`, but fails with `Error::KeyMismatch` when the key is not found and an index
+	/// entry for it points to a value with another key. That is a sign of a corrupted index, but
+	/// may also be another key sharing the same index entry bits.
+	pub fn get_checked(&self, key: &Key, log: &impl LogQuery) -> Result<Option<Value>> {
+		self.get_strict(key, log, true)
+	}
+
+	fn get_strict(&self, key: &Key, log: &impl LogQuery, strict: bool) -> Result<Option<Value>> {
 		let tables = self.tables.read();
 		let values = self.as_ref(&tables.value);
-		if let Some((tier, value)) = self.get_in_index(key, &tables.index, values, log)? {
+		let mut mismatch = None;
+		if let Some((tier, value)) =
+			self.get_in_index(key, &tables.index, values, log, strict.then_some(&mut mismatch))?
+		{
 			if self.collect_stats {
 				self.stats.query_hit(tier);
 			}
 			return Ok(Some(value))
 		}
 		for r in &self.reindex.read().queue {
-			if let Some((tier, value)) = self.get_in_index(key, r, values, log)? {
+			if let Some((tier, value)) =
+				self.get_in_index(key, r, values, log, strict.then_some(&mut mismatch))?
+			{
 				if self.collect_stats {
 					self.stats.query_hit(tier);
 				}
 				return Ok(Some(value))
 			}
 		}
+		if let Some(e) = mismatch {
+			return Err(e)
+		}
 		if self.collect_stats {
 			self.stats.query_miss();
 		}
 		Ok(None)
 	}
 
+	/// Get values for a batch of keys. Values are read ordered by their location in the value
+	/// tables rather than in key order, and adjacent entries are read from the file together. Keys
+	/// that are not found at the first index entry are looked up with `get`.
+	pub fn get_batch(&self, keys: &[Key], log: &impl LogQuery) -> Result<Vec<Option<Value>>> {
+		let mut values = vec![None; keys.len()];
+		let mut missing = Vec::new();
+		{
+			let tables = self.tables.read();
+			let index = &tables.index;
+			let mut requests = vec![Vec::new(); tables.value.len()];
+			for (i, key) in keys.iter().enumerate() {
+				let (entry, _) = index.get(key, 0, log)?;
+				if entry.is_empty() {
+					missing.push(i);
+					continue
+				}
+				let address = entry.address(index.id.index_bits());
+				requests[address.size_tier() as usize].push((i, address.offset()));
+			}
+			for (tier, requests) in requests.into_iter().enumerate() {
+				if requests.is_empty() {
+					continue
+				}
+				let table_keys: Vec<_> = requests
+					.iter()
+					.map(|(i, offset)| (TableKey::Partial(keys[*i]), *offset))
+					.collect();
+				let found = tables.value[tier].get_many(&table_keys, log)?;
+				for ((i, _), value) in requests.into_iter().zip(found) {
+					let Some((value, compressed)) = value else {
+						missing.push(i);
+						continue
+					};
+					if self.collect_stats {
+						self.stats.query_hit(tier as u8);
+					}
+					values[i] =
+						Some(if compressed { self.compression.decompress(&value)? } else { value });
+				}
+			}
+		}
+		for i in missing {
+			values[i] = self.get(&keys[i], log)?;
+		}
+		Ok(values)
+	}
+
 	pub fn get_size(&self, key: &Key, log: &RwLock<LogOverlays>) -> Result<Option<u32>> {
 		self.get(key, log).map(|v| v.map(|v| v.len() as u32))
 	}
 
+	// Look up `key` in `index`. With `mismatch`, the first entry that holds another key is recorded
+	// in it.
 	fn get_in_index(
 		&self,
 		key: &Key,
 		index: &IndexTable,
 		tables: TablesRef,
 		log: &impl LogQuery,
+		mut mismatch: Option<&mut Option<Error>>,
 	) -> Result<Option<(u8, Value)>> {
+		let table_key = TableKey::Partial(*key);
 		let (mut entry, mut sub_index) = index.get(key, 0, log)?;
 		while !entry.is_empty() {
 			let address = entry.address(index.id.index_bits());
-			let value = Column::get_value(
-				TableKeyQuery::Check(&TableKey::Partial(*key)),
-				address,
-				tables,
-				log,
-			)?;
+			let value = match &mut mismatch {
+				None => Column::get_value(TableKeyQuery::Check(&table_key), address, tables, log)?,
+				Some(mismatch) => {
+					let mut found = Default::default();
+					let value = Column::get_value(
+						TableKeyQuery::Fetch(Some(&mut found)),
+						address,
+						tables,
+						log,
+					)?;
+					match value {
+						Some(_) if !table_key.compare(&Some(found)) => {
+							if mismatch.is_none() {
+								**mismatch = Some(Error::KeyMismatch {
+									table: tables.tables[address.size_tier() as usize]
+										.id
+										.to_string(),
+									index: address.offset(),
+									expected: crate::table::key::partial_key(key).to_vec(),
+									found: found.to_vec(),
+								});
+							}
+							None
+						},
+						value => value,
+					}
+				},
+			};
 			match value {
 				Some(result) => return Ok(Some(result)),
 				None => {
@@ -308,13 +525,87 @@ impl Column {
 		(result, target_tier)
 	}
 
-	pub fn open(col: ColId, options: &Options, metadata: &Metadata) -> Result<Column> {
+	/// Find the value table each value of a batch is written to, without writing anything.
+	/// Values are routed the same way as on commit, including compression. Fails with
+	/// `Error::InvalidInput` naming the first key with a value that does not fit its table.
+	pub fn validate_batch(
+		entries: &[(Key, &[u8])],
+		tables: TablesRef,
+	) -> Result<Vec<ValueTableId>> {
+		let mut ids = Vec::with_capacity(entries.len());
+		for (key, value) in entries {
+			let table_key = TableKey::Partial(*key);
+			let (cval, tier) = Self::compress(tables.compression, &table_key, value, tables.tables);
+			let len = cval.as_ref().map_or(value.len(), |cval| cval.len());
+			let table = &tables.tables[tier];
+			match table.check_value_len(&table_key, len) {
+				Ok(()) => (),
+				Err(e @ Error::ValueTooLarge { .. }) => return Err(e),
+				Err(e) => return Err(Error::InvalidInput(format!("Key {}: {}", hex(key), e))),
+			}
+			ids.push(table.id);
+		}
+		Ok(ids)
+	}
+
+	pub fn open(
+		col: ColId,
+		options: &Options,
+		metadata: &Metadata,
+		manifest: Option<&TableManifest>,
+		read_only: bool,
+	) -> Result<Column> {
 		let path = &options.path;
 		let arc_path = Arc::new(path.clone());
 		let column_options = &metadata.columns[col as usize];
 		let db_version = metadata.version;
+		let cipher = options.encryption.get(&col).map(Cipher::new);
+		let prefetch_depth = options.prefetch_depth.get(&col).copied().unwrap_or(0);
+		let iter_read_ahead = options.iter_read_ahead.get(&col).copied().unwrap_or(0);
+		let grow_step = options
+			.grow_step_bytes
+			.get(&col)
+			.copied()
+			.unwrap_or(crate::file::GROW_SIZE_BYTES);
+		let max_chain_depth = options.max_chain_depth.get(&col).copied();
+		let max_value_len = options.max_value_len.get(&col).copied();
+		let slot_reuse_grace = options.slot_reuse_grace.get(&col).copied();
+		let access_pattern = options.access_pattern.get(&col).copied().unwrap_or_default();
+		let header_durability = match options.header_durability.get(&col).copied() {
+			// The write barrier syncs the data after the values of each record are enacted.
+			Some(Durability::Data) if options.value_write_barrier => Durability::None,
+			durability => durability.unwrap_or_default(),
+		};
 		let value = (0..SIZE_TIERS)
-			.map(|i| Self::open_table(arc_path.clone(), col, i as u8, column_options, db_version))
+			.map(|i| {
+				let config = TableConfig {
+					db_version,
+					cipher: cipher.clone(),
+					reuse_read_buffer: options.reuse_read_buffers,
+					dsync: options.dsync_writes,
+					read_only,
+					manifest,
+				};
+				let mut table =
+					Self::open_table(arc_path.clone(), col, i as u8, column_options, config)?;
+				table.set_prefetch_depth(prefetch_depth);
+				table.set_iter_read_ahead(iter_read_ahead);
+				table.set_grow_step(grow_step);
+				table.set_max_chain_depth(max_chain_depth);
+				table.set_max_value_len(max_value_len);
+				table.set_slot_reuse_grace(slot_reuse_grace)?;
+				table.set_replace_freed_as_insert(options.replace_freed_as_insert);
+				table.set_sync_directory(options.sync_data);
+				table.set_access_pattern(access_pattern)?;
+				table.set_durability(header_durability);
+				if options.direct_io {
+					table.enable_direct_io()?;
+				}
+				if options.mmap_reads {
+					table.enable_mmap_reads()?;
+				}
+				Ok(table)
+			})
 			.collect::<Result<_>>()?;
 
 		if column_options.btree_index {
@@ -329,11 +620,14 @@ impl Column {
 		col: ColId,
 		tier: u8,
 		options: &ColumnOptions,
-		db_version: u32,
+		config: TableConfig,
 	) -> Result<ValueTable> {
 		let id = ValueTableId::new(col, tier);
-		let entry_size = SIZES.get(tier as usize).cloned();
-		ValueTable::open(path, id, entry_size, options, db_version)
+		let entry_size = match SIZES.get(tier as usize) {
+			Some(size) => EntrySizing::Single(*size),
+			None => EntrySizing::Multipart { chunk: options.multipart_entry_size },
+		};
+		ValueTable::open(path, id, entry_size, options, config)
 	}
 }
 
@@ -349,6 +643,16 @@ impl HashColumn {
 		let path = &options.path;
 		let col_options = &metadata.columns[col as usize];
 		let db_version = metadata.version;
+		let key_sidecar = if col_options.key_sidecar {
+			let mut sidecar_path = path.clone();
+			sidecar_path.push(KeySidecar::file_name(col));
+			let keys = KeySidecar::load(&sidecar_path)?;
+			let sidecar = KeySidecar { path: sidecar_path, keys };
+			sidecar.invalidate()?;
+			Some(Mutex::new(sidecar))
+		} else {
+			None
+		};
 		Ok(HashColumn {
 			col,
 			tables: RwLock::new(Tables { index, value }),
@@ -357,6 +661,7 @@ impl HashColumn {
 			preimage: col_options.preimage,
 			uniform_keys: col_options.uniform,
 			ref_counted: col_options.ref_counted,
+			append_only: col_options.append_only,
 			collect_stats,
 			salt: metadata.salt,
 			stats,
@@ -367,8 +672,10 @@ impl HashColumn {
 					.get(&col)
 					.copied()
 					.unwrap_or(DEFAULT_COMPRESSION_THRESHOLD),
+				db_version,
 			),
 			db_version,
+			key_sidecar,
 		})
 	}
 
@@ -385,6 +692,35 @@ impl HashColumn {
 		Ok(())
 	}
 
+	pub fn persist_free_lists(&self) -> Result<()> {
+		let tables = self.tables.read();
+		for t in tables.value.iter() {
+			t.persist_free_list()?;
+		}
+		Ok(())
+	}
+
+	/// Save the key sidecar. Must only be called when all changes are enacted.
+	pub fn persist_key_sidecar(&self) -> Result<()> {
+		if let Some(sidecar) = &self.key_sidecar {
+			sidecar.lock().save()?;
+		}
+		Ok(())
+	}
+
+	fn update_key_sidecar(&self, removed: Option<Address>, inserted: Option<(Address, &Key)>) {
+		if let Some(sidecar) = &self.key_sidecar {
+			if let Some(keys) = &mut sidecar.lock().keys {
+				if let Some(address) = removed {
+					keys.remove(&address.as_u64());
+				}
+				if let Some((address, key)) = inserted {
+					keys.insert(address.as_u64(), *key);
+				}
+			}
+		}
+	}
+
 	fn open_index(
 		path: &std::path::Path,
 		col: ColId,
@@ -543,6 +879,9 @@ impl HashColumn {
 		let reindex = self.reindex.upgradable_read();
 		let existing = Self::search_all_indexes(change.key(), &tables, &reindex, log)?;
 		if let Some((table, sub_index, existing_address)) = existing {
+			if self.append_only {
+				self.check_append_only(&tables, change, existing_address, log)?;
+			}
 			self.write_plan_existing(&tables, change, log, table, sub_index, existing_address)
 		} else {
 			match change {
@@ -569,6 +908,43 @@ impl HashColumn {
 		}
 	}
 
+	// Fail changes that would free value table entries in an append only column, before any table
+	// state is changed.
+	fn check_append_only(
+		&self,
+		tables: &Tables,
+		change: &Operation<Key, RcValue>,
+		address: Address,
+		log: &impl LogQuery,
+	) -> Result<()> {
+		let value = match change {
+			Operation::Set(_, value) if !self.preimage => value,
+			Operation::Set(..) | Operation::Reference(_) => return Ok(()),
+			Operation::Dereference(key) =>
+				return Err(Error::InvalidInput(format!(
+					"{}: Can't remove {} from an append only column",
+					tables.index.id,
+					hex(key)
+				))),
+		};
+		let key = TableKey::Partial(*change.key());
+		let (cval, target_tier) =
+			Column::compress(&self.compression, &key, value.as_ref(), &tables.value);
+		let len = cval.map_or(value.as_ref().len(), |cval| cval.len());
+		let tier = address.size_tier() as usize;
+		let table = &tables.value[tier];
+		if target_tier != tier ||
+			table.entries_for(&key, len) < table.chain_slots(address.offset(), log)?.len() as u64
+		{
+			return Err(Error::InvalidInput(format!(
+				"{}: Can't replace {} in an append only column, the new value would free entries",
+				tables.index.id,
+				hex(change.key())
+			)))
+		}
+		Ok(())
+	}
+
 	#[allow(clippy::too_many_arguments)]
 	fn write_plan_existing(
 		&self,
@@ -597,11 +973,14 @@ impl HashColumn {
 				// If it was found in an older index we just insert a new entry. Reindex won't
 				// overwrite it.
 				let sub_index = if index.id == tables.index.id { Some(sub_index) } else { None };
-				tables.index.write_insert_plan(key, value_address, sub_index, log)
+				let outcome = tables.index.write_insert_plan(key, value_address, sub_index, log)?;
+				self.update_key_sidecar(Some(existing_address), Some((value_address, key)));
+				Ok(outcome)
 			},
 			(None, None) => {
 				log::trace!(target: "parity-db", "{}: Removing from index {}", tables.index.id, hex(key));
 				index.write_remove_plan(key, sub_index, log)?;
+				self.update_key_sidecar(Some(existing_address), None);
 				Ok(PlanOutcome::Written)
 			},
 		}
@@ -636,6 +1015,7 @@ impl HashColumn {
 			(tables, reindex) = Self::trigger_reindex(tables, reindex, self.path.as_path());
 			outcome = PlanOutcome::NeedReindex;
 		}
+		self.update_key_sidecar(None, Some((address, key)));
 		Ok((outcome, tables, reindex))
 	}
 
@@ -671,6 +1051,47 @@ impl HashColumn {
 		Ok(())
 	}
 
+	pub fn verify_plan(&self, action: LogAction, log: &mut LogReader) -> Result<()> {
+		match action {
+			// Index updates are not verified.
+			LogAction::InsertIndex(_) => IndexTable::skip_plan(log),
+			LogAction::InsertValue(record) => self.tables.read().value
+				[record.table.size_tier() as usize]
+				.verify_plan(record.index, log),
+			_ => Err(Error::Corruption("Unexpected log action".into())),
+		}
+	}
+
+	pub fn skip_plan(&self, action: LogAction, log: &mut LogReader) -> Result<()> {
+		match action {
+			LogAction::InsertIndex(_) => IndexTable::skip_plan(log),
+			LogAction::InsertValue(record) => self.tables.read().value
+				[record.table.size_tier() as usize]
+				.skip_plan(record.index, log),
+			_ => Err(Error::Corruption("Unexpected log action".into())),
+		}
+	}
+
+	pub fn read_value_plan(&self, action: LogAction, log: &mut LogReader) -> Result<Vec<u8>> {
+		match action {
+			LogAction::InsertValue(record) => self.tables.read().value
+				[record.table.size_tier() as usize]
+				.read_plan(record.index, log),
+			_ => Err(Error::Corruption("Unexpected log action".into())),
+		}
+	}
+
+	pub fn enact_values(&self, table: ValueTableId, entries: &[(u64, Vec<u8>)]) -> Result<()> {
+		self.tables.read().value[table.size_tier() as usize].enact_entries(entries)
+	}
+
+	pub fn flush_values(&self) -> Result<()> {
+		for t in self.tables.read().value.iter() {
+			t.flush()?;
+		}
+		Ok(())
+	}
+
 	pub fn validate_plan(&self, action: LogAction, log: &mut LogReader) -> Result<()> {
 		let tables = self.tables.upgradable_read();
 		let reindex = self.reindex.upgradable_read();
@@ -728,27 +1149,51 @@ impl HashColumn {
 		Ok(())
 	}
 
+	pub fn verify_free_lists(&self, log: &impl LogQuery) -> Result<()> {
+		let tables = self.tables.read();
+		verify_free_lists(tables.value.iter(), log)
+	}
+
+	pub fn write_manifest_entries(&self, manifest: &mut TableManifest) {
+		let tables = self.tables.read();
+		for t in tables.value.iter() {
+			t.write_manifest_entry(manifest);
+		}
+	}
+
 	pub fn write_stats_text(&self, writer: &mut impl std::io::Write) -> Result<()> {
 		let tables = self.tables.read();
+		self.collect_write_counters(&tables);
 		tables.index.write_stats(&self.stats)?;
 		self.stats.write_stats_text(writer, tables.index.id.col()).map_err(Error::Io)
 	}
 
 	fn stat_summary(&self) -> ColumnStatSummary {
-		self.stats.summary()
+		let tables = self.tables.read();
+		self.collect_write_counters(&tables);
+		let value_tables =
+			tables.value.iter().map(|t| t.stats()).filter(|s| s.capacity > 0).collect();
+		ColumnStatSummary { value_tables, ..self.stats.summary() }
 	}
 
 	fn clear_stats(&self) -> Result<()> {
 		let tables = self.tables.read();
+		self.collect_write_counters(&tables);
 		self.stats.clear();
 		tables.index.write_stats(&self.stats)
 	}
 
+	fn collect_write_counters(&self, tables: &Tables) {
+		for table in &tables.value {
+			self.stats.add_writes(&table.take_write_counters());
+		}
+	}
+
 	pub fn iter_values(&self, log: &Log, mut f: impl FnMut(ValueIterState) -> bool) -> Result<()> {
 		let tables = self.tables.read();
 		for table in &tables.value {
 			log::debug!( target: "parity-db", "{}: Iterating table {}", tables.index.id, table.id);
-			table.iter_while(log.overlays(), |_, rc, value, compressed| {
+			table.iter_snapshot_while(log.overlays(), |_, rc, value, compressed| {
 				let value = if compressed {
 					if let Ok(value) = self.compression.decompress(&value) {
 						value
@@ -766,6 +1211,36 @@ impl HashColumn {
 		Ok(())
 	}
 
+	/// Same as `iter_values`, but values that can not be read or decompressed are passed to `f`
+	/// as errors and iteration continues.
+	pub fn iter_values_checked(
+		&self,
+		log: &Log,
+		mut f: impl FnMut(std::result::Result<ValueIterState, CorruptedValueInfo>) -> bool,
+	) -> Result<()> {
+		let tables = self.tables.read();
+		for table in &tables.value {
+			let mut more = true;
+			table.iter_snapshot_checked_while(log.overlays(), |index, entry| {
+				let state = entry.and_then(|(rc, value, compressed)| {
+					let value =
+						if compressed { self.compression.decompress(&value)? } else { value };
+					Ok(ValueIterState { rc, value })
+				});
+				more = f(state.map_err(|error| CorruptedValueInfo {
+					size_tier: table.id.size_tier(),
+					index,
+					error,
+				}));
+				more
+			});
+			if !more {
+				break
+			}
+		}
+		Ok(())
+	}
+
 	pub fn iter_index(&self, log: &Log, mut f: impl FnMut(IterState) -> bool) -> Result<()> {
 		let action = |state| match state {
 			IterStateOrCorrupted::Item(item) => Ok(f(item)),
@@ -775,6 +1250,56 @@ impl HashColumn {
 		self.iter_index_internal(log, action, 0)
 	}
 
+	/// Iterate over all keys of the column. Keys are read from the key sidecar when it is enabled
+	/// and from the index and value tables otherwise.
+	pub fn iter_keys(&self, log: &Log, mut f: impl FnMut(Key) -> bool) -> Result<()> {
+		let tables = self.tables.read();
+		let reindex = self.reindex.read();
+		let keys: Vec<Key> = match &self.key_sidecar {
+			Some(sidecar) => {
+				let mut sidecar = sidecar.lock();
+				if sidecar.keys.is_none() {
+					let keys = Self::scan_keys(&tables, &reindex, log)?;
+					log::debug!(target: "parity-db", "{}: Rebuilt key sidecar, {} keys", tables.index.id, keys.len());
+					sidecar.keys = Some(keys);
+				}
+				sidecar.keys.iter().flat_map(|k| k.values().copied()).collect()
+			},
+			None => Self::scan_keys(&tables, &reindex, log)?.into_values().collect(),
+		};
+		drop(reindex);
+		drop(tables);
+		for key in keys {
+			if !f(key) {
+				break
+			}
+		}
+		Ok(())
+	}
+
+	// Collects keys by value address from the main index and any index that is being reindexed.
+	fn scan_keys(tables: &Tables, reindex: &Reindex, log: &Log) -> Result<BTreeMap<u64, Key>> {
+		let mut keys = BTreeMap::new();
+		for source in std::iter::once(&tables.index).chain(reindex.queue.iter()) {
+			for c in 0..source.id.total_chunks() {
+				let entries = source.entries(c, log.overlays())?;
+				for entry in entries.iter() {
+					if entry.is_empty() {
+						continue
+					}
+					let address = entry.address(source.id.index_bits());
+					let pk = tables.value[address.size_tier() as usize]
+						.partial_key_at(address.offset(), log.overlays())?
+						.ok_or_else(|| Error::Corruption("Missing indexed value".into()))?;
+					let mut key = source.recover_key_prefix(c, *entry);
+					key[6..].copy_from_slice(&pk);
+					keys.insert(address.as_u64(), key);
+				}
+			}
+		}
+		Ok(keys)
+	}
+
 	fn iter_index_internal(
 		&self,
 		log: &Log,
@@ -1007,13 +1532,32 @@ impl HashColumn {
 			log::info!(target: "parity-db", "Validating free refs");
 			let tables = self.tables.read();
 			let mut total = 0;
+			let mut residual = 0;
+			let mut reclaimable = 0;
 			for t in &tables.value {
+				for index in t.tombstoned_slots(log.overlays()) {
+					match index.and_then(|index| t.has_residual_data(index, log.overlays())) {
+						Ok(true) => residual += 1,
+						Ok(false) => (),
+						Err(e) => {
+							log::warn!(target: "parity-db", "{}: Error: {:?}", t.id, e);
+							break
+						},
+					}
+				}
 				match t.check_free_refs() {
 					Err(e) => log::warn!(target: "parity-db", "{}: Error: {:?}", t.id, e),
-					Ok(n) => total += n,
+					Ok(n) => {
+						let count = t.free_count();
+						if count != n {
+							log::warn!(target: "parity-db", "{}: Free count is {}, expected {}", t.id, count, n);
+						}
+						total += n
+					},
 				}
+				reclaimable += t.reclaimable_bytes();
 			}
-			log::info!(target: "parity-db", "{} Total free refs", total);
+			log::info!(target: "parity-db", "{} Total free refs, {} with residual data, {} bytes reclaimable", total, residual, reclaimable);
 		}
 		Ok(())
 	}
@@ -1139,23 +1683,17 @@ impl Column {
 						cval.len() as u32,
 					);
 				}
-				if tier == target_tier {
-					log::trace!(target: "parity-db", "{}: Replacing {}", tables.col, key);
-					tables.tables[target_tier].write_replace_plan(
-						address.offset(),
-						key,
-						cval,
-						log,
-						compressed,
-					)?;
-					Ok((Some(PlanOutcome::Written), None))
-				} else {
-					log::trace!(target: "parity-db", "{}: Replacing in a new table {}", tables.col, key);
-					tables.tables[tier].write_remove_plan(address.offset(), log)?;
-					let new_offset =
-						tables.tables[target_tier].write_insert_plan(key, cval, log, compressed)?;
-					let new_address = Address::new(new_offset, target_tier as u8);
-					Ok((None, Some(new_address)))
+				match Column::write_replace_value_plan(
+					key,
+					tables,
+					address,
+					target_tier,
+					cval,
+					compressed,
+					log,
+				)? {
+					None => Ok((Some(PlanOutcome::Written), None)),
+					Some(new_address) => Ok((None, Some(new_address))),
 				}
 			},
 			Operation::Dereference(_) => {
@@ -1184,6 +1722,38 @@ impl Column {
 		}
 	}
 
+	/// Replace the value stored at `address` with a value that fits `target_tier`. When the tier
+	/// differs, the old entry is removed and the value is inserted into the target tier. Returns
+	/// the new address in that case, so that the index can be updated.
+	pub fn write_replace_value_plan(
+		key: &TableKey,
+		tables: TablesRef,
+		address: Address,
+		target_tier: usize,
+		value: &[u8],
+		compressed: bool,
+		log: &mut LogWriter,
+	) -> Result<Option<Address>> {
+		let tier = address.size_tier() as usize;
+		if tier == target_tier {
+			log::trace!(target: "parity-db", "{}: Replacing {}", tables.col, key);
+			let offset = tables.tables[tier].write_replace_plan(
+				address.offset(),
+				key,
+				value,
+				log,
+				compressed,
+			)?;
+			Ok((offset != address.offset()).then(|| Address::new(offset, tier as u8)))
+		} else {
+			log::trace!(target: "parity-db", "{}: Replacing in a new table {}", tables.col, key);
+			tables.tables[tier].write_remove_plan(address.offset(), log)?;
+			let new_offset =
+				tables.tables[target_tier].write_insert_plan(key, value, log, compressed)?;
+			Ok(Some(Address::new(new_offset, target_tier as u8)))
+		}
+	}
+
 	pub fn write_new_value_plan(
 		key: &TableKey,
 		tables: TablesRef,
@@ -1226,6 +1796,37 @@ impl Column {
 		}
 	}
 
+	pub fn verify_plan(&self, action: LogAction, log: &mut LogReader) -> Result<()> {
+		match self {
+			Column::Hash(column) => column.verify_plan(action, log),
+			Column::Tree(column) => column.verify_plan(action, log),
+		}
+	}
+
+	pub fn skip_plan(&self, action: LogAction, log: &mut LogReader) -> Result<()> {
+		match self {
+			Column::Hash(column) => column.skip_plan(action, log),
+			Column::Tree(column) => column.skip_plan(action, log),
+		}
+	}
+
+	/// Read a value table entry from the log without writing it. The entry is written later with
+	/// `enact_values`.
+	pub fn read_value_plan(&self, action: LogAction, log: &mut LogReader) -> Result<Vec<u8>> {
+		match self {
+			Column::Hash(column) => column.read_value_plan(action, log),
+			Column::Tree(column) => column.read_value_plan(action, log),
+		}
+	}
+
+	/// Write value table entries read with `read_value_plan`, in order.
+	pub fn enact_values(&self, table: ValueTableId, entries: &[(u64, Vec<u8>)]) -> Result<()> {
+		match self {
+			Column::Hash(column) => column.enact_values(table, entries),
+			Column::Tree(column) => column.enact_values(table, entries),
+		}
+	}
+
 	pub fn flush(&self) -> Result<()> {
 		match self {
 			Column::Hash(column) => column.flush(),
@@ -1233,6 +1834,15 @@ impl Column {
 		}
 	}
 
+	/// Sync value tables only. B-tree nodes are stored in value tables, so this syncs whole b-tree
+	/// columns.
+	pub fn flush_values(&self) -> Result<()> {
+		match self {
+			Column::Hash(column) => column.flush_values(),
+			Column::Tree(column) => column.flush(),
+		}
+	}
+
 	pub fn refresh_metadata(&self) -> Result<()> {
 		match self {
 			Column::Hash(column) => column.refresh_metadata(),
@@ -1240,6 +1850,34 @@ impl Column {
 		}
 	}
 
+	pub fn write_manifest_entries(&self, manifest: &mut TableManifest) {
+		match self {
+			Column::Hash(column) => column.write_manifest_entries(manifest),
+			Column::Tree(column) => column.write_manifest_entries(manifest),
+		}
+	}
+
+	pub fn persist_free_lists(&self) -> Result<()> {
+		match self {
+			Column::Hash(column) => column.persist_free_lists(),
+			Column::Tree(column) => column.persist_free_lists(),
+		}
+	}
+
+	pub fn verify_free_lists(&self, log: &impl LogQuery) -> Result<()> {
+		match self {
+			Column::Hash(column) => column.verify_free_lists(log),
+			Column::Tree(column) => column.verify_free_lists(log),
+		}
+	}
+
+	pub fn persist_key_sidecar(&self) -> Result<()> {
+		match self {
+			Column::Hash(column) => column.persist_key_sidecar(),
+			Column::Tree(_) => Ok(()),
+		}
+	}
+
 	pub fn write_stats_text(&self, writer: &mut impl std::io::Write) -> Result<()> {
 		match self {
 			Column::Hash(column) => column.write_stats_text(writer),
@@ -1277,3 +1915,153 @@ impl Column {
 		}
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::{Column, TablesRef};
+	use crate::{
+		compress::{Compress, CompressionType},
+		index::Address,
+		log::{Log, LogQuery},
+		options::{ColumnOptions, Options, CURRENT_VERSION},
+		table::{key::TableKey, TableId, ValueTable},
+		Key,
+	};
+	use std::sync::Arc;
+	use tempfile::tempdir;
+
+	#[test]
+	fn replace_moves_between_tiers() {
+		let dir = tempdir().unwrap();
+		let path = Arc::new(dir.path().to_path_buf());
+		let options = ColumnOptions::default();
+		let tables: Vec<ValueTable> = [64, 256]
+			.iter()
+			.enumerate()
+			.map(|(tier, size)| {
+				ValueTable::open(
+					path.clone(),
+					TableId::new(0, tier as u8),
+					Some(*size),
+					&options,
+					Default::default(),
+				)
+				.unwrap()
+			})
+			.collect();
+		let compression = Compress::new(CompressionType::NoCompression, 0, CURRENT_VERSION);
+		let tables = TablesRef {
+			tables: &tables,
+			compression: &compression,
+			col: 0,
+			preimage: false,
+			ref_counted: false,
+		};
+		let log = Log::open(&Options::with_columns(dir.path(), 1)).unwrap();
+		let mut writer = log.begin_record();
+		let key = TableKey::Partial([1; 32]);
+		let tier = |value: &[u8]| Column::compress(&compression, &key, value, tables.tables).1;
+
+		let small = vec![1u8; 10];
+		let offset = tables.tables[0].write_insert_plan(&key, &small, &mut writer, false).unwrap();
+		let address = Address::new(offset, 0);
+
+		let same = vec![2u8; 12];
+		assert_eq!(
+			Column::write_replace_value_plan(
+				&key,
+				tables,
+				address,
+				tier(&same),
+				&same,
+				false,
+				&mut writer
+			)
+			.unwrap(),
+			None
+		);
+
+		// Promotion
+		let large = vec![3u8; 100];
+		let promoted = Column::write_replace_value_plan(
+			&key,
+			tables,
+			address,
+			tier(&large),
+			&large,
+			false,
+			&mut writer,
+		)
+		.unwrap()
+		.unwrap();
+		assert_eq!(promoted.size_tier(), 1);
+		let mut value = vec![0u8; 2];
+		assert!(writer.value(TableId::new(0, 0), address.offset(), &mut value));
+		assert_eq!(value, [0xff, 0xff]);
+		let query = crate::table::key::TableKeyQuery::Check(&key);
+		assert_eq!(Column::get_value(query, promoted, tables, &writer).unwrap(), Some((1, large)));
+
+		// Demotion
+		let demoted = Column::write_replace_value_plan(
+			&key,
+			tables,
+			promoted,
+			tier(&small),
+			&small,
+			false,
+			&mut writer,
+		)
+		.unwrap()
+		.unwrap();
+		assert_eq!(demoted.size_tier(), 0);
+		let query = crate::table::key::TableKeyQuery::Check(&key);
+		assert_eq!(Column::get_value(query, demoted, tables, &writer).unwrap(), Some((0, small)));
+	}
+
+	#[test]
+	fn validate_batch() {
+		let dir = tempdir().unwrap();
+		let path = Arc::new(dir.path().to_path_buf());
+		let options = ColumnOptions::default();
+		let mut tables: Vec<ValueTable> = [Some(64), None]
+			.iter()
+			.enumerate()
+			.map(|(tier, size)| {
+				ValueTable::open(
+					path.clone(),
+					TableId::new(0, tier as u8),
+					*size,
+					&options,
+					Default::default(),
+				)
+				.unwrap()
+			})
+			.collect();
+		tables[1].set_max_chain_depth(Some(2));
+		let compression = Compress::new(CompressionType::NoCompression, 0, CURRENT_VERSION);
+		let tables = TablesRef {
+			tables: &tables,
+			compression: &compression,
+			col: 0,
+			preimage: false,
+			ref_counted: false,
+		};
+
+		let small = vec![1u8; 10];
+		let large = vec![2u8; 5000];
+		let oversized = vec![3u8; 10000];
+		let batch: Vec<(Key, &[u8])> = vec![([1; 32], &small), ([2; 32], &large)];
+		assert_eq!(
+			Column::validate_batch(&batch, tables).unwrap(),
+			vec![TableId::new(0, 0), TableId::new(0, 1)]
+		);
+
+		let batch: Vec<(Key, &[u8])> =
+			vec![([1; 32], &small), ([3; 32], &oversized), ([2; 32], &large)];
+		match Column::validate_batch(&batch, tables) {
+			Err(crate::Error::InvalidInput(message)) =>
+				assert!(message.contains(&crate::display::hex(&[3; 32]).to_string())),
+			r => panic!("Unexpected result {r:?}"),
+		}
+	}
+}
@@ -2,10 +2,14 @@
 // This file is dual-licensed as Apache-2.0 or MIT.
 
 //! Compression utility and types.
+//!
+//! Since database version 9 compressed values start with a one byte codec tag, the
+//! `CompressionType` value. This allows reading values written with any codec after the column
+//! compression setting is changed.
 
 use std::str::FromStr;
 
-use crate::error::Result;
+use crate::error::{Error, Result};
 
 /// Different compression type
 /// allowed and their u8 representation.
@@ -22,16 +26,24 @@ pub enum CompressionType {
 pub struct Compress {
 	inner: Compressor,
 	pub threshold: u32,
+	tagged: bool,
 }
 
 impl Compress {
-	pub fn new(kind: CompressionType, threshold: u32) -> Self {
-		Compress { inner: kind.into(), threshold }
+	pub fn new(kind: CompressionType, threshold: u32, db_version: u32) -> Self {
+		Compress { inner: kind.into(), threshold, tagged: db_version > 8 }
 	}
 }
 
-pub const NO_COMPRESSION: Compress =
-	Compress { inner: Compressor::NoCompression(NoCompression), threshold: u32::MAX };
+/// Used for btree nodes, which are never compressed, and to route values by their uncompressed
+/// length. The threshold is never reached, so no value is written with the compressed flag and
+/// `decompress` is never called. The codec tag is not needed, and leaving it out keeps this
+/// usable for databases of any version.
+pub const NO_COMPRESSION: Compress = Compress {
+	inner: Compressor::NoCompression(NoCompression),
+	threshold: u32::MAX,
+	tagged: false,
+};
 
 #[derive(Debug)]
 enum Compressor {
@@ -90,7 +102,37 @@ impl FromStr for CompressionType {
 
 impl Compress {
 	pub fn compress(&self, buf: &[u8]) -> Vec<u8> {
-		match &self.inner {
+		let compressed = self.inner.compress(buf);
+		if !self.tagged {
+			return compressed
+		}
+		let mut result = Vec::with_capacity(compressed.len() + 1);
+		result.push(CompressionType::from(self) as u8);
+		result.extend_from_slice(&compressed);
+		result
+	}
+
+	pub fn decompress(&self, buf: &[u8]) -> Result<Vec<u8>> {
+		if !self.tagged {
+			return self.inner.decompress(buf)
+		}
+		let (tag, buf) = buf.split_first().ok_or(Error::Compression)?;
+		if *tag == CompressionType::from(self) as u8 {
+			return self.inner.decompress(buf)
+		}
+		let kind = match *tag {
+			a if a == CompressionType::NoCompression as u8 => CompressionType::NoCompression,
+			a if a == CompressionType::Lz4 as u8 => CompressionType::Lz4,
+			a if a == CompressionType::Snappy as u8 => CompressionType::Snappy,
+			_ => return Err(Error::Compression),
+		};
+		Compressor::from(kind).decompress(buf)
+	}
+}
+
+impl Compressor {
+	fn compress(&self, buf: &[u8]) -> Vec<u8> {
+		match self {
 			Compressor::NoCompression(inner) => inner.compress(buf),
 			Compressor::Lz4(inner) => inner.compress(buf),
 			Compressor::Snappy(inner) => inner.compress(buf),
@@ -99,8 +141,8 @@ impl Compress {
 		}
 	}
 
-	pub fn decompress(&self, buf: &[u8]) -> Result<Vec<u8>> {
-		Ok(match &self.inner {
+	fn decompress(&self, buf: &[u8]) -> Result<Vec<u8>> {
+		Ok(match self {
 			Compressor::NoCompression(inner) => inner.decompress(buf)?,
 			Compressor::Lz4(inner) => inner.decompress(buf)?,
 			Compressor::Snappy(inner) => inner.decompress(buf)?,
@@ -177,6 +219,7 @@ mod snappy {
 #[cfg(test)]
 mod tests {
 	use super::*;
+	use crate::options::CURRENT_VERSION;
 
 	#[test]
 	fn test_compression_interfaces() {
@@ -185,12 +228,30 @@ mod tests {
 			vec![CompressionType::NoCompression, CompressionType::Snappy, CompressionType::Lz4];
 
 		for compression_type in types {
-			let compress = Compress::new(compression_type, 0);
-			let v = compress.compress(&original[..]);
-			assert!(v.len() <= 100);
-			let round_tripped = compress.decompress(&v[..]).unwrap();
-			assert_eq!(original, round_tripped);
+			for version in [8, CURRENT_VERSION] {
+				let compress = Compress::new(compression_type, 0, version);
+				let v = compress.compress(&original[..]);
+				assert!(v.len() <= 101);
+				let round_tripped = compress.decompress(&v[..]).unwrap();
+				assert_eq!(original, round_tripped);
+			}
+		}
+	}
+
+	#[test]
+	fn test_mixed_codecs() {
+		let original = vec![42; 100];
+		let lz4 = Compress::new(CompressionType::Lz4, 0, CURRENT_VERSION);
+		let snappy = Compress::new(CompressionType::Snappy, 0, CURRENT_VERSION);
+		let none = Compress::new(CompressionType::NoCompression, 0, CURRENT_VERSION);
+		let from_lz4 = lz4.compress(&original);
+		let from_snappy = snappy.compress(&original);
+		for compress in [&lz4, &snappy, &none] {
+			assert_eq!(compress.decompress(&from_lz4).unwrap(), original);
+			assert_eq!(compress.decompress(&from_snappy).unwrap(), original);
 		}
+		assert!(lz4.decompress(&[0xff, 1, 2]).is_err());
+		assert!(lz4.decompress(&[]).is_err());
 	}
 
 	#[test]
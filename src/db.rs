@@ -20,23 +20,27 @@
 
 use crate::{
 	btree::{commit_overlay::BTreeChangeSet, BTreeIterator, BTreeTable},
-	column::{hash_key, ColId, Column, IterState, ReindexBatch, ValueIterState},
+	column::{
+		hash_key, ColId, Column, CorruptedValueInfo, IterState, ReindexBatch, ValueIterState,
+	},
 	error::{try_io, Error, Result},
+	file::{IoPriorityGuard, ReadPriority},
 	hash::IdentityBuildHasher,
-	index::PlanOutcome,
-	log::{Log, LogAction},
-	options::{Options, CURRENT_VERSION},
+	index::{IndexTable, PlanOutcome},
+	log::{InsertValueAction, Log, LogAction, LogReader},
+	options::Options,
 	parking_lot::{Condvar, Mutex, RwLock},
 	stats::StatSummary,
+	table::{TableId as ValueTableId, TableManifest},
 	ColumnOptions, Key,
 };
 use fs2::FileExt;
 use std::{
 	borrow::Borrow,
-	collections::{BTreeMap, HashMap, VecDeque},
+	collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
 	ops::Bound,
 	sync::{
-		atomic::{AtomicBool, AtomicU64, Ordering},
+		atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
 		Arc,
 	},
 	thread,
@@ -54,6 +58,9 @@ const MIN_LOG_SIZE_BYTES: u64 = 64 * 1024 * 1024;
 // Number of log files to keep after flush when sync mode is disabled. Give the database some chance
 // to recover in case of crash.
 const KEEP_LOGS: usize = 16;
+// Max number of threads writing value tables with `parallel_enact`. The writes are mostly waiting
+// on IO, so this does not depend on the number of cores.
+const MAX_ENACT_THREADS: usize = 8;
 // Hard limit on the number of log files in sync mode. The number of log may grow while existing
 // logs are waiting on fsync. Commits will be throttled if total number of log files exceeds this
 // number.
@@ -130,6 +137,60 @@ struct CommitQueue {
 	commits: VecDeque<Commit>,
 }
 
+// Value table entries of a log record, read ahead of writing them with `parallel_enact`. Entries
+// are grouped by table, in log order.
+#[derive(Default)]
+struct ValueBatches(HashMap<ValueTableId, Vec<(u64, Vec<u8>)>>);
+
+impl ValueBatches {
+	fn read(
+		&mut self,
+		columns: &[Column],
+		insertion: InsertValueAction,
+		log: &mut LogReader,
+	) -> Result<()> {
+		let (table, index) = (insertion.table, insertion.index);
+		let data = columns[table.col() as usize]
+			.read_value_plan(LogAction::InsertValue(insertion), log)?;
+		self.0.entry(table).or_default().push((index, data));
+		Ok(())
+	}
+
+	// Write all entries. Each table is written by one thread, tables are picked up by the next
+	// free thread.
+	fn enact(&mut self, columns: &[Column]) -> Result<()> {
+		let batches: Vec<_> = self.0.drain().collect();
+		let threads = MAX_ENACT_THREADS.min(batches.len());
+		if threads <= 1 {
+			for (table, entries) in &batches {
+				columns[table.col() as usize].enact_values(*table, entries)?;
+			}
+			return Ok(())
+		}
+		let next = AtomicUsize::new(0);
+		thread::scope(|s| {
+			let workers: Vec<_> = (0..threads)
+				.map(|_| {
+					s.spawn(|| -> Result<()> {
+						while let Some((table, entries)) =
+							batches.get(next.fetch_add(1, Ordering::Relaxed))
+						{
+							columns[table.col() as usize].enact_values(*table, entries)?;
+						}
+						Ok(())
+					})
+				})
+				.collect();
+			// Wait for all threads before returning the first error.
+			let results: Vec<_> = workers
+				.into_iter()
+				.map(|w| w.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+				.collect();
+			results.into_iter().collect()
+		})
+	}
+}
+
 #[derive(Debug)]
 struct DbInner {
 	columns: Vec<Column>,
@@ -147,11 +208,19 @@ struct DbInner {
 	flush_worker_wait: Arc<WaitCondvar<bool>>,
 	cleanup_worker_wait: WaitCondvar<bool>,
 	cleanup_queue_wait: WaitCondvar<bool>,
+	// Set while writes to the data files are paused. Locked for the duration of each log
+	// enactment.
+	writes_paused: Mutex<bool>,
+	writes_resumed_cv: Condvar,
 	last_enacted: AtomicU64,
 	next_reindex: AtomicU64,
 	bg_err: Mutex<Option<Arc<Error>>>,
 	db_version: u32,
+	read_only: bool,
 	_lock_file: std::fs::File,
+	// Used in tests to simulate a crash right after a value write barrier.
+	#[cfg(test)]
+	crash_after_barrier: AtomicBool,
 }
 
 #[derive(Debug)]
@@ -199,13 +268,25 @@ impl DbInner {
 			.open(lock_path.as_path()));
 		lock_file.try_lock_exclusive().map_err(Error::Locked)?;
 
-		let metadata = options.load_and_validate_metadata(opening_mode == OpeningMode::Create)?;
+		let metadata = options.load_and_validate_metadata_with_access(
+			opening_mode == OpeningMode::Create,
+			opening_mode == OpeningMode::ReadOnly,
+		)?;
 		let mut columns = Vec::with_capacity(metadata.columns.len());
 		let mut commit_overlay = Vec::with_capacity(metadata.columns.len());
 		let log = Log::open(options)?;
 		let last_enacted = log.replay_record_id().unwrap_or(2) - 1;
+		let manifest =
+			if options.trusted_open { TableManifest::load(&options.path)? } else { None };
+		let read_only = opening_mode == OpeningMode::ReadOnly;
+		if !read_only {
+			// Tables change from here on. The manifest is only saved again on clean shutdown with
+			// `trusted_open`.
+			TableManifest::invalidate(&options.path)?;
+		}
 		for c in 0..metadata.columns.len() {
-			let column = Column::open(c as ColId, options, &metadata)?;
+			let column =
+				Column::open(c as ColId, options, &metadata, manifest.as_ref(), read_only)?;
 			commit_overlay.push(CommitOverlay::new());
 			columns.push(column);
 		}
@@ -229,15 +310,24 @@ impl DbInner {
 			flush_worker_wait: Arc::new(WaitCondvar::new()),
 			cleanup_worker_wait: WaitCondvar::new(),
 			cleanup_queue_wait: WaitCondvar::new(),
+			writes_paused: Mutex::new(false),
+			writes_resumed_cv: Condvar::new(),
 			next_reindex: AtomicU64::new(1),
 			last_enacted: AtomicU64::new(last_enacted),
 			bg_err: Mutex::new(None),
 			db_version: metadata.version,
+			read_only,
 			_lock_file: lock_file,
+			#[cfg(test)]
+			crash_after_barrier: AtomicBool::new(false),
 		})
 	}
 
 	fn get(&self, col: ColId, key: &[u8]) -> Result<Option<Value>> {
+		self.get_strict(col, key, false)
+	}
+
+	fn get_strict(&self, col: ColId, key: &[u8], strict: bool) -> Result<Option<Value>> {
 		match &self.columns[col as usize] {
 			Column::Hash(column) => {
 				let key = column.hash_key(key);
@@ -248,7 +338,11 @@ impl DbInner {
 				}
 				// Go into tables and log overlay.
 				let log = self.log.overlays();
-				column.get(&key, log)
+				if strict {
+					column.get_checked(&key, log)
+				} else {
+					column.get(&key, log)
+				}
 			},
 			Column::Tree(column) => {
 				let overlay = self.commit_overlay.read();
@@ -262,6 +356,36 @@ impl DbInner {
 		}
 	}
 
+	fn get_batch<K: AsRef<[u8]>>(&self, col: ColId, keys: &[K]) -> Result<Vec<Option<Value>>> {
+		match &self.columns[col as usize] {
+			Column::Hash(column) => {
+				let mut values = Vec::with_capacity(keys.len());
+				let mut missing = Vec::new();
+				let mut missing_keys = Vec::new();
+				{
+					let overlay = self.commit_overlay.read();
+					for (i, key) in keys.iter().enumerate() {
+						let key = column.hash_key(key.as_ref());
+						match overlay.get(col as usize).and_then(|o| o.get(&key)) {
+							Some(v) => values.push(v.map(|i| i.value().clone())),
+							None => {
+								values.push(None);
+								missing.push(i);
+								missing_keys.push(key);
+							},
+						}
+					}
+				}
+				let log = self.log.overlays();
+				for (i, value) in missing.into_iter().zip(column.get_batch(&missing_keys, log)?) {
+					values[i] = value;
+				}
+				Ok(values)
+			},
+			Column::Tree(_) => keys.iter().map(|key| self.get(col, key.as_ref())).collect(),
+		}
+	}
+
 	fn get_size(&self, col: ColId, key: &[u8]) -> Result<Option<u32>> {
 		match &self.columns[col as usize] {
 			Column::Hash(column) => {
@@ -341,6 +465,12 @@ impl DbInner {
 	}
 
 	fn commit_raw(&self, commit: CommitChangeSet) -> Result<()> {
+		for (c, indexed) in &commit.indexed {
+			if let Column::Hash(column) = &self.columns[*c as usize] {
+				column.validate_changes(&indexed.changes)?;
+			}
+		}
+
 		let mut queue = self.commit_queue.lock();
 
 		#[cfg(any(test, feature = "instrumentation"))]
@@ -498,6 +628,8 @@ impl DbInner {
 				self.start_reindex(record_id);
 			}
 
+			self.limit_log_overlay()?;
+
 			log::debug!(
 				target: "parity-db",
 				"Processed commit {} (record {}), {} ops, {} bytes written",
@@ -512,6 +644,22 @@ impl DbInner {
 		}
 	}
 
+	// Enact the oldest log records until no value table overlay is larger than
+	// `max_log_overlay_bytes`.
+	fn limit_log_overlay(&self) -> Result<()> {
+		let Some(max_bytes) = self.options.max_log_overlay_bytes else { return Ok(()) };
+		while self.log.overlays().read().max_value_bytes() as u64 > max_bytes {
+			let paused = self.writes_paused.lock();
+			if *paused {
+				break
+			}
+			if !self.enact_next_log(false)? && !self.flush_logs(0)? {
+				break
+			}
+		}
+		Ok(())
+	}
+
 	fn start_reindex(&self, record_id: u64) {
 		log::trace!(target: "parity-db", "Scheduled reindex at record {}", record_id);
 		self.next_reindex.store(record_id, Ordering::SeqCst);
@@ -568,6 +716,15 @@ impl DbInner {
 	}
 
 	fn enact_logs(&self, validation_mode: bool) -> Result<bool> {
+		let mut paused = self.writes_paused.lock();
+		while *paused && !self.shutdown.load(Ordering::SeqCst) {
+			self.writes_resumed_cv.wait(&mut paused);
+		}
+		self.enact_next_log(validation_mode)
+	}
+
+	// Must be called with `writes_paused` locked.
+	fn enact_next_log(&self, validation_mode: bool) -> Result<bool> {
 		let cleared = {
 			let reader = match self.log.read_next(validation_mode) {
 				Ok(reader) => reader,
@@ -653,14 +810,55 @@ impl DbInner {
 					reader.reset()?;
 					reader.next()?;
 				}
+				let barrier = self.options.value_write_barrier;
+				let mut barrier_columns = BTreeSet::new();
+				let parallel = self.options.parallel_enact;
+				let mut values = ValueBatches::default();
+				if barrier {
+					// Value tables first.
+					loop {
+						match reader.next()? {
+							LogAction::BeginRecord =>
+								return Err(Error::Corruption("Bad log record".into())),
+							LogAction::EndRecord => break,
+							LogAction::InsertIndex(_) => IndexTable::skip_plan(&mut reader)?,
+							LogAction::InsertValue(insertion) if parallel => {
+								barrier_columns.insert(insertion.table.col());
+								values.read(&self.columns, insertion, &mut reader)?;
+							},
+							LogAction::InsertValue(insertion) => {
+								let col = insertion.table.col();
+								self.columns[col as usize]
+									.enact_plan(LogAction::InsertValue(insertion), &mut reader)?;
+								barrier_columns.insert(col);
+							},
+							LogAction::DropTable(_) => (),
+						}
+					}
+					values.enact(&self.columns)?;
+					self.barrier(&barrier_columns)?;
+					reader.reset()?;
+					reader.next()?;
+				}
 				loop {
 					match reader.next()? {
 						LogAction::BeginRecord =>
 							return Err(Error::Corruption("Bad log record".into())),
 						LogAction::EndRecord => break,
 						LogAction::InsertIndex(insertion) => {
-							self.columns[insertion.table.col() as usize]
+							let col = insertion.table.col();
+							self.columns[col as usize]
 								.enact_plan(LogAction::InsertIndex(insertion), &mut reader)?;
+							if barrier {
+								barrier_columns.insert(col);
+							}
+						},
+						LogAction::InsertValue(insertion) if barrier => {
+							self.columns[insertion.table.col() as usize]
+								.skip_plan(LogAction::InsertValue(insertion), &mut reader)?;
+						},
+						LogAction::InsertValue(insertion) if parallel => {
+							values.read(&self.columns, insertion, &mut reader)?;
 						},
 						LogAction::InsertValue(insertion) => {
 							self.columns[insertion.table.col() as usize]
@@ -683,6 +881,35 @@ impl DbInner {
 						},
 					}
 				}
+				values.enact(&self.columns)?;
+				if barrier {
+					for c in barrier_columns {
+						self.columns[c as usize].flush()?;
+					}
+				}
+				if self.options.verify_writes {
+					for c in self.columns.iter() {
+						c.flush()?;
+					}
+					reader.reset()?;
+					reader.next()?;
+					loop {
+						match reader.next()? {
+							LogAction::BeginRecord =>
+								return Err(Error::Corruption("Bad log record".into())),
+							LogAction::EndRecord => break,
+							LogAction::InsertIndex(insertion) => {
+								self.columns[insertion.table.col() as usize]
+									.verify_plan(LogAction::InsertIndex(insertion), &mut reader)?;
+							},
+							LogAction::InsertValue(insertion) => {
+								self.columns[insertion.table.col() as usize]
+									.verify_plan(LogAction::InsertValue(insertion), &mut reader)?;
+							},
+							LogAction::DropTable(_) => (),
+						}
+					}
+				}
 				log::debug!(
 					target: "parity-db",
 					"Enacted log record {}, {} bytes",
@@ -764,6 +991,45 @@ impl DbInner {
 		Ok(result)
 	}
 
+	// Sync value tables of `columns`. Index writes referencing new values may only be enacted
+	// after this.
+	fn barrier(&self, columns: &BTreeSet<ColId>) -> Result<()> {
+		for c in columns {
+			self.columns[*c as usize].flush_values()?;
+		}
+		#[cfg(test)]
+		if self.crash_after_barrier.load(Ordering::Relaxed) {
+			return Err(Error::Io(std::io::Error::other("Simulated crash")))
+		}
+		Ok(())
+	}
+
+	fn pause_writes(&self) -> Result<()> {
+		let mut paused = self.writes_paused.lock();
+		if *paused {
+			return Ok(())
+		}
+		log::debug!(target: "parity-db", "Pausing writes");
+		// Enact everything that has been logged so far and make sure it is on disk.
+		self.flush_logs(0)?;
+		while self.enact_next_log(false)? {}
+		for c in self.columns.iter() {
+			c.flush()?;
+		}
+		*paused = true;
+		Ok(())
+	}
+
+	fn resume_writes(&self) {
+		let mut paused = self.writes_paused.lock();
+		if *paused {
+			log::debug!(target: "parity-db", "Resuming writes");
+			*paused = false;
+			self.writes_resumed_cv.notify_all();
+			self.commit_worker_wait.signal();
+		}
+	}
+
 	fn clean_all_logs(&self) -> Result<()> {
 		for c in self.columns.iter() {
 			c.flush()?;
@@ -774,14 +1040,18 @@ impl DbInner {
 	}
 
 	fn replay_all_logs(&mut self) -> Result<()> {
+		let mut replayed = false;
 		while let Some(id) = self.log.replay_next()? {
 			log::debug!(target: "parity-db", "Replaying database log {}", id);
 			while self.enact_logs(true)? {}
+			replayed = true;
 		}
 
 		// Re-read any cached metadata
-		for c in self.columns.iter() {
-			c.refresh_metadata()?;
+		if replayed {
+			for c in self.columns.iter() {
+				c.refresh_metadata()?;
+			}
 		}
 		log::debug!(target: "parity-db", "Replay is complete.");
 		Ok(())
@@ -794,6 +1064,9 @@ impl DbInner {
 		self.log_worker_wait.signal();
 		self.commit_worker_wait.signal();
 		self.cleanup_worker_wait.signal();
+		// Paused writes are not waited for on shutdown.
+		let _paused = self.writes_paused.lock();
+		self.writes_resumed_cv.notify_all();
 	}
 
 	fn kill_logs(&self) -> Result<()> {
@@ -816,6 +1089,18 @@ impl DbInner {
 		while self.enact_logs(false)? {}
 		self.clean_all_logs()?;
 		self.log.kill_logs()?;
+		// All changes are enacted at this point.
+		for c in self.columns.iter() {
+			c.persist_free_lists()?;
+			c.persist_key_sidecar()?;
+		}
+		if self.options.trusted_open && !self.read_only {
+			let mut manifest = TableManifest::default();
+			for c in self.columns.iter() {
+				c.write_manifest_entries(&mut manifest);
+			}
+			manifest.save(&self.options.path)?;
+		}
 		if self.options.stats {
 			let mut path = self.options.path.clone();
 			path.push("stats.txt");
@@ -877,12 +1162,30 @@ impl DbInner {
 		}
 	}
 
+	fn iter_column_checked_while(
+		&self,
+		c: ColId,
+		f: impl FnMut(std::result::Result<ValueIterState, CorruptedValueInfo>) -> bool,
+	) -> Result<()> {
+		match &self.columns[c as usize] {
+			Column::Hash(column) => column.iter_values_checked(&self.log, f),
+			Column::Tree(_) => unimplemented!(),
+		}
+	}
+
 	fn iter_column_index_while(&self, c: ColId, f: impl FnMut(IterState) -> bool) -> Result<()> {
 		match &self.columns[c as usize] {
 			Column::Hash(column) => column.iter_index(&self.log, f),
 			Column::Tree(_) => unimplemented!(),
 		}
 	}
+
+	fn iter_column_keys_while(&self, c: ColId, f: impl FnMut(Key) -> bool) -> Result<()> {
+		match &self.columns[c as usize] {
+			Column::Hash(column) => column.iter_keys(&self.log, f),
+			Column::Tree(_) => unimplemented!(),
+		}
+	}
 }
 
 /// Database instance.
@@ -931,6 +1234,11 @@ impl Db {
 			db.clean_all_logs()?;
 			db.log.kill_logs()?;
 		}
+		if options.validate_free_lists_on_open {
+			for c in db.columns.iter() {
+				c.verify_free_lists(db.log.overlays())?;
+			}
+		}
 		let db = Arc::new(db);
 		#[cfg(any(test, feature = "instrumentation"))]
 		let start_threads = opening_mode != OpeningMode::ReadOnly && options.with_background_thread;
@@ -980,6 +1288,34 @@ impl Db {
 		self.inner.get(col, key)
 	}
 
+	/// Get a value in a specified column by key. Unlike `get`, fails with `Error::KeyMismatch`
+	/// instead of returning `None` when the index points to a value stored for another key, which
+	/// tells a corrupted index apart from a missing key. Keys of `btree_index` columns are stored
+	/// in full and are not affected.
+	pub fn get_checked(&self, col: ColId, key: &[u8]) -> Result<Option<Value>> {
+		self.inner.get_strict(col, key, true)
+	}
+
+	/// Get a value with an I/O priority hint for the reads it issues. High priority is meant for
+	/// foreground reads that should not be starved by background scans. The hint is a no-op on
+	/// platforms other than Linux.
+	pub fn get_with_priority(
+		&self,
+		col: ColId,
+		key: &[u8],
+		priority: ReadPriority,
+	) -> Result<Option<Value>> {
+		let _priority = IoPriorityGuard::new(priority);
+		self.inner.get(col, key)
+	}
+
+	/// Get values for a batch of keys in a specified column. Returns a value or `None` for each
+	/// key, in the order of `keys`. Values are read from disk ordered by location, which is faster
+	/// than separate `get` calls when many values are not cached.
+	pub fn get_batch<K: AsRef<[u8]>>(&self, col: ColId, keys: &[K]) -> Result<Vec<Option<Value>>> {
+		self.inner.get_batch(col, keys)
+	}
+
 	/// Get value size by key. Returns `None` if the key does not exist.
 	pub fn get_size(&self, col: ColId, key: &[u8]) -> Result<Option<u32>> {
 		self.inner.get_size(col, key)
@@ -1012,6 +1348,22 @@ impl Db {
 		self.inner.commit_raw(commit)
 	}
 
+	/// Stop writing to the database files, so that they can be copied in a consistent state.
+	///
+	/// All changes that were already written to the log are applied and the files are synced to
+	/// disk before returning. Until [`Db::resume_writes`] is called, no further changes are
+	/// applied to the files. Changes may still be committed, and both new and existing values
+	/// can be read. This applies to all columns, as a single commit may span several of them.
+	/// Commits that are still queued for logging are not included.
+	pub fn pause_writes(&self) -> Result<()> {
+		self.inner.pause_writes()
+	}
+
+	/// Resume writing to the database files after [`Db::pause_writes`].
+	pub fn resume_writes(&self) {
+		self.inner.resume_writes()
+	}
+
 	/// Returns the number of columns in the database.
 	pub fn num_columns(&self) -> u8 {
 		self.inner.columns.len() as u8
@@ -1024,6 +1376,16 @@ impl Db {
 		self.inner.iter_column_while(c, f)
 	}
 
+	/// Same as `iter_column_while`, but values are passed as results. Values that can not be read
+	/// or decompressed are passed as errors with their location, and iteration continues.
+	pub fn iter_column_checked_while(
+		&self,
+		c: ColId,
+		f: impl FnMut(std::result::Result<ValueIterState, CorruptedValueInfo>) -> bool,
+	) -> Result<()> {
+		self.inner.iter_column_checked_while(c, f)
+	}
+
 	/// Iterate a column and call a function for each value. This is only supported for columns with
 	/// `btree_index` set to `false`. Iteration order is unspecified. Note that the
 	/// `key` field in the state is the hash of the original key.
@@ -1036,6 +1398,14 @@ impl Db {
 		self.inner.iter_column_index_while(c, f)
 	}
 
+	/// Iterate a column and call a function for each key. This is only supported for columns with
+	/// `btree_index` set to `false`. Iteration order is unspecified. Note that keys are hashes of
+	/// the original keys. Set `key_sidecar` for the column to avoid reading the value tables.
+	/// Unlike `get` the iteration may not include changes made in recent `commit` calls.
+	pub fn iter_column_keys_while(&self, c: ColId, f: impl FnMut(Key) -> bool) -> Result<()> {
+		self.inner.iter_column_keys_while(c, f)
+	}
+
 	fn commit_worker(db: Arc<DbInner>) -> Result<()> {
 		let mut more_work = false;
 		while !db.shutdown.load(Ordering::SeqCst) || more_work {
@@ -1130,13 +1500,14 @@ impl Db {
 		// logs.
 		let db = Db::open(options)?;
 		let salt = db.inner.options.salt;
+		let db_version = db.inner.db_version;
 		drop(db);
 
 		options.columns.push(new_column_options);
 		options.write_metadata_with_version(
 			&options.path,
 			&salt.expect("`salt` is always `Some` after opening the DB; qed"),
-			Some(CURRENT_VERSION),
+			Some(db_version),
 		)?;
 
 		Ok(())
@@ -1522,17 +1893,23 @@ enum OpeningMode {
 mod tests {
 	use super::{Db, Options};
 	use crate::{
-		column::ColId,
+		column::{ColId, Column},
 		db::{DbInner, OpeningMode},
 		ColumnOptions, Value,
 	};
 	use rand::Rng;
 	use std::{
-		collections::{BTreeMap, HashMap, HashSet},
+		collections::{BTreeMap, BTreeSet, HashMap, HashSet},
 		path::Path,
+		sync::atomic::Ordering,
 	};
 	use tempfile::tempdir;
 
+	#[cfg(feature = "bench")]
+	use test::Bencher;
+	#[cfg(feature = "bench")]
+	extern crate test;
+
 	// This is used in tests to disable certain commit stages.
 	#[derive(Eq, PartialEq, Debug, Clone, Copy)]
 	enum EnableCommitPipelineStages {
@@ -1559,6 +1936,26 @@ mod tests {
 				salt: None,
 				columns: (0..num_columns).map(|_| Default::default()).collect(),
 				compression_threshold: HashMap::new(),
+				encryption: HashMap::new(),
+				reuse_read_buffers: false,
+				dsync_writes: false,
+				prefetch_depth: HashMap::new(),
+				iter_read_ahead: HashMap::new(),
+				grow_step_bytes: HashMap::new(),
+				access_pattern: HashMap::new(),
+				header_durability: HashMap::new(),
+				max_chain_depth: HashMap::new(),
+				max_value_len: HashMap::new(),
+				slot_reuse_grace: HashMap::new(),
+				verify_writes: false,
+				value_write_barrier: false,
+				trusted_open: false,
+				validate_free_lists_on_open: false,
+				replace_freed_as_insert: false,
+				parallel_enact: false,
+				max_log_overlay_bytes: None,
+				direct_io: false,
+				mmap_reads: false,
 				with_background_thread: *self == Self::Standard,
 				always_flush: *self == Self::DbFile,
 			}
@@ -1830,97 +2227,992 @@ mod tests {
 	}
 
 	#[test]
-	fn test_indexed_btree_1() {
-		test_indexed_btree_inner(EnableCommitPipelineStages::CommitOverlay, false);
-		test_indexed_btree_inner(EnableCommitPipelineStages::LogOverlay, false);
-		test_indexed_btree_inner(EnableCommitPipelineStages::DbFile, false);
-		test_indexed_btree_inner(EnableCommitPipelineStages::Standard, false);
-		test_indexed_btree_inner(EnableCommitPipelineStages::CommitOverlay, true);
-		test_indexed_btree_inner(EnableCommitPipelineStages::LogOverlay, true);
-		test_indexed_btree_inner(EnableCommitPipelineStages::DbFile, true);
-		test_indexed_btree_inner(EnableCommitPipelineStages::Standard, true);
-	}
-	fn test_indexed_btree_inner(db_test: EnableCommitPipelineStages, long_key: bool) {
+	fn test_verify_writes() {
 		let tmp = tempdir().unwrap();
-		let col_nb = 0u8;
-		let mut options = db_test.options(tmp.path(), 5);
-		options.columns[col_nb as usize].btree_index = true;
-
-		let (key1, key2, key3, key4) = if !long_key {
-			(b"key1".to_vec(), b"key2".to_vec(), b"key3".to_vec(), b"key4".to_vec())
-		} else {
-			let key2 = vec![2; 272];
-			let mut key3 = key2.clone();
-			key3[271] = 3;
-			(vec![1; 953], key2, key3, vec![4; 79])
-		};
+		let db_test = EnableCommitPipelineStages::DbFile;
+		let mut options = db_test.options(tmp.path(), 2);
+		options.columns[1].btree_index = true;
+		options.verify_writes = true;
 
 		let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
-		assert_eq!(db.get(col_nb, &key1).unwrap(), None);
+		db.commit(vec![
+			(0, b"key1".to_vec(), Some(b"value1".to_vec())),
+			(0, b"key2".to_vec(), Some(vec![2; 50000])),
+			(1, b"key1".to_vec(), Some(b"value1".to_vec())),
+		])
+		.unwrap();
+		db_test.run_stages(&db);
+		db.commit(vec![(0, b"key1".to_vec(), None), (1, b"key2".to_vec(), Some(vec![3; 100]))])
+			.unwrap();
+		db_test.run_stages(&db);
+		drop(db);
 
-		let mut iter = db.iter(col_nb).unwrap();
-		assert_eq!(iter.next().unwrap(), None);
-		assert_eq!(iter.prev().unwrap(), None);
+		let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
+		assert_eq!(db.get(0, b"key1").unwrap(), None);
+		assert_eq!(db.get(0, b"key2").unwrap(), Some(vec![2; 50000]));
+		assert_eq!(db.get(1, b"key1").unwrap(), Some(b"value1".to_vec()));
+		assert_eq!(db.get(1, b"key2").unwrap(), Some(vec![3; 100]));
+	}
 
-		db.commit(vec![(col_nb, key1.clone(), Some(b"value1".to_vec()))]).unwrap();
-		db_test.run_stages(&db);
+	#[test]
+	fn test_key_sidecar() {
+		let tmp = tempdir().unwrap();
+		let db_test = EnableCommitPipelineStages::DbFile;
+		let mut options = db_test.options(tmp.path(), 1);
+		options.columns[0].key_sidecar = true;
+		let sidecar_path = tmp.path().join("index_00_keys");
 
-		assert_eq!(db.get(col_nb, &key1).unwrap(), Some(b"value1".to_vec()));
-		iter.seek_to_first().unwrap();
-		assert_eq!(iter.next().unwrap(), Some((key1.clone(), b"value1".to_vec())));
-		assert_eq!(iter.next().unwrap(), None);
-		assert_eq!(iter.prev().unwrap(), Some((key1.clone(), b"value1".to_vec())));
-		assert_eq!(iter.prev().unwrap(), None);
-		assert_eq!(iter.next().unwrap(), Some((key1.clone(), b"value1".to_vec())));
-		assert_eq!(iter.next().unwrap(), None);
+		let check_keys = |db: &Db, expected: &BTreeSet<Vec<u8>>| {
+			let hashed: BTreeSet<_> = match &db.inner.columns[0] {
+				Column::Hash(column) => expected.iter().map(|k| column.hash_key(k)).collect(),
+				Column::Tree(_) => unreachable!(),
+			};
+			let mut from_sidecar = Vec::new();
+			db.iter_column_keys_while(0, |key| {
+				from_sidecar.push(key);
+				true
+			})
+			.unwrap();
+			let mut from_tables = BTreeSet::new();
+			db.iter_column_index_while(0, |state| {
+				from_tables.insert(state.key);
+				true
+			})
+			.unwrap();
+			assert_eq!(from_sidecar.len(), hashed.len());
+			assert_eq!(from_sidecar.into_iter().collect::<BTreeSet<_>>(), hashed);
+			assert_eq!(from_tables, hashed);
+		};
 
-		iter.seek_to_first().unwrap();
-		assert_eq!(iter.next().unwrap(), Some((key1.clone(), b"value1".to_vec())));
-		assert_eq!(iter.prev().unwrap(), None);
+		let mut expected = BTreeSet::new();
+		{
+			let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
+			db.commit((0u8..100).map(|i| (0, vec![i], Some(vec![i; 10])))).unwrap();
+			expected.extend((0u8..100).map(|i| vec![i]));
+			db_test.run_stages(&db);
+			check_keys(&db, &expected);
 
-		iter.seek(&[0xff]).unwrap();
-		assert_eq!(iter.prev().unwrap(), Some((key1.clone(), b"value1".to_vec())));
-		assert_eq!(iter.prev().unwrap(), None);
+			// Replace, moving values to another tier, and remove.
+			db.commit((0u8..50).map(|i| (0, vec![i], Some(vec![i; 500])))).unwrap();
+			db.commit((40u8..60).map(|i| (0, vec![i], None))).unwrap();
+			expected.retain(|k| !(40..60).contains(&k[0]));
+			db_test.run_stages(&db);
+			check_keys(&db, &expected);
+		}
+		assert!(std::fs::metadata(&sidecar_path).unwrap().len() > 0);
 
-		db.commit(vec![
-			(col_nb, key1.clone(), None),
-			(col_nb, key2.clone(), Some(b"value2".to_vec())),
-			(col_nb, key3.clone(), Some(b"value3".to_vec())),
-		])
-		.unwrap();
+		{
+			// Saved keys are loaded and the file is invalidated while the database is open.
+			let db = Db::open_inner(&options, OpeningMode::Write).unwrap();
+			assert_eq!(std::fs::metadata(&sidecar_path).unwrap().len(), 0);
+			check_keys(&db, &expected);
+			db.commit((100u8..110).map(|i| (0, vec![i], Some(vec![i; 10])))).unwrap();
+			expected.extend((100u8..110).map(|i| vec![i]));
+			db_test.run_stages(&db);
+			check_keys(&db, &expected);
+		}
+
+		// Missing keys are rebuilt from the index.
+		std::fs::remove_file(&sidecar_path).unwrap();
+		let db = Db::open_inner(&options, OpeningMode::Write).unwrap();
+		check_keys(&db, &expected);
+		db.commit(vec![(0, vec![0], None)]).unwrap();
+		expected.remove(&vec![0]);
 		db_test.run_stages(&db);
+		check_keys(&db, &expected);
+	}
 
-		assert_eq!(db.get(col_nb, &key1).unwrap(), None);
-		assert_eq!(db.get(col_nb, &key2).unwrap(), Some(b"value2".to_vec()));
-		assert_eq!(db.get(col_nb, &key3).unwrap(), Some(b"value3".to_vec()));
+	#[test]
+	fn test_value_write_barrier() {
+		let tmp = tempdir().unwrap();
+		let db_test = EnableCommitPipelineStages::DbFile;
+		let mut options = db_test.options(tmp.path(), 2);
+		options.columns[1].btree_index = true;
+		options.value_write_barrier = true;
+		let value = vec![0x5a; 500];
 
-		iter.seek(key2.as_slice()).unwrap();
-		assert_eq!(iter.next().unwrap(), Some((key2.clone(), b"value2".to_vec())));
-		assert_eq!(iter.next().unwrap(), Some((key3.clone(), b"value3".to_vec())));
-		assert_eq!(iter.next().unwrap(), None);
+		{
+			let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
+			db.commit(vec![(0, b"key1".to_vec(), Some(b"value1".to_vec()))]).unwrap();
+			db_test.run_stages(&db);
+
+			db.commit(vec![
+				(0, b"key2".to_vec(), Some(value.clone())),
+				(1, b"key2".to_vec(), Some(value.clone())),
+			])
+			.unwrap();
+			while db.inner.process_commits().unwrap() {}
+			db.inner.log.flush_one(0).unwrap();
+			db.inner.crash_after_barrier.store(true, Ordering::Relaxed);
+			let err = db.inner.enact_logs(false);
+			assert!(err.is_err());
 
-		iter.seek(key3.as_slice()).unwrap();
-		assert_eq!(iter.prev().unwrap(), Some((key3.clone(), b"value3".to_vec())));
-		assert_eq!(iter.prev().unwrap(), Some((key2.clone(), b"value2".to_vec())));
-		assert_eq!(iter.prev().unwrap(), None);
+			// The value is on disk, but the index does not reference it yet.
+			let on_disk = std::fs::read_dir(tmp.path()).unwrap().any(|entry| {
+				let entry = entry.unwrap();
+				entry.file_name().to_str().unwrap().starts_with("table_00_") &&
+					std::fs::read(entry.path())
+						.unwrap()
+						.windows(value.len())
+						.any(|w| w == value.as_slice())
+			});
+			assert!(on_disk);
+			match &db.inner.columns[0] {
+				Column::Hash(column) => {
+					let no_log = crate::log::LogOverlays::default();
+					let key1 = column.hash_key(b"key1");
+					let key2 = column.hash_key(b"key2");
+					assert_eq!(column.get(&key1, &no_log).unwrap(), Some(b"value1".to_vec()));
+					assert_eq!(column.get(&key2, &no_log).unwrap(), None);
+				},
+				Column::Tree(_) => unreachable!(),
+			}
+			db.inner.store_err(err.map(|_| ()));
+		}
 
-		db.commit(vec![
-			(col_nb, key2.clone(), Some(b"value2b".to_vec())),
-			(col_nb, key4.clone(), Some(b"value4".to_vec())),
-			(col_nb, key3.clone(), None),
-		])
-		.unwrap();
+		let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
+		assert_eq!(db.get(0, b"key1").unwrap(), Some(b"value1".to_vec()));
+		assert_eq!(db.get(0, b"key2").unwrap(), Some(value.clone()));
+		assert_eq!(db.get(1, b"key2").unwrap(), Some(value.clone()));
+		db.commit(vec![(0, b"key1".to_vec(), None)]).unwrap();
 		db_test.run_stages(&db);
+		drop(db);
 
-		assert_eq!(db.get(col_nb, &key1).unwrap(), None);
-		assert_eq!(db.get(col_nb, &key3).unwrap(), None);
-		assert_eq!(db.get(col_nb, &key2).unwrap(), Some(b"value2b".to_vec()));
-		assert_eq!(db.get(col_nb, &key4).unwrap(), Some(b"value4".to_vec()));
-		let mut key22 = key2.clone();
-		key22.push(2);
-		iter.seek(key22.as_slice()).unwrap();
-		assert_eq!(iter.next().unwrap(), Some((key4, b"value4".to_vec())));
-		assert_eq!(iter.next().unwrap(), None);
+		let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
+		assert_eq!(db.get(0, b"key1").unwrap(), None);
+		assert_eq!(db.get(0, b"key2").unwrap(), Some(value));
+	}
+
+	#[test]
+	fn test_max_log_overlay_bytes() {
+		let tmp = tempdir().unwrap();
+		let db_test = EnableCommitPipelineStages::LogOverlay;
+		let mut options = db_test.options(tmp.path(), 1);
+		options.max_log_overlay_bytes = Some(32 * 1024);
+		let value = |i: u32| vec![i as u8; 5000];
+		let key = |i: u32| i.to_le_bytes().to_vec();
+
+		let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
+		for i in 0..40 {
+			db.commit(vec![(0, key(i), Some(value(i)))]).unwrap();
+			if i % 10 == 9 {
+				db.commit(vec![(0, key(i - 9), None)]).unwrap();
+			}
+			db_test.run_stages(&db);
+			// No background threads, so logs enacted by the commit are cleaned here.
+			db.inner.clean_logs().unwrap();
+			assert!(db.inner.log.overlays().read().max_value_bytes() <= 32 * 1024);
+			for k in 0..=i {
+				let expected = if k % 10 == 0 && k + 9 <= i { None } else { Some(value(k)) };
+				assert_eq!(db.get(0, &key(k)).unwrap(), expected);
+			}
+		}
+		// Older values were written to the table.
+		match &db.inner.columns[0] {
+			Column::Hash(column) => {
+				let no_log = crate::log::LogOverlays::default();
+				let written = (0..40)
+					.filter(|k| column.get(&column.hash_key(&key(*k)), &no_log).unwrap().is_some())
+					.count();
+				assert!(written > 0);
+			},
+			Column::Tree(_) => unreachable!(),
+		}
+		drop(db);
+
+		let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
+		for k in 0..40 {
+			let expected = if k % 10 == 0 { None } else { Some(value(k)) };
+			assert_eq!(db.get(0, &key(k)).unwrap(), expected);
+		}
+	}
+
+	#[test]
+	fn test_ref_counted_dedup() {
+		let tmp = tempdir().unwrap();
+		let db_test = EnableCommitPipelineStages::DbFile;
+		let mut options = db_test.options(tmp.path(), 1);
+		options.columns[0].ref_counted = true;
+		options.columns[0].preimage = true;
+		let (key, value) = (b"node".to_vec(), vec![7u8; 10_000]);
+		let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
+		for _ in 0..2 {
+			db.commit([(0, key.clone(), Some(value.clone()))]).unwrap();
+			db_test.run_stages(&db);
+		}
+		// The second insert only increments the reference counter of the stored value.
+		let stats = db.stats().columns[0].take().unwrap();
+		assert_eq!((stats.total_values, stats.logical_writes), (1, 1));
+		db.commit([(0, key.clone(), None)]).unwrap();
+		db_test.run_stages(&db);
+		drop(db);
+
+		let db = Db::open_inner(&options, OpeningMode::Write).unwrap();
+		assert_eq!(db.get(0, &key).unwrap(), Some(value));
+		db.commit([(0, key.clone(), None)]).unwrap();
+		db_test.run_stages(&db);
+		assert_eq!(db.get(0, &key).unwrap(), None);
+		let stats = db.stats().columns[0].take().unwrap();
+		assert_eq!(stats.total_values, 0);
+		// The only entry of the table is free.
+		let [table] = stats.value_tables.as_slice() else { panic!("Expected one value table") };
+		assert_eq!((table.filled, table.free, table.used_bytes), (2, 1, 0));
+	}
+
+	#[test]
+	fn test_commit_rejects_oversized_values() {
+		let tmp = tempdir().unwrap();
+		let db_test = EnableCommitPipelineStages::DbFile;
+		let mut options = db_test.options(tmp.path(), 1);
+		options.columns[0].compression = crate::CompressionType::Lz4;
+		options.compression_threshold.insert(0, 0);
+		options.max_chain_depth.insert(0, 2);
+		let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
+		// Only fits compressed.
+		let compressible = vec![1u8; 100_000];
+		db.commit([(0, b"compressible".to_vec(), Some(compressible.clone()))]).unwrap();
+		db_test.run_stages(&db);
+
+		let Column::Hash(column) = &db.inner.columns[0] else { panic!("Not a hash column") };
+		let key = column.hash_key(b"oversized");
+		let oversized: Vec<u8> = (0..100_000).map(|_| rand::random()).collect();
+		let result = db.commit([
+			(0, b"small".to_vec(), Some(vec![2u8; 10])),
+			(0, b"oversized".to_vec(), Some(oversized)),
+		]);
+		match result {
+			Err(crate::Error::InvalidInput(message)) =>
+				assert!(message.contains(&crate::display::hex(&key).to_string())),
+			r => panic!("Unexpected result {r:?}"),
+		}
+		db_test.run_stages(&db);
+		assert_eq!(db.get(0, b"small").unwrap(), None);
+		assert_eq!(db.get(0, b"compressible").unwrap(), Some(compressible));
+	}
+
+	#[test]
+	fn test_multipart_entry_size() {
+		let tmp = tempdir().unwrap();
+		let db_test = EnableCommitPipelineStages::DbFile;
+		let mut options = db_test.options(tmp.path(), 1);
+		options.columns[0].multipart_entry_size = 16384;
+		let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
+		let value = vec![3u8; 100_000];
+		db.commit([(0, b"large".to_vec(), Some(value.clone()))]).unwrap();
+		db_test.run_stages(&db);
+		drop(db);
+
+		// Header and 7 chunks, instead of 25 with the default entry size.
+		let tier = (crate::table::SIZE_TIERS - 1) as u8;
+		assert_eq!(crate::read_table_header(tmp.path(), 0, tier).unwrap().filled, 8);
+
+		let mut other = options.clone();
+		other.columns[0].multipart_entry_size = 4096;
+		assert!(matches!(
+			Db::open_inner(&other, OpeningMode::Write),
+			Err(crate::Error::IncompatibleColumnConfig { id: 0, .. })
+		));
+		other.columns[0].multipart_entry_size = 16;
+		assert!(!other.is_valid());
+
+		let db = Db::open_inner(&options, OpeningMode::Write).unwrap();
+		assert_eq!(db.get(0, b"large").unwrap(), Some(value));
+	}
+
+	#[test]
+	fn test_append_only_rejects_freeing_changes() {
+		let tmp = tempdir().unwrap();
+		let db_test = EnableCommitPipelineStages::DbFile;
+		let mut options = db_test.options(tmp.path(), 1);
+		options.columns[0].append_only = true;
+		let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
+		let value = |len: usize| vec![7u8; len];
+		db.commit([
+			(0, b"small".to_vec(), Some(value(10))),
+			(0, b"large".to_vec(), Some(value(100_000))),
+		])
+		.unwrap();
+		db_test.run_stages(&db);
+		// Same size, and a longer chain.
+		db.commit([
+			(0, b"small".to_vec(), Some(vec![8u8; 10])),
+			(0, b"large".to_vec(), Some(value(200_000))),
+		])
+		.unwrap();
+		db_test.run_stages(&db);
+
+		let tables = || db.stats().columns[0].take().unwrap().value_tables;
+		let before = tables();
+		for change in [
+			(b"large".to_vec(), Some(value(50_000))),
+			(b"small".to_vec(), Some(value(1000))),
+			(b"small".to_vec(), None),
+		] {
+			db.commit([(0, change.0, change.1)]).unwrap();
+			assert!(matches!(db.inner.process_commits(), Err(crate::Error::InvalidInput(_))));
+			assert_eq!(tables(), before);
+		}
+	}
+
+	fn parallel_enact_options(path: &Path, parallel: bool) -> Options {
+		let mut options = EnableCommitPipelineStages::DbFile.options(path, 3);
+		options.salt = Some([0; 32]);
+		options.columns[1].ref_counted = true;
+		options.columns[1].preimage = true;
+		options.columns[2].btree_index = true;
+		options.parallel_enact = parallel;
+		options
+	}
+
+	#[test]
+	fn test_parallel_enact() {
+		let sequential = tempdir().unwrap();
+		let parallel = tempdir().unwrap();
+		let db_test = EnableCommitPipelineStages::DbFile;
+		let sizes = [10, 100, 500, 1000, 5000, 50000];
+		let commits: Vec<Vec<_>> = (0..4u32)
+			.map(|i| {
+				let mut commit = Vec::new();
+				for (n, size) in sizes.iter().enumerate() {
+					let key = (i * 100 + n as u32).to_le_bytes().to_vec();
+					let value = vec![(i as usize + n) as u8; *size];
+					for col in 0..3 {
+						commit.push((col, key.clone(), Some(value.clone())));
+					}
+				}
+				if i > 0 {
+					let key = ((i - 1) * 100).to_le_bytes().to_vec();
+					commit.push((0, key.clone(), None));
+					commit.push((2, key, None));
+				}
+				commit
+			})
+			.collect();
+
+		for (path, flag) in [(sequential.path(), false), (parallel.path(), true)] {
+			let options = parallel_enact_options(path, flag);
+			let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
+			for commit in &commits {
+				db.commit(commit.clone()).unwrap();
+				db_test.run_stages(&db);
+			}
+			drop(db);
+
+			let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
+			for (i, commit) in commits.iter().enumerate() {
+				for (col, key, value) in commit {
+					let removed = i < commits.len() - 1 &&
+						*col != 1 && key == &(i as u32 * 100).to_le_bytes().to_vec();
+					let expected = if removed { None } else { value.clone() };
+					assert_eq!(db.get(*col, key).unwrap(), expected);
+				}
+			}
+		}
+
+		// Both modes write the same files.
+		let files = |path: &Path| {
+			let mut files: Vec<_> = std::fs::read_dir(path)
+				.unwrap()
+				.map(|entry| entry.unwrap())
+				.filter(|entry| {
+					let name = entry.file_name().into_string().unwrap();
+					name.starts_with("table_") || name.starts_with("index_")
+				})
+				.map(|entry| (entry.file_name(), std::fs::read(entry.path()).unwrap()))
+				.collect();
+			files.sort();
+			files
+		};
+		let sequential_files = files(sequential.path());
+		assert!(!sequential_files.is_empty());
+		assert!(sequential_files == files(parallel.path()));
+	}
+
+	// Replays about 100MB of logged values, with and without `parallel_enact`.
+	#[cfg(feature = "bench")]
+	#[test]
+	fn bench_parallel_enact() {
+		use rand::{RngCore, SeedableRng};
+		let sizes = [100, 400, 1000, 2000, 4000, 10000, 40000];
+		let mut rng = rand::rngs::SmallRng::seed_from_u64(0);
+		let commits: Vec<Vec<_>> = (0..220u32)
+			.map(|i| {
+				(0..sizes.len() * 8)
+					.map(|n| {
+						let mut value = vec![0u8; sizes[n % sizes.len()]];
+						rng.fill_bytes(&mut value);
+						(0, (i * 1000 + n as u32).to_le_bytes().to_vec(), Some(value))
+					})
+					.collect()
+			})
+			.collect();
+
+		for parallel in [false, true] {
+			let tmp = tempdir().unwrap();
+			let db =
+				Db::open_inner(&parallel_enact_options(tmp.path(), parallel), OpeningMode::Create)
+					.unwrap();
+			for commit in &commits {
+				db.commit(commit.clone()).unwrap();
+				while db.inner.process_commits().unwrap() {}
+			}
+			while db.inner.log.flush_one(0).unwrap() {}
+			let start = std::time::Instant::now();
+			while db.inner.enact_logs(false).unwrap() {}
+			println!("parallel_enact={}: {:?}", parallel, start.elapsed());
+		}
+	}
+
+	#[test]
+	fn test_pause_writes() {
+		let tmp = tempdir().unwrap();
+		let copy = tempdir().unwrap();
+		let db_test = EnableCommitPipelineStages::Standard;
+		let options = db_test.options(tmp.path(), 1);
+		let wait_logged = |db: &Db| {
+			for _ in 0..100 {
+				if db.inner.commit_overlay.read()[0].is_empty() {
+					return
+				}
+				std::thread::sleep(std::time::Duration::from_millis(10));
+			}
+			panic!("Commits were not logged");
+		};
+
+		let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
+		db.commit((0u8..10).map(|i| (0, vec![i], Some(vec![i; 100])))).unwrap();
+		wait_logged(&db);
+		db.pause_writes().unwrap();
+
+		db.commit(vec![(0, vec![0], None), (0, vec![10], Some(vec![10; 100]))]).unwrap();
+		wait_logged(&db);
+		assert_eq!(db.get(0, &[0]).unwrap(), None);
+		assert_eq!(db.get(0, &[10]).unwrap(), Some(vec![10; 100]));
+		for entry in std::fs::read_dir(tmp.path()).unwrap() {
+			let entry = entry.unwrap();
+			let name = entry.file_name().into_string().unwrap();
+			if name == "metadata" || name.starts_with("index_") || name.starts_with("table_") {
+				std::fs::copy(entry.path(), copy.path().join(name)).unwrap();
+			}
+		}
+		db.resume_writes();
+
+		db.commit(vec![(0, vec![11], Some(vec![11; 100]))]).unwrap();
+		drop(db);
+
+		// The copy has all changes made before pausing and none after.
+		let db = Db::open(&db_test.options(copy.path(), 1)).unwrap();
+		for i in 0u8..10 {
+			assert_eq!(db.get(0, &[i]).unwrap(), Some(vec![i; 100]));
+		}
+		assert_eq!(db.get(0, &[10]).unwrap(), None);
+		drop(db);
+
+		let db = Db::open(&options).unwrap();
+		assert_eq!(db.get(0, &[0]).unwrap(), None);
+		for i in 1u8..12 {
+			assert_eq!(db.get(0, &[i]).unwrap(), Some(vec![i; 100]));
+		}
+	}
+
+	#[test]
+	fn test_trusted_open() {
+		let tmp = tempdir().unwrap();
+		let crashed = tempdir().unwrap();
+		let db_test = EnableCommitPipelineStages::DbFile;
+		let mut options = db_test.options(tmp.path(), 1);
+		options.trusted_open = true;
+		let insert = |db: &Db, keys: std::ops::Range<u32>| {
+			db.commit(keys.map(|i| (0, i.to_le_bytes().to_vec(), Some(vec![i as u8; 100]))))
+				.unwrap();
+			db_test.run_stages(db);
+		};
+		let check = |db: &Db, keys: std::ops::Range<u32>| {
+			for i in keys {
+				assert_eq!(db.get(0, &i.to_le_bytes()).unwrap(), Some(vec![i as u8; 100]));
+			}
+		};
+
+		let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
+		insert(&db, 0..50);
+		drop(db);
+		assert_eq!(std::fs::read(tmp.path().join("clean_shutdown")).unwrap().len(), 4);
+
+		// Read-only opens keep the marker and don't save the manifest.
+		let manifest = std::fs::read(tmp.path().join("manifest")).unwrap();
+		std::fs::remove_file(tmp.path().join("manifest")).unwrap();
+		let db = Db::open_inner(&options, OpeningMode::ReadOnly).unwrap();
+		drop(db);
+		assert_eq!(std::fs::read(tmp.path().join("clean_shutdown")).unwrap().len(), 4);
+		assert!(!tmp.path().join("manifest").exists());
+		std::fs::write(tmp.path().join("manifest"), manifest).unwrap();
+
+		// Opens without `trusted_open` don't save the manifest, so they clear the marker.
+		let untrusted = db_test.options(tmp.path(), 1);
+		drop(Db::open_inner(&untrusted, OpeningMode::Write).unwrap());
+		assert_eq!(std::fs::read(tmp.path().join("clean_shutdown")).unwrap().len(), 0);
+		drop(Db::open_inner(&options, OpeningMode::Write).unwrap());
+		assert_eq!(std::fs::read(tmp.path().join("clean_shutdown")).unwrap().len(), 4);
+
+		// Table headers are not read when the manifest is trusted.
+		let mut tables = Vec::new();
+		for entry in std::fs::read_dir(tmp.path()).unwrap() {
+			let path = entry.unwrap().path();
+			if path.file_name().unwrap().to_str().unwrap().starts_with("table_00_") {
+				let mut data = std::fs::read(&path).unwrap();
+				data[0..8].copy_from_slice(&u64::MAX.to_le_bytes());
+				std::fs::write(&path, data).unwrap();
+				tables.push(path);
+			}
+		}
+		assert!(!tables.is_empty());
+		let db = Db::open_inner(&options, OpeningMode::Write).unwrap();
+		check(&db, 0..50);
+		assert_eq!(std::fs::read(tmp.path().join("clean_shutdown")).unwrap().len(), 0);
+
+		// A crash leaves a stale manifest without the marker.
+		insert(&db, 50..100);
+		for entry in std::fs::read_dir(tmp.path()).unwrap() {
+			let path = entry.unwrap().path();
+			if path.file_name().unwrap() != "lock" {
+				std::fs::copy(&path, crashed.path().join(path.file_name().unwrap())).unwrap();
+			}
+		}
+		drop(db);
+
+		let options = db_test.options(crashed.path(), 1);
+		let db = Db::open_inner(&options, OpeningMode::Write).unwrap();
+		// Slots are allocated past the values written after the manifest was saved.
+		insert(&db, 100..150);
+		check(&db, 0..150);
+	}
+
+	#[test]
+	fn test_validate_free_lists_on_open() {
+		let tmp = tempdir().unwrap();
+		let db_test = EnableCommitPipelineStages::DbFile;
+		let mut options = db_test.options(tmp.path(), 1);
+		options.validate_free_lists_on_open = true;
+
+		let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
+		db.commit((0u32..10).map(|i| (0, i.to_le_bytes().to_vec(), Some(vec![i as u8; 100]))))
+			.unwrap();
+		db_test.run_stages(&db);
+		db.commit((0u32..5).map(|i| (0, i.to_le_bytes().to_vec(), None))).unwrap();
+		db_test.run_stages(&db);
+		drop(db);
+		let db = Db::open_inner(&options, OpeningMode::Write).unwrap();
+		drop(db);
+
+		// Point the list head past the end of the table.
+		let mut corrupted = false;
+		for entry in std::fs::read_dir(tmp.path()).unwrap() {
+			let path = entry.unwrap().path();
+			if path.file_name().unwrap().to_str().unwrap().starts_with("table_00_") {
+				let mut data = std::fs::read(&path).unwrap();
+				if data[0..8] == [0; 8] {
+					continue
+				}
+				let filled = u64::from_le_bytes(data[8..16].try_into().unwrap());
+				data[0..8].copy_from_slice(&(filled + 100).to_le_bytes());
+				std::fs::write(&path, data).unwrap();
+				corrupted = true;
+			}
+		}
+		assert!(corrupted);
+		assert!(matches!(
+			Db::open_inner(&options, OpeningMode::Write),
+			Err(crate::error::Error::Corruption(_))
+		));
+	}
+
+	#[test]
+	fn test_get_batch() {
+		let tmp = tempdir().unwrap();
+		let db_test = EnableCommitPipelineStages::DbFile;
+		let mut options = db_test.options(tmp.path(), 2);
+		options.columns[0].compression = crate::CompressionType::Lz4;
+		options.columns[1].btree_index = true;
+		let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
+		let mut rng = rand::thread_rng();
+		let value = |i: u32| vec![i as u8; 1 + (i as usize * 37) % 6000];
+		for col in 0..2 {
+			db.commit((0..400u32).map(|i| (col, i.to_le_bytes().to_vec(), Some(value(i)))))
+				.unwrap();
+			db_test.run_stages(&db);
+			db.commit((0..400u32).step_by(5).map(|i| (col, i.to_le_bytes().to_vec(), None)))
+				.unwrap();
+			db_test.run_stages(&db);
+			// Left in the commit overlay.
+			db.commit((400..420u32).map(|i| (col, i.to_le_bytes().to_vec(), Some(value(i)))))
+				.unwrap();
+		}
+
+		for col in 0..2 {
+			let mut keys: Vec<Vec<u8>> = (0..500u32).map(|i| i.to_le_bytes().to_vec()).collect();
+			for i in (1..keys.len()).rev() {
+				keys.swap(i, rng.gen_range(0..=i));
+			}
+			let expected: Vec<_> = keys.iter().map(|k| db.get(col, k).unwrap()).collect();
+			assert_eq!(expected.iter().filter(|v| v.is_some()).count(), 340);
+			assert_eq!(db.get_batch(col, &keys).unwrap(), expected);
+		}
+		assert_eq!(db.get_batch::<&[u8]>(0, &[]).unwrap(), vec![]);
+	}
+
+	#[cfg(feature = "bench")]
+	fn bench_get_internal(b: &mut Bencher, batch: bool) {
+		let tmp = tempdir().unwrap();
+		let db_test = EnableCommitPipelineStages::DbFile;
+		let db = Db::open_inner(&db_test.options(tmp.path(), 1), OpeningMode::Create).unwrap();
+		db.commit((0..10000u32).map(|i| (0, i.to_le_bytes().to_vec(), Some(vec![i as u8; 100]))))
+			.unwrap();
+		db_test.run_stages(&db);
+		let keys: Vec<Vec<u8>> = (0..10000u32)
+			.step_by(97)
+			.map(|i| (i * 7919 % 10000).to_le_bytes().to_vec())
+			.collect();
+		b.iter(|| {
+			if batch {
+				assert!(db.get_batch(0, &keys).unwrap().iter().all(|v| v.is_some()));
+			} else {
+				assert!(keys.iter().all(|k| db.get(0, k).unwrap().is_some()));
+			}
+		});
+	}
+
+	#[cfg(feature = "bench")]
+	#[bench]
+	fn bench_get_scattered(b: &mut Bencher) {
+		bench_get_internal(b, false)
+	}
+
+	#[cfg(feature = "bench")]
+	#[bench]
+	fn bench_get_batch_scattered(b: &mut Bencher) {
+		bench_get_internal(b, true)
+	}
+
+	#[cfg(target_os = "linux")]
+	#[test]
+	fn test_read_priority() {
+		use crate::file::{take_ioprio_calls, ReadPriority};
+
+		let tmp = tempdir().unwrap();
+		let db_test = EnableCommitPipelineStages::DbFile;
+		let options = db_test.options(tmp.path(), 1);
+		let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
+		db.commit(vec![(0, b"key".to_vec(), Some(b"value".to_vec()))]).unwrap();
+		db_test.run_stages(&db);
+
+		take_ioprio_calls();
+		assert_eq!(
+			db.get_with_priority(0, b"key", ReadPriority::Normal).unwrap(),
+			Some(b"value".to_vec())
+		);
+		assert_eq!(take_ioprio_calls(), Vec::<libc::c_int>::new());
+
+		// Best effort class, level 0, then the previous priority is restored.
+		assert_eq!(
+			db.get_with_priority(0, b"key", ReadPriority::High).unwrap(),
+			Some(b"value".to_vec())
+		);
+		assert_eq!(take_ioprio_calls(), vec![2 << 13, 0]);
+
+		// Best effort class, level 7.
+		assert_eq!(db.get_with_priority(0, b"missing", ReadPriority::Low).unwrap(), None);
+		assert_eq!(take_ioprio_calls(), vec![(2 << 13) | 7, 0]);
+	}
+
+	#[test]
+	fn test_iter_column_checked() {
+		let tmp = tempdir().unwrap();
+		let db_test = EnableCommitPipelineStages::DbFile;
+		let mut options = db_test.options(tmp.path(), 1);
+		options.columns[0].compression = crate::CompressionType::Lz4;
+		options.compression_threshold.insert(0, 0);
+		let values: Vec<Value> = (0u8..20).map(|i| vec![i; 500]).collect();
+
+		{
+			let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
+			db.commit(values.iter().enumerate().map(|(i, v)| (0, vec![i as u8], Some(v.clone()))))
+				.unwrap();
+			db_test.run_stages(&db);
+		}
+
+		// Replace the codec tag of one stored value.
+		let compressed = crate::compress::Compress::new(
+			crate::CompressionType::Lz4,
+			0,
+			crate::options::CURRENT_VERSION,
+		)
+		.compress(&values[7]);
+		let mut corrupted = false;
+		for entry in std::fs::read_dir(tmp.path()).unwrap() {
+			let path = entry.unwrap().path();
+			if !path.file_name().unwrap().to_str().unwrap().starts_with("table_00_") {
+				continue
+			}
+			let mut data = std::fs::read(&path).unwrap();
+			if let Some(pos) = data.windows(compressed.len()).position(|w| w == compressed) {
+				data[pos] = 0xee;
+				std::fs::write(&path, data).unwrap();
+				corrupted = true;
+			}
+		}
+		assert!(corrupted);
+
+		let db = Db::open(&options).unwrap();
+		let mut good = Vec::new();
+		let mut bad = Vec::new();
+		db.iter_column_checked_while(0, |item| {
+			match item {
+				Ok(state) => good.push(state.value),
+				Err(info) => bad.push(info),
+			}
+			true
+		})
+		.unwrap();
+		assert_eq!(bad.len(), 1);
+		assert!(matches!(bad[0].error, crate::Error::Compression));
+		let mut expected = values.clone();
+		expected.remove(7);
+		good.sort();
+		assert_eq!(good, expected);
+
+		assert!((1..=values.len() as u64).contains(&bad[0].index));
+		assert!(matches!(db.get(0, &[7]), Err(crate::Error::Compression)));
+	}
+
+	#[test]
+	fn test_untagged_compression_v8() {
+		let tmp = tempdir().unwrap();
+		let db_test = EnableCommitPipelineStages::DbFile;
+		let mut options = db_test.options(tmp.path(), 2);
+		options.columns[0].compression = crate::CompressionType::Lz4;
+		options.columns[1].btree_index = true;
+		options.compression_threshold.insert(0, 0);
+		// Keys hash the same on each run, so the bytes before the value are fixed.
+		options.salt = Some(Default::default());
+		drop(Db::open_inner(&options, OpeningMode::Create).unwrap());
+		let salt = Options::load_metadata(tmp.path()).unwrap().unwrap().salt;
+		options.write_metadata_with_version(tmp.path(), &salt, Some(8)).unwrap();
+
+		// Values are written without a codec tag.
+		let value = vec![1u8; 10000];
+		let db = Db::open_inner(&options, OpeningMode::Write).unwrap();
+		assert_eq!(db.inner.db_version, 8);
+		db.commit(vec![(0, b"lz4".to_vec(), Some(value.clone()))]).unwrap();
+		db.commit(vec![(1, b"btree".to_vec(), Some(value.clone()))]).unwrap();
+		db_test.run_stages(&db);
+		drop(db);
+		let compress = |version| {
+			crate::compress::Compress::new(crate::CompressionType::Lz4, 0, version).compress(&value)
+		};
+		let (untagged, tagged) = (compress(8), compress(crate::options::CURRENT_VERSION));
+		let mut found = (false, false);
+		for entry in std::fs::read_dir(tmp.path()).unwrap() {
+			let path = entry.unwrap().path();
+			if path.file_name().unwrap().to_str().unwrap().starts_with("table_00_") {
+				let data = std::fs::read(&path).unwrap();
+				found.0 |= data.windows(untagged.len()).any(|w| w == untagged);
+				found.1 |= data.windows(tagged.len()).any(|w| w == tagged);
+			}
+		}
+		assert_eq!(found, (true, false));
+
+		// The codec can't change without the tags, and the database stays at version 8.
+		options.columns[0].compression = crate::CompressionType::Snappy;
+		assert!(matches!(
+			Db::open_inner(&options, OpeningMode::Write),
+			Err(crate::Error::IncompatibleColumnConfig { .. })
+		));
+		options.columns[0].compression = crate::CompressionType::Lz4;
+		let db = Db::open_inner(&options, OpeningMode::Write).unwrap();
+		assert_eq!(db.get(0, b"lz4").unwrap(), Some(value.clone()));
+		assert_eq!(db.get(1, b"btree").unwrap(), Some(value));
+		drop(db);
+		assert_eq!(Options::load_metadata(tmp.path()).unwrap().unwrap().version, 8);
+	}
+
+	#[test]
+	fn test_change_compression() {
+		let tmp = tempdir().unwrap();
+		let db_test = EnableCommitPipelineStages::DbFile;
+		let mut options = db_test.options(tmp.path(), 1);
+		options.columns[0].compression = crate::CompressionType::Lz4;
+		options.compression_threshold.insert(0, 0);
+
+		let lz4_value = vec![1u8; 10000];
+		let snappy_value = vec![2u8; 10000];
+
+		let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
+		db.commit(vec![(0, b"lz4".to_vec(), Some(lz4_value.clone()))]).unwrap();
+		db_test.run_stages(&db);
+		drop(db);
+
+		options.columns[0].compression = crate::CompressionType::Snappy;
+		// Read only opens don't update the metadata.
+		let metadata_path = tmp.path().join("metadata");
+		let saved = std::fs::read(&metadata_path).unwrap();
+		let db = Db::open_inner(&options, OpeningMode::ReadOnly).unwrap();
+		assert_eq!(db.get(0, b"lz4").unwrap(), Some(lz4_value.clone()));
+		drop(db);
+		assert_eq!(std::fs::read(&metadata_path).unwrap(), saved);
+
+		let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
+		db.commit(vec![(0, b"snappy".to_vec(), Some(snappy_value.clone()))]).unwrap();
+		db_test.run_stages(&db);
+		assert_eq!(db.get(0, b"lz4").unwrap(), Some(lz4_value.clone()));
+		assert_eq!(db.get(0, b"snappy").unwrap(), Some(snappy_value.clone()));
+		drop(db);
+
+		let metadata = Options::load_metadata(tmp.path()).unwrap().unwrap();
+		assert_eq!(metadata.columns[0].compression, crate::CompressionType::Snappy);
+		assert!(!tmp.path().join("metadata.tmp").exists());
+		let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
+		assert_eq!(db.get(0, b"lz4").unwrap(), Some(lz4_value));
+		assert_eq!(db.get(0, b"snappy").unwrap(), Some(snappy_value));
+		drop(db);
+
+		// Other options can't change.
+		options.columns[0].uniform = true;
+		assert!(matches!(
+			Db::open_inner(&options, OpeningMode::Create),
+			Err(crate::Error::IncompatibleColumnConfig { .. })
+		));
+	}
+
+	#[test]
+	fn test_encryption_config() {
+		let tmp = tempdir().unwrap();
+		let db_test = EnableCommitPipelineStages::DbFile;
+		let mut options = db_test.options(tmp.path(), 2);
+		let encryption = crate::EncryptionOptions { key: [1; 32], deterministic: false };
+		options.encryption.insert(1, encryption.clone());
+
+		let value = vec![1u8; 10000];
+		let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
+		db.commit(vec![(1, b"key".to_vec(), Some(value.clone()))]).unwrap();
+		db_test.run_stages(&db);
+		drop(db);
+
+		let mismatch = |options: &Options| {
+			matches!(
+				Db::open_inner(options, OpeningMode::Write),
+				Err(crate::Error::IncompatibleColumnConfig { id: 1, .. })
+			)
+		};
+		let mut other = options.clone();
+		other.encryption.clear();
+		assert!(mismatch(&other));
+		other
+			.encryption
+			.insert(1, crate::EncryptionOptions { key: [2; 32], ..encryption });
+		assert!(mismatch(&other));
+		let mut other = options.clone();
+		other.encryption.insert(0, encryption);
+		assert!(matches!(
+			Db::open_inner(&other, OpeningMode::Write),
+			Err(crate::Error::IncompatibleColumnConfig { id: 0, .. })
+		));
+
+		let db = Db::open_inner(&options, OpeningMode::Write).unwrap();
+		assert_eq!(db.get(1, b"key").unwrap(), Some(value));
+	}
+
+	#[test]
+	fn test_indexed_btree_1() {
+		test_indexed_btree_inner(EnableCommitPipelineStages::CommitOverlay, false);
+		test_indexed_btree_inner(EnableCommitPipelineStages::LogOverlay, false);
+		test_indexed_btree_inner(EnableCommitPipelineStages::DbFile, false);
+		test_indexed_btree_inner(EnableCommitPipelineStages::Standard, false);
+		test_indexed_btree_inner(EnableCommitPipelineStages::CommitOverlay, true);
+		test_indexed_btree_inner(EnableCommitPipelineStages::LogOverlay, true);
+		test_indexed_btree_inner(EnableCommitPipelineStages::DbFile, true);
+		test_indexed_btree_inner(EnableCommitPipelineStages::Standard, true);
+	}
+	fn test_indexed_btree_inner(db_test: EnableCommitPipelineStages, long_key: bool) {
+		let tmp = tempdir().unwrap();
+		let col_nb = 0u8;
+		let mut options = db_test.options(tmp.path(), 5);
+		options.columns[col_nb as usize].btree_index = true;
+
+		let (key1, key2, key3, key4) = if !long_key {
+			(b"key1".to_vec(), b"key2".to_vec(), b"key3".to_vec(), b"key4".to_vec())
+		} else {
+			let key2 = vec![2; 272];
+			let mut key3 = key2.clone();
+			key3[271] = 3;
+			(vec![1; 953], key2, key3, vec![4; 79])
+		};
+
+		let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
+		assert_eq!(db.get(col_nb, &key1).unwrap(), None);
+
+		let mut iter = db.iter(col_nb).unwrap();
+		assert_eq!(iter.next().unwrap(), None);
+		assert_eq!(iter.prev().unwrap(), None);
+
+		db.commit(vec![(col_nb, key1.clone(), Some(b"value1".to_vec()))]).unwrap();
+		db_test.run_stages(&db);
+
+		assert_eq!(db.get(col_nb, &key1).unwrap(), Some(b"value1".to_vec()));
+		iter.seek_to_first().unwrap();
+		assert_eq!(iter.next().unwrap(), Some((key1.clone(), b"value1".to_vec())));
+		assert_eq!(iter.next().unwrap(), None);
+		assert_eq!(iter.prev().unwrap(), Some((key1.clone(), b"value1".to_vec())));
+		assert_eq!(iter.prev().unwrap(), None);
+		assert_eq!(iter.next().unwrap(), Some((key1.clone(), b"value1".to_vec())));
+		assert_eq!(iter.next().unwrap(), None);
+
+		iter.seek_to_first().unwrap();
+		assert_eq!(iter.next().unwrap(), Some((key1.clone(), b"value1".to_vec())));
+		assert_eq!(iter.prev().unwrap(), None);
+
+		iter.seek(&[0xff]).unwrap();
+		assert_eq!(iter.prev().unwrap(), Some((key1.clone(), b"value1".to_vec())));
+		assert_eq!(iter.prev().unwrap(), None);
+
+		db.commit(vec![
+			(col_nb, key1.clone(), None),
+			(col_nb, key2.clone(), Some(b"value2".to_vec())),
+			(col_nb, key3.clone(), Some(b"value3".to_vec())),
+		])
+		.unwrap();
+		db_test.run_stages(&db);
+
+		assert_eq!(db.get(col_nb, &key1).unwrap(), None);
+		assert_eq!(db.get(col_nb, &key2).unwrap(), Some(b"value2".to_vec()));
+		assert_eq!(db.get(col_nb, &key3).unwrap(), Some(b"value3".to_vec()));
+
+		iter.seek(key2.as_slice()).unwrap();
+		assert_eq!(iter.next().unwrap(), Some((key2.clone(), b"value2".to_vec())));
+		assert_eq!(iter.next().unwrap(), Some((key3.clone(), b"value3".to_vec())));
+		assert_eq!(iter.next().unwrap(), None);
+
+		iter.seek(key3.as_slice()).unwrap();
+		assert_eq!(iter.prev().unwrap(), Some((key3.clone(), b"value3".to_vec())));
+		assert_eq!(iter.prev().unwrap(), Some((key2.clone(), b"value2".to_vec())));
+		assert_eq!(iter.prev().unwrap(), None);
+
+		db.commit(vec![
+			(col_nb, key2.clone(), Some(b"value2b".to_vec())),
+			(col_nb, key4.clone(), Some(b"value4".to_vec())),
+			(col_nb, key3.clone(), None),
+		])
+		.unwrap();
+		db_test.run_stages(&db);
+
+		assert_eq!(db.get(col_nb, &key1).unwrap(), None);
+		assert_eq!(db.get(col_nb, &key3).unwrap(), None);
+		assert_eq!(db.get(col_nb, &key2).unwrap(), Some(b"value2b".to_vec()));
+		assert_eq!(db.get(col_nb, &key4).unwrap(), Some(b"value4".to_vec()));
+		let mut key22 = key2.clone();
+		key22.push(2);
+		iter.seek(key22.as_slice()).unwrap();
+		assert_eq!(iter.next().unwrap(), Some((key4, b"value4".to_vec())));
+		assert_eq!(iter.next().unwrap(), None);
 	}
 
 	#[test]
@@ -2566,6 +3858,118 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn test_lookup_checks_full_key() {
+		let tmp = tempdir().unwrap();
+		let db_test = EnableCommitPipelineStages::DbFile;
+		let mut options = db_test.options(tmp.path(), 1);
+		options.columns[0].uniform = true;
+		// Keys are used as is.
+		options.salt = Some(Default::default());
+		let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
+
+		let key: Vec<u8> = (1..=32).collect();
+		let flipped = |bit: usize| {
+			let mut other = key.clone();
+			other[bit / 8] ^= 0x80 >> (bit % 8);
+			other
+		};
+		// Differs in a bit that is only checked by the index entry, not by the stored key.
+		let neighbour = flipped(40);
+		db.commit([(0, key.clone(), Some(vec![1])), (0, neighbour.clone(), Some(vec![2]))])
+			.unwrap();
+		db_test.run_stages(&db);
+
+		assert_eq!(db.get(0, &key).unwrap(), Some(vec![1]));
+		assert_eq!(db.get(0, &neighbour).unwrap(), Some(vec![2]));
+		for bit in (0..256).filter(|bit| *bit != 40) {
+			assert_eq!(db.get(0, &flipped(bit)).unwrap(), None, "bit {bit}");
+		}
+	}
+
+	#[test]
+	fn test_write_counters_persist() {
+		let tmp = tempdir().unwrap();
+		let db_test = EnableCommitPipelineStages::DbFile;
+		let options = db_test.options(tmp.path(), 1);
+		let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
+		db.commit([
+			(0, b"key1".to_vec(), Some(vec![1u8; 100])),
+			(0, b"key2".to_vec(), Some(vec![2u8; 100_000])),
+		])
+		.unwrap();
+		db_test.run_stages(&db);
+		let stats = db.stats().columns[0].take().unwrap();
+		assert_eq!(stats.logical_writes, 2);
+		// The large value is split into 4KB entries.
+		assert!(stats.entry_writes > 25);
+		assert!(stats.bytes_written > 100_100);
+		drop(db);
+
+		let db = Db::open_inner(&options, OpeningMode::Write).unwrap();
+		let reopened = db.stats().columns[0].take().unwrap();
+		assert_eq!(reopened.logical_writes, stats.logical_writes);
+		assert_eq!(reopened.entry_writes, stats.entry_writes);
+		assert_eq!(reopened.bytes_written, stats.bytes_written);
+		db.clear_stats(None).unwrap();
+		assert_eq!(db.stats().columns[0].take().unwrap().entry_writes, 0);
+	}
+
+	#[test]
+	fn test_get_checked() {
+		let tmp = tempdir().unwrap();
+		let db_test = EnableCommitPipelineStages::DbFile;
+		let options = db_test.options(tmp.path(), 1);
+		let (key1, key2) = (b"key1".to_vec(), b"key2".to_vec());
+		let value1: Vec<u8> = (0..100).collect();
+		let value2: Vec<u8> = (100..200).collect();
+		let db = Db::open_inner(&options, OpeningMode::Create).unwrap();
+		db.commit([
+			(0, key1.clone(), Some(value1.clone())),
+			(0, key2.clone(), Some(value2.clone())),
+		])
+		.unwrap();
+		db_test.run_stages(&db);
+		assert_eq!(db.get_checked(0, &key1).unwrap(), Some(value1.clone()));
+		assert_eq!(db.get_checked(0, b"missing").unwrap(), None);
+		drop(db);
+
+		// Swap the two entries, so that the index of each key points to the other one.
+		let mut swapped = false;
+		for entry in std::fs::read_dir(tmp.path()).unwrap() {
+			let path = entry.unwrap().path();
+			if !path.file_name().unwrap().to_str().unwrap().starts_with("table_00_") {
+				continue
+			}
+			let mut data = std::fs::read(&path).unwrap();
+			let find = |value: &[u8]| data.windows(value.len()).position(|w| w == value);
+			let (Some(pos1), Some(pos2)) = (find(&value1), find(&value2)) else { continue };
+			let entry_size = pos1.abs_diff(pos2);
+			let (start1, start2) = (pos1 - pos1 % entry_size, pos2 - pos2 % entry_size);
+			let entry1 = data[start1..start1 + entry_size].to_vec();
+			data.copy_within(start2..start2 + entry_size, start1);
+			data[start2..start2 + entry_size].copy_from_slice(&entry1);
+			std::fs::write(&path, data).unwrap();
+			swapped = true;
+		}
+		assert!(swapped);
+
+		let db = Db::open_inner(&options, OpeningMode::Write).unwrap();
+		assert_eq!(db.get(0, &key1).unwrap(), None);
+		let key = |k: &[u8]| {
+			let Column::Hash(column) = &db.inner.columns[0] else { unreachable!() };
+			crate::table::key::partial_key(&column.hash_key(k)).to_vec()
+		};
+		match db.get_checked(0, &key1) {
+			Err(crate::error::Error::KeyMismatch { expected, found, .. }) => {
+				assert_eq!(expected, key(&key1));
+				assert_eq!(found, key(&key2));
+			},
+			other => panic!("Unexpected result {other:?}"),
+		}
+		assert_eq!(db.get_checked(0, b"missing").unwrap(), None);
+	}
+
 	#[cfg(feature = "instrumentation")]
 	#[test]
 	fn test_continue_reindex() {
@@ -0,0 +1,187 @@
+// Copyright 2021-2022 Parity Technologies (UK) Ltd.
+// This file is dual-licensed as Apache-2.0 or MIT.
+
+//! Value encryption.
+//!
+//! Values are encrypted with AES-256-GCM-SIV, which authenticates them as well.
+//! Stored value layout: [NONCE: 12][CIPHERTEXT][TAG: 16]
+//! In deterministic mode the nonce is a keyed BLAKE2b hash of the plaintext, otherwise it is
+//! random. GCM-SIV stays secure when a nonce repeats, apart from revealing that the values are
+//! equal.
+
+use crate::error::{Error, Result};
+use aes_gcm_siv::{aead::AeadInPlace, Aes256GcmSiv, KeyInit, Nonce, Tag};
+use blake2::{
+	digest::{typenum::U12, Mac},
+	Blake2bMac, Blake2bMac512,
+};
+use rand::Rng;
+
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+const KEY_SIZE: usize = 32;
+/// Size of the key fingerprint stored in the database metadata.
+pub const KEY_CHECK_SIZE: usize = 8;
+/// Number of bytes encryption adds to each value.
+pub const OVERHEAD: usize = NONCE_SIZE + TAG_SIZE;
+
+/// Column value encryption settings.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionOptions {
+	/// Secret key.
+	pub key: [u8; KEY_SIZE],
+	/// Derive each value nonce from the value itself instead of generating it randomly.
+	///
+	/// Identical values are then stored identically, which lets reference counting deduplicate
+	/// encrypted values. The tradeoff is that anyone with access to the files can tell which
+	/// stored values are equal. Only use this when values have enough entropy, e.g. for content
+	/// addressed data.
+	pub deterministic: bool,
+}
+
+impl EncryptionOptions {
+	/// Fingerprint of the key, saved in the database metadata so that opening with a different
+	/// key fails. Does not reveal the key.
+	pub fn key_check(&self) -> [u8; KEY_CHECK_SIZE] {
+		let mut check = [0u8; KEY_CHECK_SIZE];
+		check.copy_from_slice(&derive_key(&self.key, b"parity-db key check")[..KEY_CHECK_SIZE]);
+		check
+	}
+}
+
+impl std::fmt::Debug for EncryptionOptions {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("EncryptionOptions")
+			.field("deterministic", &self.deterministic)
+			.finish()
+	}
+}
+
+#[derive(Clone)]
+pub struct Cipher {
+	aead: Aes256GcmSiv,
+	nonce_key: [u8; KEY_SIZE],
+	deterministic: bool,
+}
+
+impl std::fmt::Debug for Cipher {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("Cipher").field("deterministic", &self.deterministic).finish()
+	}
+}
+
+fn derive_key(key: &[u8], purpose: &[u8]) -> [u8; KEY_SIZE] {
+	let mut mac = <Blake2bMac512 as Mac>::new_from_slice(key).expect("Key size is valid");
+	mac.update(purpose);
+	let mut result = [0u8; KEY_SIZE];
+	result.copy_from_slice(&mac.finalize().into_bytes()[..KEY_SIZE]);
+	result
+}
+
+impl Cipher {
+	pub fn new(options: &EncryptionOptions) -> Cipher {
+		let value_key = derive_key(&options.key, b"parity-db value");
+		Cipher {
+			aead: Aes256GcmSiv::new_from_slice(&value_key).expect("Key size is valid"),
+			nonce_key: derive_key(&options.key, b"parity-db nonce"),
+			deterministic: options.deterministic,
+		}
+	}
+
+	fn nonce(&self, value: &[u8]) -> [u8; NONCE_SIZE] {
+		if self.deterministic {
+			let mut mac = <Blake2bMac<U12> as Mac>::new_from_slice(&self.nonce_key)
+				.expect("Key size is valid");
+			mac.update(value);
+			mac.finalize().into_bytes().into()
+		} else {
+			rand::thread_rng().gen()
+		}
+	}
+
+	pub fn encrypt(&self, value: &[u8]) -> Vec<u8> {
+		let nonce = self.nonce(value);
+		let mut result = Vec::with_capacity(OVERHEAD + value.len());
+		result.extend_from_slice(&nonce);
+		result.extend_from_slice(value);
+		let tag = self
+			.aead
+			.encrypt_in_place_detached(Nonce::from_slice(&nonce), &[], &mut result[NONCE_SIZE..])
+			.expect("Values are shorter than the AES-GCM-SIV limit");
+		result.extend_from_slice(&tag);
+		result
+	}
+
+	/// Decrypt a value stored by `encrypt`. Fails if the value was not encrypted with the same
+	/// key or was modified.
+	pub fn decrypt(&self, mut data: Vec<u8>) -> Result<Vec<u8>> {
+		if data.len() < OVERHEAD {
+			return Err(Error::Corruption(format!("Encrypted value is too short: {}", data.len())))
+		}
+		let tag = Tag::clone_from_slice(&data[data.len() - TAG_SIZE..]);
+		let end = data.len() - TAG_SIZE;
+		let (nonce, value) = data[..end].split_at_mut(NONCE_SIZE);
+		self.aead
+			.decrypt_in_place_detached(Nonce::from_slice(nonce), &[], value, &tag)
+			.map_err(|_| {
+				Error::Corruption(
+					"Value failed authentication. It is damaged or was encrypted with another key"
+						.into(),
+				)
+			})?;
+		data.truncate(end);
+		data.drain(0..NONCE_SIZE);
+		Ok(data)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn cipher(deterministic: bool) -> Cipher {
+		Cipher::new(&EncryptionOptions { key: [7u8; KEY_SIZE], deterministic })
+	}
+
+	#[test]
+	fn roundtrip() {
+		for deterministic in [false, true] {
+			let cipher = cipher(deterministic);
+			for len in [0, 1, 63, 64, 65, 5000] {
+				let value: Vec<u8> = (0..len).map(|i| i as u8).collect();
+				let encrypted = cipher.encrypt(&value);
+				assert_eq!(encrypted.len(), value.len() + OVERHEAD);
+				assert_eq!(cipher.decrypt(encrypted).unwrap(), value);
+			}
+		}
+	}
+
+	#[test]
+	fn nonce_mode() {
+		let value = b"some value".to_vec();
+		let deterministic = cipher(true);
+		assert_eq!(deterministic.encrypt(&value), deterministic.encrypt(&value));
+		assert_ne!(deterministic.encrypt(&value), deterministic.encrypt(b"other value"));
+		let random = cipher(false);
+		assert_ne!(random.encrypt(&value), random.encrypt(&value));
+	}
+
+	#[test]
+	fn tampering() {
+		let value = b"some value".to_vec();
+		for deterministic in [false, true] {
+			let encrypted = cipher(deterministic).encrypt(&value);
+			for i in 0..encrypted.len() {
+				let mut tampered = encrypted.clone();
+				tampered[i] ^= 1;
+				assert!(matches!(
+					cipher(deterministic).decrypt(tampered),
+					Err(Error::Corruption(_))
+				));
+			}
+			let other = Cipher::new(&EncryptionOptions { key: [8u8; KEY_SIZE], deterministic });
+			assert!(matches!(other.decrypt(encrypted), Err(Error::Corruption(_))));
+		}
+		assert!(cipher(false).decrypt(vec![0; OVERHEAD - 1]).is_err());
+	}
+}
@@ -1,7 +1,7 @@
 // Copyright 2021-2022 Parity Technologies (UK) Ltd.
 // This file is dual-licensed as Apache-2.0 or MIT.
 
-use crate::column::ColId;
+use crate::{column::ColId, display::hex};
 #[cfg(feature = "instrumentation")]
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::{fmt, io, sync::Arc};
@@ -13,14 +13,36 @@ pub enum Error {
 	Io(io::Error),
 	Corruption(String),
 	InvalidConfiguration(String),
-	IncompatibleColumnConfig { id: ColId, reason: String },
+	IncompatibleColumnConfig {
+		id: ColId,
+		reason: String,
+	},
 	InvalidInput(String),
 	InvalidValueData,
 	Background(Arc<Error>),
 	Locked(io::Error),
+	/// A value table file is already open through another handle, in this or another process.
+	AlreadyOpen(String),
 	Migration(String),
 	Compression,
 	DatabaseNotFound,
+	FormatMismatch(String),
+	ReplaceTombstone(String),
+	/// Growing a file failed because the disk is full.
+	OutOfSpace(String),
+	/// A value is larger than the table it goes to allows. Sizes are in bytes, after compression.
+	ValueTooLarge {
+		max: usize,
+		got: usize,
+	},
+	/// A value table entry referenced by the index holds a different key. `expected` and `found`
+	/// are the key prefixes stored in value table entries.
+	KeyMismatch {
+		table: String,
+		index: u64,
+		expected: Vec<u8>,
+		found: Vec<u8>,
+	},
 }
 
 impl fmt::Display for Error {
@@ -36,9 +58,21 @@ impl fmt::Display for Error {
 			Error::InvalidValueData => write!(f, "Invalid data in value table"),
 			Error::Background(e) => write!(f, "Background worker error: {e}"),
 			Error::Locked(e) => write!(f, "Database file is in use. ({e})"),
+			Error::AlreadyOpen(e) => write!(f, "Table file is already open: {e}"),
 			Error::Migration(e) => write!(f, "Migration error: {e}"),
 			Error::Compression => write!(f, "Compression error"),
 			Error::DatabaseNotFound => write!(f, "Database does not exist"),
+			Error::FormatMismatch(e) => write!(f, "Not a database file: {e}"),
+			Error::ReplaceTombstone(e) => write!(f, "Replacing a removed value: {e}"),
+			Error::OutOfSpace(e) => write!(f, "Out of disk space: {e}"),
+			Error::ValueTooLarge { max, got } =>
+				write!(f, "Value of {got} bytes is larger than the maximum of {max} bytes"),
+			Error::KeyMismatch { table, index, expected, found } => write!(
+				f,
+				"Key mismatch in table {table} at {index}. Expected {}, found {}",
+				hex(expected),
+				hex(found),
+			),
 		}
 	}
 }
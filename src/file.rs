@@ -6,14 +6,44 @@
 use crate::{
 	error::{try_io, Error, Result},
 	parking_lot::{RwLock, RwLockUpgradableReadGuard, RwLockWriteGuard},
-	table::TableId,
+	table::{TableId, TABLE_MAGIC},
 };
 use std::sync::atomic::{AtomicU64, Ordering};
 
+/// Expected order of reads from a value table file, used to advise the kernel on read ahead.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AccessPattern {
+	/// Entries are read by index in no particular order. Read ahead is disabled, so that it does
+	/// not fill the page cache with entries that are never read.
+	#[default]
+	Random,
+	/// Entries are read in order, such as when bulk loading or compacting. Read ahead is enabled.
+	Sequential,
+}
+
+/// How far a value table header write is synced when a log record is enacted. Without a sync,
+/// the header only reaches the device with the next table flush, and until then the log is
+/// needed to recover it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Durability {
+	/// Leave the header to the next table flush.
+	#[default]
+	None,
+	/// Sync the file data, like `fdatasync`. Skipped for files opened with `O_DSYNC`, where the
+	/// write is on the device already.
+	Data,
+	/// Sync the file data and metadata, like `fsync`.
+	Full,
+}
+
 #[cfg(target_os = "linux")]
-fn disable_read_ahead(file: &std::fs::File) -> std::io::Result<()> {
+fn advise_access_pattern(file: &std::fs::File, pattern: AccessPattern) -> std::io::Result<()> {
 	use std::os::unix::io::AsRawFd;
-	let err = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_RANDOM) };
+	let advice = match pattern {
+		AccessPattern::Random => libc::POSIX_FADV_RANDOM,
+		AccessPattern::Sequential => libc::POSIX_FADV_SEQUENTIAL,
+	};
+	let err = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, advice) };
 	if err != 0 {
 		Err(std::io::Error::from_raw_os_error(err))
 	} else {
@@ -22,9 +52,10 @@ fn disable_read_ahead(file: &std::fs::File) -> std::io::Result<()> {
 }
 
 #[cfg(target_os = "macos")]
-fn disable_read_ahead(file: &std::fs::File) -> std::io::Result<()> {
+fn advise_access_pattern(file: &std::fs::File, pattern: AccessPattern) -> std::io::Result<()> {
 	use std::os::unix::io::AsRawFd;
-	if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_RDAHEAD, 0) } != 0 {
+	let read_ahead = (pattern == AccessPattern::Sequential) as libc::c_int;
+	if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_RDAHEAD, read_ahead) } != 0 {
 		Err(std::io::Error::last_os_error())
 	} else {
 		Ok(())
@@ -32,7 +63,142 @@ fn disable_read_ahead(file: &std::fs::File) -> std::io::Result<()> {
 }
 
 #[cfg(not(any(target_os = "macos", target_os = "linux")))]
-fn disable_read_ahead(_file: &std::fs::File) -> std::io::Result<()> {
+fn advise_access_pattern(_file: &std::fs::File, _pattern: AccessPattern) -> std::io::Result<()> {
+	Ok(())
+}
+
+// Switch an open file to `O_DIRECT`. Headers are read and written before this, with buffered I/O.
+#[cfg(all(feature = "direct_io", target_os = "linux"))]
+fn set_direct_io(file: &std::fs::File) -> std::io::Result<()> {
+	use std::os::unix::io::AsRawFd;
+	let fd = file.as_raw_fd();
+	let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+	if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_DIRECT) } != 0 {
+		Err(std::io::Error::last_os_error())
+	} else {
+		Ok(())
+	}
+}
+
+#[cfg(not(all(feature = "direct_io", target_os = "linux")))]
+fn set_direct_io(_file: &std::fs::File) -> std::io::Result<()> {
+	Err(std::io::ErrorKind::Unsupported.into())
+}
+
+// Block size of the file system at `path`, which direct I/O offsets, lengths and buffers are
+// aligned to.
+#[cfg(unix)]
+fn direct_io_block_size(path: &std::path::Path) -> std::io::Result<u64> {
+	use std::os::unix::fs::MetadataExt;
+	Ok(std::fs::metadata(path)?.blksize().max(512))
+}
+
+#[cfg(not(unix))]
+fn direct_io_block_size(_path: &std::path::Path) -> std::io::Result<u64> {
+	Err(std::io::ErrorKind::Unsupported.into())
+}
+
+// Zeroed buffer for direct I/O.
+#[cfg(unix)]
+struct AlignedBuf {
+	ptr: std::ptr::NonNull<u8>,
+	layout: std::alloc::Layout,
+}
+
+#[cfg(unix)]
+impl AlignedBuf {
+	fn zeroed(len: usize, align: usize) -> AlignedBuf {
+		let layout =
+			std::alloc::Layout::from_size_align(len, align).expect("Block size is a power of two");
+		let ptr = unsafe { std::alloc::alloc_zeroed(layout) };
+		let ptr =
+			std::ptr::NonNull::new(ptr).unwrap_or_else(|| std::alloc::handle_alloc_error(layout));
+		AlignedBuf { ptr, layout }
+	}
+}
+
+#[cfg(unix)]
+impl std::ops::Deref for AlignedBuf {
+	type Target = [u8];
+
+	fn deref(&self) -> &[u8] {
+		unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.layout.size()) }
+	}
+}
+
+#[cfg(unix)]
+impl std::ops::DerefMut for AlignedBuf {
+	fn deref_mut(&mut self) -> &mut [u8] {
+		unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.layout.size()) }
+	}
+}
+
+#[cfg(unix)]
+impl Drop for AlignedBuf {
+	fn drop(&mut self) {
+		unsafe { std::alloc::dealloc(self.ptr.as_ptr(), self.layout) }
+	}
+}
+
+#[cfg(unix)]
+fn set_dsync(options: &mut std::fs::OpenOptions) {
+	use std::os::unix::fs::OpenOptionsExt;
+	options.custom_flags(libc::O_DSYNC);
+}
+
+#[cfg(windows)]
+fn set_dsync(options: &mut std::fs::OpenOptions) {
+	use std::os::windows::fs::OpenOptionsExt;
+	const FILE_FLAG_WRITE_THROUGH: u32 = 0x80000000;
+	options.custom_flags(FILE_FLAG_WRITE_THROUGH);
+}
+
+#[cfg(not(any(unix, windows)))]
+fn set_dsync(_options: &mut std::fs::OpenOptions) {}
+
+#[cfg(target_os = "linux")]
+fn advise_will_need(file: &std::fs::File, offset: u64, len: u64) -> std::io::Result<()> {
+	use std::os::unix::io::AsRawFd;
+	let err = unsafe {
+		libc::posix_fadvise(
+			file.as_raw_fd(),
+			offset as libc::off_t,
+			len as libc::off_t,
+			libc::POSIX_FADV_WILLNEED,
+		)
+	};
+	if err != 0 {
+		Err(std::io::Error::from_raw_os_error(err))
+	} else {
+		Ok(())
+	}
+}
+
+#[cfg(not(target_os = "linux"))]
+fn advise_will_need(_file: &std::fs::File, _offset: u64, _len: u64) -> std::io::Result<()> {
+	Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn advise_dont_need(file: &std::fs::File, offset: u64, len: u64) -> std::io::Result<()> {
+	use std::os::unix::io::AsRawFd;
+	let err = unsafe {
+		libc::posix_fadvise(
+			file.as_raw_fd(),
+			offset as libc::off_t,
+			len as libc::off_t,
+			libc::POSIX_FADV_DONTNEED,
+		)
+	};
+	if err != 0 {
+		Err(std::io::Error::from_raw_os_error(err))
+	} else {
+		Ok(())
+	}
+}
+
+#[cfg(not(target_os = "linux"))]
+fn advise_dont_need(_file: &std::fs::File, _offset: u64, _len: u64) -> std::io::Result<()> {
 	Ok(())
 }
 
@@ -58,7 +224,160 @@ fn fsync(file: &std::fs::File) -> std::io::Result<()> {
 	file.sync_data()
 }
 
-const GROW_SIZE_BYTES: u64 = 256 * 1024;
+// Advisory lock that keeps other handles from writing to the same file. Read-only handles share
+// the lock, so that several of them may be open at once.
+#[cfg(unix)]
+fn lock(file: &std::fs::File, id: TableId, shared: bool) -> Result<()> {
+	let result = if shared {
+		fs2::FileExt::try_lock_shared(file)
+	} else {
+		fs2::FileExt::try_lock_exclusive(file)
+	};
+	result.map_err(|e| Error::AlreadyOpen(format!("{id}: {e}")))
+}
+
+// Locks on Windows are mandatory. A locked file can't be read through other handles, which would
+// break `ValueTable::read_header` while the database is open. Table files are not locked there,
+// and only the database lock file keeps a second `Db` from opening the same directory.
+#[cfg(not(unix))]
+fn lock(_file: &std::fs::File, _id: TableId, _shared: bool) -> Result<()> {
+	Ok(())
+}
+
+#[cfg(unix)]
+fn sync_dir(path: &std::path::Path) -> std::io::Result<()> {
+	std::fs::File::open(path)?.sync_all()
+}
+
+// Directories can't be opened as files on other platforms.
+#[cfg(not(unix))]
+fn sync_dir(_path: &std::path::Path) -> std::io::Result<()> {
+	Ok(())
+}
+
+/// I/O priority hint for reads issued by the calling thread.
+///
+/// On Linux this sets the thread I/O priority with `ioprio_set`, which is honored by I/O
+/// schedulers that support priorities (e.g. BFQ). It is a no-op elsewhere.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReadPriority {
+	/// Latency sensitive foreground read.
+	High,
+	/// Leave the thread I/O priority unchanged.
+	#[default]
+	Normal,
+	/// Background read, e.g. a scan or a scrub, that should yield to foreground reads.
+	Low,
+}
+
+impl ReadPriority {
+	#[cfg(target_os = "linux")]
+	fn ioprio(self) -> Option<libc::c_int> {
+		const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+		const IOPRIO_CLASS_BE: libc::c_int = 2;
+		match self {
+			ReadPriority::High => Some(IOPRIO_CLASS_BE << IOPRIO_CLASS_SHIFT),
+			ReadPriority::Normal => None,
+			// Lowest best effort level rather than the idle class, which may starve entirely.
+			ReadPriority::Low => Some((IOPRIO_CLASS_BE << IOPRIO_CLASS_SHIFT) | 7),
+		}
+	}
+
+	#[cfg(not(target_os = "linux"))]
+	fn ioprio(self) -> Option<libc::c_int> {
+		None
+	}
+}
+
+#[cfg(all(target_os = "linux", not(test)))]
+fn ioprio_get() -> std::io::Result<libc::c_int> {
+	const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+	// Process id 0 targets the calling thread.
+	let result = unsafe { libc::syscall(libc::SYS_ioprio_get, IOPRIO_WHO_PROCESS, 0) };
+	if result < 0 {
+		Err(std::io::Error::last_os_error())
+	} else {
+		Ok(result as libc::c_int)
+	}
+}
+
+#[cfg(all(target_os = "linux", not(test)))]
+fn ioprio_set(ioprio: libc::c_int) -> std::io::Result<()> {
+	const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+	if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) } != 0 {
+		Err(std::io::Error::last_os_error())
+	} else {
+		Ok(())
+	}
+}
+
+// Tests record the calls instead of changing the priority of the test threads.
+#[cfg(test)]
+thread_local! {
+	static IOPRIO: std::cell::Cell<libc::c_int> = const { std::cell::Cell::new(0) };
+	static IOPRIO_CALLS: std::cell::RefCell<Vec<libc::c_int>> =
+		const { std::cell::RefCell::new(Vec::new()) };
+}
+
+#[cfg(test)]
+fn ioprio_get() -> std::io::Result<libc::c_int> {
+	Ok(IOPRIO.with(|p| p.get()))
+}
+
+#[cfg(test)]
+fn ioprio_set(ioprio: libc::c_int) -> std::io::Result<()> {
+	IOPRIO.with(|p| p.set(ioprio));
+	IOPRIO_CALLS.with(|c| c.borrow_mut().push(ioprio));
+	Ok(())
+}
+
+/// Returns `ioprio_set` values issued by the calling thread since the last call.
+#[cfg(test)]
+pub fn take_ioprio_calls() -> Vec<libc::c_int> {
+	IOPRIO_CALLS.with(|c| std::mem::take(&mut *c.borrow_mut()))
+}
+
+#[cfg(all(not(target_os = "linux"), not(test)))]
+fn ioprio_get() -> std::io::Result<libc::c_int> {
+	Err(std::io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(all(not(target_os = "linux"), not(test)))]
+fn ioprio_set(_ioprio: libc::c_int) -> std::io::Result<()> {
+	Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// Applies a read priority to the calling thread and restores the previous one when dropped.
+pub struct IoPriorityGuard {
+	previous: Option<libc::c_int>,
+}
+
+impl IoPriorityGuard {
+	pub fn new(priority: ReadPriority) -> Self {
+		let previous = priority.ioprio().and_then(|ioprio| {
+			let previous = ioprio_get().and_then(|previous| ioprio_set(ioprio).map(|_| previous));
+			previous
+				.map_err(
+					|e| log::debug!(target: "parity-db", "Error setting I/O priority: {:?}", e),
+				)
+				.ok()
+		});
+		IoPriorityGuard { previous }
+	}
+}
+
+impl Drop for IoPriorityGuard {
+	fn drop(&mut self) {
+		if let Some(previous) = self.previous {
+			if let Err(e) = ioprio_set(previous) {
+				log::debug!(target: "parity-db", "Error restoring I/O priority: {:?}", e);
+			}
+		}
+	}
+}
+
+/// Default number of bytes a table file grows by when it is full.
+pub const GROW_SIZE_BYTES: u64 = 256 * 1024;
 
 #[derive(Debug)]
 pub struct TableFile {
@@ -66,22 +385,71 @@ pub struct TableFile {
 	pub path: std::path::PathBuf,
 	pub capacity: AtomicU64,
 	pub id: TableId,
+	/// Sync the containing directory when the file is created.
+	pub sync_dir: bool,
+	/// Open the file with `O_DSYNC`, so that each write reaches the device before returning.
+	pub dsync: bool,
+	pub access_pattern: AccessPattern,
+	/// Number of bytes the file grows by when it is full.
+	pub grow_step: u64,
+	/// Sync done by `sync_header`.
+	pub durability: Durability,
+	/// Table format written after the marker when the file is created.
+	pub format: [u8; 8],
+	/// Reads and writes bypass the page cache with `O_DIRECT`. They are done on whole blocks,
+	/// through aligned buffers, and the file length is kept a multiple of the block size.
+	pub direct_io: bool,
+	/// Block size direct reads and writes are aligned to.
+	pub block_size: u64,
+	// Writes of entries that share a block read and write back the whole block.
+	direct_writes: crate::parking_lot::Mutex<()>,
+	/// Reads are copied from a read only memory map of the file. Writes still use `write_at`.
+	pub mmap_reads: bool,
+	// Mapping used with `mmap_reads`. Created on the first read and replaced when a read goes
+	// past its end after the file has grown.
+	map: RwLock<Option<memmap2::Mmap>>,
+	#[cfg(test)]
+	pub dir_syncs: AtomicU64,
+	/// Number of times the file was grown.
+	#[cfg(test)]
+	pub grows: AtomicU64,
+	/// Growing the file fails as if the disk was full.
+	#[cfg(test)]
+	pub fail_grow: std::sync::atomic::AtomicBool,
+	/// Number of `read_at` calls.
+	#[cfg(test)]
+	pub reads: AtomicU64,
+	/// Offset and length of each read ahead hint.
+	#[cfg(test)]
+	pub prefetches: crate::parking_lot::Mutex<Vec<(u64, u64)>>,
+	/// Kind of each sync issued by `sync_header`.
+	#[cfg(test)]
+	pub header_syncs: crate::parking_lot::Mutex<Vec<Durability>>,
+	// Used in tests to simulate lost writes.
+	#[cfg(test)]
+	pub drop_next_write: std::sync::atomic::AtomicBool,
 }
 
 impl TableFile {
-	pub fn open(filepath: std::path::PathBuf, entry_size: u16, id: TableId) -> Result<Self> {
+	pub fn open(
+		filepath: std::path::PathBuf,
+		entry_size: u16,
+		format: [u8; 8],
+		id: TableId,
+		dsync: bool,
+		read_only: bool,
+	) -> Result<Self> {
 		let mut capacity = 0u64;
 		let file = if std::fs::metadata(&filepath).is_ok() {
-			let file = try_io!(std::fs::OpenOptions::new()
-				.read(true)
-				.write(true)
-				.open(filepath.as_path()));
-			try_io!(disable_read_ahead(&file));
+			let mut file = try_io!(Self::open_options(dsync).open(filepath.as_path()));
+			lock(&file, id, read_only)?;
+			try_io!(advise_access_pattern(&file, AccessPattern::Random));
 			let len = try_io!(file.metadata()).len();
 			if len == 0 {
-				// Preallocate.
+				// Preallocate. The file was left empty, so it gets the header of a new file.
 				capacity += GROW_SIZE_BYTES / entry_size as u64;
 				try_io!(file.set_len(capacity * entry_size as u64));
+				Self::write_marker(&mut file, &format)?;
 			} else {
 				capacity = len / entry_size as u64;
 			}
@@ -94,36 +462,278 @@ impl TableFile {
 			file: RwLock::new(file),
 			capacity: AtomicU64::new(capacity),
 			id,
+			sync_dir: false,
+			dsync,
+			access_pattern: AccessPattern::Random,
+			grow_step: GROW_SIZE_BYTES,
+			durability: Durability::None,
+			format,
+			direct_io: false,
+			block_size: 0,
+			direct_writes: Default::default(),
+			mmap_reads: false,
+			map: RwLock::new(None),
+			#[cfg(test)]
+			dir_syncs: AtomicU64::new(0),
+			#[cfg(test)]
+			grows: AtomicU64::new(0),
+			#[cfg(test)]
+			fail_grow: Default::default(),
+			#[cfg(test)]
+			reads: AtomicU64::new(0),
+			#[cfg(test)]
+			prefetches: Default::default(),
+			#[cfg(test)]
+			header_syncs: Default::default(),
+			#[cfg(test)]
+			drop_next_write: Default::default(),
 		})
 	}
 
+	fn open_options(dsync: bool) -> std::fs::OpenOptions {
+		let mut options = std::fs::OpenOptions::new();
+		options.read(true).write(true);
+		if dsync {
+			set_dsync(&mut options);
+		}
+		options
+	}
+
 	fn create_file(&self) -> Result<std::fs::File> {
 		log::debug!(target: "parity-db", "Created value table {}", self.id);
-		let file = try_io!(std::fs::OpenOptions::new()
-			.create(true)
-			.read(true)
-			.write(true)
-			.open(self.path.as_path()));
-		try_io!(disable_read_ahead(&file));
+		let mut file =
+			try_io!(Self::open_options(self.dsync).create(true).open(self.path.as_path()));
+		lock(&file, self.id, false)?;
+		try_io!(advise_access_pattern(&file, self.access_pattern));
+		Self::write_marker(&mut file, &self.format)?;
+		if self.direct_io {
+			try_io!(file.set_len(self.block_size));
+			try_io!(set_direct_io(&file));
+		}
+		if self.sync_dir {
+			// Make sure the file still exists after a crash.
+			if let Some(dir) = self.path.parent() {
+				try_io!(sync_dir(dir));
+				#[cfg(test)]
+				self.dir_syncs.fetch_add(1, Ordering::Relaxed);
+			}
+		}
 		Ok(file)
 	}
 
+	// Value table header is 16 bytes, followed by the marker and the table format.
+	fn write_marker(file: &mut std::fs::File, format: &[u8; 8]) -> Result<()> {
+		use std::io::{Seek, SeekFrom, Write};
+		try_io!(file.seek(SeekFrom::Start(16)));
+		try_io!(file.write_all(TABLE_MAGIC));
+		try_io!(file.write_all(format));
+		Ok(())
+	}
+
+	/// Change the read ahead advice for the file, now and when it is created.
+	pub fn set_access_pattern(&mut self, pattern: AccessPattern) -> Result<()> {
+		if pattern != self.access_pattern {
+			if let Some(file) = self.file.read().as_ref() {
+				try_io!(advise_access_pattern(file, pattern));
+			}
+			self.access_pattern = pattern;
+		}
+		Ok(())
+	}
+
+	/// Use direct I/O for the file, now and when it is created. Requires the `direct_io` feature
+	/// and Linux.
+	pub fn enable_direct_io(&mut self) -> Result<()> {
+		if self.direct_io {
+			return Ok(())
+		}
+		if !cfg!(all(feature = "direct_io", target_os = "linux")) {
+			return Err(Error::InvalidConfiguration(
+				"Direct I/O requires the `direct_io` feature on Linux".into(),
+			))
+		}
+		let dir = self.path.parent().unwrap_or(std::path::Path::new("."));
+		self.block_size = try_io!(direct_io_block_size(dir));
+		if let Some(file) = self.file.read().as_ref() {
+			let len = try_io!(file.metadata()).len();
+			if len % self.block_size != 0 {
+				try_io!(file.set_len(self.aligned_len(len)));
+			}
+			try_io!(set_direct_io(file));
+		}
+		self.direct_io = true;
+		Ok(())
+	}
+
+	/// Read entries from a memory map of the file instead of with `pread`. Not supported with
+	/// direct I/O.
+	pub fn enable_mmap_reads(&mut self) -> Result<()> {
+		if self.direct_io {
+			return Err(Error::InvalidConfiguration(
+				"Memory mapped reads can't be used with direct I/O".into(),
+			))
+		}
+		self.mmap_reads = true;
+		Ok(())
+	}
+
+	// Copy `buf.len()` bytes at `offset` from the mapping, remapping the file if it has grown.
+	// Returns `false` if the file is shorter, in which case the read is left to `pread`.
+	fn read_mapped(&self, buf: &mut [u8], offset: u64) -> Result<bool> {
+		let end = offset + buf.len() as u64;
+		let copy = |map: &memmap2::Mmap, buf: &mut [u8]| {
+			buf.copy_from_slice(&map[offset as usize..end as usize])
+		};
+		if let Some(map) = self.map.read().as_ref().filter(|map| end <= map.len() as u64) {
+			copy(map, buf);
+			return Ok(true)
+		}
+		let file = self.file.read();
+		let Some(file) = file.as_ref() else { return Ok(false) };
+		let mut map = self.map.write();
+		if map.as_ref().is_none_or(|map| end > map.len() as u64) {
+			if end > try_io!(file.metadata()).len() {
+				return Ok(false)
+			}
+			// The file only grows while it is mapped, see `truncate` and `remove`.
+			*map = Some(try_io!(unsafe { memmap2::Mmap::map(file) }));
+		}
+		copy(map.as_ref().expect("Mapped above"), buf);
+		Ok(true)
+	}
+
+	/// Length of the mapping used with `mmap_reads`, if the file is mapped.
+	#[cfg(test)]
+	pub fn mapped_len(&self) -> Option<usize> {
+		self.map.read().as_ref().map(|map| map.len())
+	}
+
+	// File length for `len` bytes of entries.
+	fn aligned_len(&self, len: u64) -> u64 {
+		if self.block_size == 0 {
+			len
+		} else {
+			len.div_ceil(self.block_size) * self.block_size
+		}
+	}
+
+	// Read the blocks covering `len` bytes at `offset`. Blocks past the end of the file are left
+	// zeroed. Returns the buffer, the position of `offset` in it and the number of bytes read.
+	#[cfg(unix)]
+	fn read_blocks(
+		&self,
+		file: &std::fs::File,
+		offset: u64,
+		len: usize,
+		skip_read: bool,
+	) -> Result<(AlignedBuf, usize, usize)> {
+		use std::os::unix::fs::FileExt;
+		let start = offset / self.block_size * self.block_size;
+		let end = (offset + len as u64).div_ceil(self.block_size) * self.block_size;
+		let mut buf = AlignedBuf::zeroed((end - start) as usize, self.block_size as usize);
+		let mut read = 0;
+		while !skip_read && read < buf.len() {
+			match file.read_at(&mut buf[read..], start + read as u64) {
+				Ok(0) => break,
+				Ok(n) => read += n,
+				Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => {},
+				Err(e) => return Err(Error::Io(e)),
+			}
+			if read % self.block_size as usize != 0 {
+				// End of file.
+				break
+			}
+		}
+		Ok((buf, (offset - start) as usize, read))
+	}
+
+	#[cfg(unix)]
+	fn read_direct(&self, file: &std::fs::File, buf: &mut [u8], offset: u64) -> Result<()> {
+		let (blocks, pos, read) = self.read_blocks(file, offset, buf.len(), false)?;
+		if read < pos + buf.len() {
+			return Err(Error::Io(std::io::Error::new(
+				std::io::ErrorKind::UnexpectedEof,
+				"failed to fill whole buffer",
+			)))
+		}
+		buf.copy_from_slice(&blocks[pos..pos + buf.len()]);
+		Ok(())
+	}
+
+	#[cfg(unix)]
+	fn write_direct(&self, file: &std::fs::File, buf: &[u8], offset: u64) -> Result<()> {
+		use std::os::unix::fs::FileExt;
+		let _lock = self.direct_writes.lock();
+		// Blocks that are written in full don't need to be read first.
+		let whole_blocks = offset.is_multiple_of(self.block_size) &&
+			(buf.len() as u64).is_multiple_of(self.block_size);
+		let (mut blocks, pos, _) = self.read_blocks(file, offset, buf.len(), whole_blocks)?;
+		blocks[pos..pos + buf.len()].copy_from_slice(buf);
+		try_io!(file.write_all_at(&blocks, offset - pos as u64));
+		Ok(())
+	}
+
+	/// Hint that the given range is going to be read soon.
+	pub fn prefetch(&self, offset: u64, len: u64) -> Result<()> {
+		if let Some(file) = self.file.read().as_ref() {
+			#[cfg(test)]
+			self.prefetches.lock().push((offset, len));
+			try_io!(advise_will_need(file, offset, len));
+		}
+		Ok(())
+	}
+
+	/// Read from the device instead of the page cache. The range must be synced already, as only
+	/// clean pages are dropped from the cache. Memory mapped reads are not used. On platforms
+	/// other than Linux this is a regular read.
+	#[cfg(unix)]
+	pub fn read_uncached(&self, buf: &mut [u8], offset: u64) -> Result<()> {
+		use std::os::unix::fs::FileExt;
+		let file = self.file.read();
+		let file = file.as_ref().ok_or_else(|| Error::Corruption("File does not exist.".into()))?;
+		if self.direct_io {
+			return self.read_direct(file, buf, offset)
+		}
+		try_io!(advise_dont_need(file, offset, buf.len() as u64));
+		try_io!(file.read_exact_at(buf, offset));
+		Ok(())
+	}
+
+	#[cfg(windows)]
+	pub fn read_uncached(&self, buf: &mut [u8], offset: u64) -> Result<()> {
+		self.read_at(buf, offset)
+	}
+
 	#[cfg(unix)]
 	pub fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<()> {
 		use std::os::unix::fs::FileExt;
-		try_io!(self
-			.file
-			.read()
-			.as_ref()
-			.ok_or_else(|| Error::Corruption("File does not exist.".into()))?
-			.read_exact_at(buf, offset));
+		#[cfg(test)]
+		self.reads.fetch_add(1, Ordering::Relaxed);
+		if self.mmap_reads && self.read_mapped(buf, offset)? {
+			return Ok(())
+		}
+		let file = self.file.read();
+		let file = file.as_ref().ok_or_else(|| Error::Corruption("File does not exist.".into()))?;
+		if self.direct_io {
+			return self.read_direct(file, buf, offset)
+		}
+		try_io!(file.read_exact_at(buf, offset));
 		Ok(())
 	}
 
 	#[cfg(unix)]
 	pub fn write_at(&self, buf: &[u8], offset: u64) -> Result<()> {
 		use std::os::unix::fs::FileExt;
-		try_io!(self.file.read().as_ref().unwrap().write_all_at(buf, offset));
+		#[cfg(test)]
+		if self.drop_next_write.swap(false, Ordering::Relaxed) {
+			return Ok(())
+		}
+		let file = self.file.read();
+		let file = file.as_ref().unwrap();
+		if self.direct_io {
+			return self.write_direct(file, buf, offset)
+		}
+		try_io!(file.write_all_at(buf, offset));
 		Ok(())
 	}
 
@@ -132,6 +742,11 @@ impl TableFile {
 		use crate::error::Error;
 		use std::{io, os::windows::fs::FileExt};
 
+		#[cfg(test)]
+		self.reads.fetch_add(1, Ordering::Relaxed);
+		if self.mmap_reads && self.read_mapped(buf, offset)? {
+			return Ok(())
+		}
 		let file = self.file.read();
 		let file = file.as_ref().ok_or_else(|| Error::Corruption("File does not exist.".into()))?;
 
@@ -163,6 +778,10 @@ impl TableFile {
 	pub fn write_at(&self, mut buf: &[u8], mut offset: u64) -> Result<()> {
 		use crate::error::Error;
 		use std::{io, os::windows::fs::FileExt};
+		#[cfg(test)]
+		if self.drop_next_write.swap(false, Ordering::Relaxed) {
+			return Ok(())
+		}
 
 		let file = self.file.read();
 		let file = file.as_ref().unwrap();
@@ -188,21 +807,53 @@ impl TableFile {
 		Ok(())
 	}
 
+	/// Number of entries the file grows by when it is full. At least one.
+	pub fn grow_entries(&self, entry_size: u16) -> u64 {
+		(self.grow_step / entry_size as u64).max(1)
+	}
+
 	pub fn grow(&self, entry_size: u16) -> Result<()> {
-		let mut capacity = self.capacity.load(Ordering::Relaxed);
-		capacity += GROW_SIZE_BYTES / entry_size as u64;
+		let capacity = self.capacity.load(Ordering::Relaxed) + self.grow_entries(entry_size);
+		self.resize(capacity, entry_size)
+	}
 
-		self.capacity.store(capacity, Ordering::Relaxed);
+	fn resize(&self, capacity: u64, entry_size: u16) -> Result<()> {
+		// Resizes are serialized by the upgradable lock. The capacity is only updated once the file
+		// has been resized, so that a failed resize leaves it unchanged.
 		let mut file = self.file.upgradable_read();
+		if capacity <= self.capacity.load(Ordering::Relaxed) {
+			// Grown by another thread already.
+			return Ok(())
+		}
+		#[cfg(test)]
+		self.grows.fetch_add(1, Ordering::Relaxed);
 		if file.is_none() {
 			let mut wfile = RwLockUpgradableReadGuard::upgrade(file);
 			*wfile = Some(self.create_file()?);
 			file = RwLockWriteGuard::downgrade_to_upgradable(wfile);
 		}
-		try_io!(file.as_ref().unwrap().set_len(capacity * entry_size as u64));
+		let file = file.as_ref().unwrap();
+		match self.grow_file(file, self.aligned_len(capacity * entry_size as u64)) {
+			Ok(()) => (),
+			Err(e) if e.kind() == std::io::ErrorKind::StorageFull =>
+				return Err(Error::OutOfSpace(format!(
+					"{}: Can't grow the file to {} entries",
+					self.id, capacity
+				))),
+			Err(e) => return Err(Error::Io(e)),
+		}
+		self.capacity.store(capacity, Ordering::Relaxed);
 		Ok(())
 	}
 
+	fn grow_file(&self, file: &std::fs::File, len: u64) -> std::io::Result<()> {
+		#[cfg(test)]
+		if self.fail_grow.load(Ordering::Relaxed) {
+			return Err(std::io::ErrorKind::StorageFull.into())
+		}
+		file.set_len(len)
+	}
+
 	pub fn flush(&self) -> Result<()> {
 		if let Some(file) = self.file.read().as_ref() {
 			try_io!(fsync(file));
@@ -210,8 +861,28 @@ impl TableFile {
 		Ok(())
 	}
 
+	/// Sync the file after a header write, as set by `durability`.
+	pub fn sync_header(&self) -> Result<()> {
+		let durability = match self.durability {
+			Durability::Data if self.dsync => return Ok(()),
+			Durability::None => return Ok(()),
+			durability => durability,
+		};
+		if let Some(file) = self.file.read().as_ref() {
+			if durability == Durability::Full {
+				try_io!(file.sync_all());
+			} else {
+				try_io!(fsync(file));
+			}
+			#[cfg(test)]
+			self.header_syncs.lock().push(durability);
+		}
+		Ok(())
+	}
+
 	pub fn remove(&self) -> Result<()> {
 		let mut file = self.file.write();
+		self.map.write().take();
 		if let Some(file) = file.take() {
 			drop(file);
 			try_io!(std::fs::remove_file(&self.path));
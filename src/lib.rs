@@ -8,6 +8,7 @@ mod column;
 mod compress;
 mod db;
 mod display;
+mod encrypt;
 mod error;
 mod file;
 mod hash;
@@ -20,15 +21,18 @@ mod stats;
 mod table;
 
 pub use btree::BTreeIterator;
-pub use column::{ColId, ValueIterState};
+pub use column::{ColId, CorruptedValueInfo, ValueIterState};
 pub use compress::CompressionType;
 pub use db::{check::CheckOptions, Db, Operation, Value};
+pub use encrypt::EncryptionOptions;
 #[cfg(feature = "instrumentation")]
 pub use error::set_number_of_allowed_io_operations;
 pub use error::{Error, Result};
-pub use migration::{clear_column, migrate};
+pub use file::{AccessPattern, Durability, ReadPriority};
+pub use migration::{clear_column, migrate, migrate_with_options, MigrationOptions};
 pub use options::{ColumnOptions, Options};
 pub use stats::{ColumnStatSummary, StatSummary};
+pub use table::{read_table_header, table_inventory, TableHeader, TableStats};
 
 pub const KEY_SIZE: usize = 32;
 pub type Key = [u8; KEY_SIZE];
@@ -66,6 +66,16 @@ impl LogOverlays {
 	pub fn last_record_id(&self, col: ColId) -> u64 {
 		self.last_record_id.get(&col).cloned().unwrap_or(u64::MAX)
 	}
+
+	#[cfg(test)]
+	pub fn value_overlay(&self, table: ValueTableId) -> Option<&ValueLogOverlay> {
+		self.value.get(&table)
+	}
+
+	/// Bytes of entry data held by the largest value table overlay.
+	pub fn max_value_bytes(&self) -> usize {
+		self.value.values().map(|o| o.bytes).max().unwrap_or(0)
+	}
 }
 
 impl LogQuery for RwLock<LogOverlays> {
@@ -448,6 +458,8 @@ pub struct IndexLogOverlay {
 #[derive(Debug, Default)]
 pub struct ValueLogOverlay {
 	pub map: HashMap<u64, (u64, Vec<u8>), BuildIdHash>, // index -> (record_id, entry)
+	// Total size of the entries in `map`. Only maintained for `LogOverlays`.
+	bytes: usize,
 }
 
 #[derive(Debug)]
@@ -621,7 +633,13 @@ impl Log {
 		for (id, overlay) in values.into_iter() {
 			total_value += overlay.map.len();
 			overlays.last_record_id.insert(id.col(), record_id);
-			overlays.value.entry(id).or_default().map.extend(overlay.map.into_iter());
+			let target = overlays.value.entry(id).or_default();
+			for (index, (record_id, data)) in overlay.map.into_iter() {
+				target.bytes += data.len();
+				if let Some((_, replaced)) = target.map.insert(index, (record_id, data)) {
+					target.bytes -= replaced.len();
+				}
+			}
 		}
 
 		log::debug!(
@@ -650,17 +668,26 @@ impl Log {
 				}
 			}
 		}
+		let mut drained = Vec::new();
 		for (table, index) in cleared.values.into_iter() {
 			if let Some(ref mut overlay) = overlays.value.get_mut(&table) {
 				if let std::collections::hash_map::Entry::Occupied(e) = overlay.map.entry(index) {
 					if e.get().0 == record_id {
-						e.remove_entry();
+						let (_, (_, data)) = e.remove_entry();
+						overlay.bytes -= data.len();
+						if overlay.map.is_empty() {
+							drained.push(table);
+						}
 					}
 				}
 			}
 		}
 		// Cleanup index overlays
 		overlays.index.retain(|_, overlay| !overlay.map.is_empty());
+		drop(overlays);
+		for table in drained {
+			self.compact_overlay(table);
+		}
 	}
 
 	pub fn flush_one(&self, min_size: u64) -> Result<bool> {
@@ -761,6 +788,27 @@ impl Log {
 		&self.overlays
 	}
 
+	/// Release memory held by the value overlay of `table`. Entries are keyed by slot, so a later
+	/// write to a slot already replaces the earlier one, and enacted entries are removed by
+	/// `end_read`. The map keeps its peak capacity though. The overlay is dropped if it is empty
+	/// and shrunk otherwise. Returns the number of entries kept. Called by `end_read` when the
+	/// overlay of a table is drained.
+	pub fn compact_overlay(&self, table: ValueTableId) -> usize {
+		let mut overlays = self.overlays.write();
+		let kept = match overlays.value.get_mut(&table) {
+			Some(overlay) => {
+				overlay.map.shrink_to_fit();
+				overlay.map.len()
+			},
+			None => return 0,
+		};
+		if kept == 0 {
+			overlays.value.remove(&table);
+		}
+		log::debug!(target: "parity-db", "{}: Compacted overlay, {} entries", table, kept);
+		kept
+	}
+
 	pub fn has_log_files_to_read(&self) -> bool {
 		self.read_queue.read().len() > 0
 	}
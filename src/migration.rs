@@ -14,6 +14,14 @@ use std::path::{Path, PathBuf};
 const COMMIT_SIZE: usize = 10240;
 const OVERWRITE_TMP_PATH: &str = "to_revert_overwrite";
 
+/// Settings for [`migrate_with_options`].
+#[derive(Clone, Debug, Default)]
+pub struct MigrationOptions {
+	/// Insert values in key order rather than in source index order, so that migrating the same
+	/// data always produces the same value table layout.
+	pub deterministic: bool,
+}
+
 /// Attempt to migrate a database to a new configuration with different column settings.
 /// `from` Source database path
 /// `to` New database configuration.
@@ -22,7 +30,18 @@ const OVERWRITE_TMP_PATH: &str = "to_revert_overwrite";
 /// `force_migrate` Force column re-population even if its setting did not change.
 ///
 /// Note that migration between hash to btree columns is not possible.
-pub fn migrate(from: &Path, mut to: Options, overwrite: bool, force_migrate: &[u8]) -> Result<()> {
+pub fn migrate(from: &Path, to: Options, overwrite: bool, force_migrate: &[u8]) -> Result<()> {
+	migrate_with_options(from, to, overwrite, force_migrate, &Default::default())
+}
+
+/// Same as [`migrate`], with additional settings.
+pub fn migrate_with_options(
+	from: &Path,
+	mut to: Options,
+	overwrite: bool,
+	force_migrate: &[u8],
+	options: &MigrationOptions,
+) -> Result<()> {
 	let source_meta = Options::load_metadata(from)?
 		.ok_or_else(|| Error::Migration("Error loading source metadata".into()))?;
 
@@ -76,9 +95,8 @@ pub fn migrate(from: &Path, mut to: Options, overwrite: bool, force_migrate: &[u
 			continue
 		}
 		log::info!("Migrating col {}", c);
-		source.iter_column_index_while(
-			c,
-			|IterState { item_index: index, key, rc, mut value, .. }| {
+		{
+			let mut add = |IterState { item_index: index, key, rc, mut value, .. }| {
 				//TODO: more efficient ref migration
 				for _ in 0..rc {
 					let value = std::mem::take(&mut value);
@@ -104,8 +122,25 @@ pub fn migrate(from: &Path, mut to: Options, overwrite: bool, force_migrate: &[u
 					}
 				}
 				true
-			},
-		)?;
+			};
+			// Index chunks are ordered by key prefix, so sorting each chunk by key is enough to
+			// insert in key order.
+			let mut chunk: Vec<IterState> = Vec::new();
+			let mut completed = true;
+			source.iter_column_index_while(c, |state| {
+				if !options.deterministic {
+					return add(state)
+				}
+				if chunk.first().is_some_and(|s| s.item_index != state.item_index) {
+					completed = add_sorted(&mut chunk, &mut add);
+				}
+				chunk.push(state);
+				completed
+			})?;
+			if completed {
+				add_sorted(&mut chunk, &mut add);
+			}
+		}
 		if overwrite {
 			dest.commit_raw(commit)?;
 			commit = Default::default();
@@ -171,6 +206,7 @@ pub fn clear_column(path: &Path, column: ColId) -> Result<()> {
 	let _db = Db::open(&options)?;
 	drop(_db);
 
+	crate::table::TableManifest::invalidate(path)?;
 	// It is not specified how read_dir behaves when deleting and iterating in the same loop
 	// We collect a list of paths to be deleted first.
 	let mut to_delete = Vec::new();
@@ -193,6 +229,11 @@ pub fn clear_column(path: &Path, column: ColId) -> Result<()> {
 	Ok(())
 }
 
+fn add_sorted(chunk: &mut Vec<IterState>, add: impl FnMut(IterState) -> bool) -> bool {
+	chunk.sort_by_key(|s| s.key);
+	chunk.drain(..).all(add)
+}
+
 fn move_column(c: ColId, from: &Path, to: &Path) -> Result<()> {
 	deplace_column(c, from, to, false)
 }
@@ -202,6 +243,8 @@ fn copy_column(c: ColId, from: &Path, to: &Path) -> Result<()> {
 }
 
 fn deplace_column(c: ColId, from: &Path, to: &Path, copy: bool) -> Result<()> {
+	crate::table::TableManifest::invalidate(from)?;
+	crate::table::TableManifest::invalidate(to)?;
 	for entry in try_io!(std::fs::read_dir(from)) {
 		let entry = try_io!(entry);
 		if let Some(file) = entry.path().file_name().and_then(|f| f.to_str()) {
@@ -244,6 +287,67 @@ mod test {
 		assert_eq!(dest.get(0, b"1").unwrap(), Some("value".as_bytes().to_vec()));
 	}
 
+	#[test]
+	fn migrate_deterministic() {
+		let dir = tempdir().unwrap();
+		let value = |i: u32| vec![i as u8; 10 + (i as usize % 7) * 100];
+		let mut source_options = Vec::new();
+		for (n, reverse) in [false, true].into_iter().enumerate() {
+			let mut options = Options::with_columns(&dir.path().join(format!("source{n}")), 1);
+			options.salt = Some([1; 32]);
+			options.columns[0].ref_counted = true;
+			options.columns[0].preimage = true;
+			let db = Db::open_or_create(&options).unwrap();
+			// Enough keys for some of them to share an index chunk.
+			let mut keys: Vec<u32> = (0..2000).collect();
+			if reverse {
+				keys.reverse();
+				// Removed entries leave holes in the source tables.
+				db.commit((10000..10010u32).map(|i| (0, i.to_le_bytes().to_vec(), Some(value(i)))))
+					.unwrap();
+				db.commit((10000..10010u32).map(|i| (0, i.to_le_bytes().to_vec(), None)))
+					.unwrap();
+			}
+			for chunk in keys.chunks(300) {
+				db.commit(chunk.iter().map(|i| (0, i.to_le_bytes().to_vec(), Some(value(*i)))))
+					.unwrap();
+			}
+			db.commit([(0, 5u32.to_le_bytes().to_vec(), Some(value(5)))]).unwrap();
+			source_options.push(options);
+		}
+
+		let mut tables = Vec::new();
+		for (n, source) in source_options.iter().enumerate() {
+			let dest_dir = dir.path().join(format!("dest{n}"));
+			let mut dest_opts = Options::with_columns(&dest_dir, 1);
+			dest_opts.columns = source.columns.clone();
+			let options = migration::MigrationOptions { deterministic: true };
+			migration::migrate_with_options(&source.path, dest_opts, false, &[0], &options)
+				.unwrap();
+
+			let mut files = std::collections::BTreeMap::new();
+			for entry in std::fs::read_dir(&dest_dir).unwrap() {
+				let entry = entry.unwrap();
+				let name = entry.file_name().into_string().unwrap();
+				if name.starts_with("index_00") || name.starts_with("table_00") {
+					files.insert(name, std::fs::read(entry.path()).unwrap());
+				}
+			}
+			assert!(files.keys().any(|name| name.starts_with("table_00")));
+			tables.push(files);
+		}
+		assert!(tables[0] == tables[1]);
+
+		let mut options = Options::with_columns(&dir.path().join("dest0"), 1);
+		options.columns = source_options[0].columns.clone();
+		let db = Db::open(&options).unwrap();
+		for i in 0..2000u32 {
+			assert_eq!(db.get(0, &i.to_le_bytes()).unwrap(), Some(value(i)));
+		}
+		db.commit([(0, 5u32.to_le_bytes().to_vec(), None)]).unwrap();
+		assert_eq!(db.get(0, &5u32.to_le_bytes()).unwrap(), Some(value(5)));
+	}
+
 	#[test]
 	fn clear_column() {
 		let source_dir = tempdir().unwrap();
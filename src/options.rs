@@ -4,12 +4,15 @@
 use crate::{
 	column::{ColId, Salt},
 	compress::CompressionType,
+	encrypt::{EncryptionOptions, KEY_CHECK_SIZE},
 	error::{try_io, Error, Result},
+	file::{AccessPattern, Durability},
+	table::{MAX_ENTRY_SIZE, MIN_ENTRY_SIZE, MULTIPART_ENTRY_SIZE},
 };
 use rand::Rng;
 use std::{collections::HashMap, path::Path};
 
-pub const CURRENT_VERSION: u32 = 8;
+pub const CURRENT_VERSION: u32 = 9;
 // TODO on last supported 5, remove MULTIHEAD_V4 and MULTIPART_V4
 // TODO on last supported 8, remove XOR with salt in column::hash
 const LAST_SUPPORTED_VERSION: u32 = 4;
@@ -26,8 +29,8 @@ pub struct Options {
 	/// fsync WAL to disk before committing any changes. Provides extra consistency
 	/// guarantees. On by default.
 	pub sync_wal: bool,
-	/// fsync/msync data to disk before removing logs. Provides crash resistance guarantee.
-	/// On by default.
+	/// fsync/msync data to disk before removing logs, and fsync the database directory when a new
+	/// value table file is created. Provides crash resistance guarantee. On by default.
 	pub sync_data: bool,
 	/// Collect database statistics. May have effect on performance.
 	pub stats: bool,
@@ -38,6 +41,106 @@ pub struct Options {
 	///
 	/// Optional. A sensible default is used if nothing is set for a given column.
 	pub compression_threshold: HashMap<ColId, u32>,
+	/// Encrypt values of a column. Encrypted columns and a fingerprint of their keys are saved in
+	/// the metadata, and opening with a different key or without encryption for such a column
+	/// fails with `Error::IncompatibleColumnConfig`.
+	pub encryption: HashMap<ColId, EncryptionOptions>,
+	/// Size thread local value read buffers to the value table entry instead of the maximum
+	/// entry size. Reduces memory use and per read overhead for threads doing many queries. Off
+	/// by default.
+	pub reuse_read_buffers: bool,
+	/// Open value table files with `O_DSYNC`, so that writes done while enacting the log are on
+	/// the device once they return, instead of being flushed at the end of the enact. Trades
+	/// write throughput for a smaller window of unflushed data. Off by default.
+	pub dsync_writes: bool,
+	/// Number of value table entries to hint for read ahead per column, when reading each part
+	/// of a value that spans multiple entries. Parts are linked, so only the location of the next
+	/// part is known in advance. The entries that follow it are hinted as well, which helps when
+	/// parts were allocated next to each other.
+	///
+	/// Optional. Disabled for columns that are not set.
+	pub prefetch_depth: HashMap<ColId, u32>,
+	/// Number of value table entries to keep hinted for read ahead in front of the cursor per
+	/// column, when iterating a column's value tables. Hints are issued for half of this window at
+	/// a time. Useful on devices where the kernel read ahead does not keep up with scans.
+	///
+	/// Optional. Disabled for columns that are not set.
+	pub iter_read_ahead: HashMap<ColId, u32>,
+	/// Number of bytes a value table file of a column grows by when it is full. Larger steps mean
+	/// fewer file resizes under write heavy loads, at the cost of more preallocated space.
+	///
+	/// Optional. 256 KiB for columns that are not set.
+	pub grow_step_bytes: HashMap<ColId, u64>,
+	/// Read ahead advice for the value table files of a column. Tables are read randomly by index
+	/// during normal operation. `Sequential` suits columns that are bulk loaded or scanned.
+	///
+	/// Optional. `Random` for columns that are not set.
+	pub access_pattern: HashMap<ColId, AccessPattern>,
+	/// Sync value table files of a column each time a table header is written while enacting the
+	/// log, so that the header no longer depends on the log once the record is enacted. With
+	/// `value_write_barrier` the value tables are synced for each record already, and only `Full`
+	/// adds a sync.
+	///
+	/// Optional. `None` for columns that are not set.
+	pub header_durability: HashMap<ColId, Durability>,
+	/// Maximum number of entries a value may span per column. Writing a larger value fails
+	/// instead of creating a long chain of parts that all have to be read on each query.
+	///
+	/// Optional. Values are not limited for columns that are not set.
+	pub max_chain_depth: HashMap<ColId, u32>,
+	/// Maximum length of a value in bytes per column, after compression. Writing a larger value
+	/// fails with `Error::ValueTooLarge`.
+	///
+	/// Optional. Values are not limited for columns that are not set.
+	pub max_value_len: HashMap<ColId, usize>,
+	/// Debug option. Number of value table allocations a freed entry is kept out of reuse per
+	/// column. Reads through a stale address then find a removed entry instead of an unrelated
+	/// value written later, which makes such bugs visible. Entries still held back on shutdown
+	/// are not reused again.
+	///
+	/// Optional. Freed entries are reused right away for columns that are not set.
+	pub slot_reuse_grace: HashMap<ColId, u64>,
+	/// After each log record is enacted, sync the data to disk, read back all written value
+	/// entries and compare them with the log. The record is not considered enacted on mismatch.
+	/// On Linux the entries are dropped from the page cache before reading, so that they are read
+	/// from the device. Elsewhere the page cache may serve the reads. This is expensive. Off by
+	/// default.
+	pub verify_writes: bool,
+	/// Enact each log record in two steps: first write and sync the value tables, then write the
+	/// index. Indexes on disk then never reference values that are not yet durable, without
+	/// relying on the log. Adds a sync per record. Off by default.
+	pub value_write_barrier: bool,
+	/// Save the headers of all value tables on clean shutdown, and open tables from them on the
+	/// next start instead of reading and validating each table header. The saved headers are
+	/// discarded when the database was not shut down cleanly, or was last opened for writing
+	/// without this option. Off by default.
+	pub trusted_open: bool,
+	/// Walk the removed entries list of every value table after the log is replayed on open, and
+	/// fail with `Error::Corruption` if a list links past the end of its table or loops. Takes a
+	/// read per removed entry. Off by default.
+	pub validate_free_lists_on_open: bool,
+	/// Replacing a value at an address that was freed inserts the value at a new address instead
+	/// of failing with `Error::ReplaceTombstone`. Off by default.
+	pub replace_freed_as_insert: bool,
+	/// Write the value table entries of each log record on several threads, one table per thread
+	/// at a time. Writes to the same table keep their log order. The entries of a record are read
+	/// into memory before they are written. Off by default.
+	pub parallel_enact: bool,
+	/// Maximum number of bytes of logged value table entries kept in memory per value table until
+	/// they are enacted. When a commit takes a table over this, the log is flushed and the oldest
+	/// records are enacted right away, before the next commit is processed. Enacting is skipped
+	/// while writes are paused.
+	///
+	/// Optional. Not limited if not set.
+	pub max_log_overlay_bytes: Option<u64>,
+	/// Read and write value table files with `O_DIRECT`, bypassing the page cache. Meant for
+	/// applications that do their own caching. Entries are read and written as whole file system
+	/// blocks. Requires the `direct_io` feature and Linux. Off by default.
+	pub direct_io: bool,
+	/// Read value table files through a read only memory map instead of a `pread` call per
+	/// entry. Best suited to tables that fit in memory. Can't be used with `direct_io`. Off by
+	/// default.
+	pub mmap_reads: bool,
 	#[cfg(any(test, feature = "instrumentation"))]
 	/// Always starts background threads.
 	pub with_background_thread: bool,
@@ -65,6 +168,33 @@ pub struct ColumnOptions {
 	/// Column is configured to use Btree storage. Btree columns allow for ordered key iteration
 	/// and key retrieval, but are significantly less performant and require more disk space.
 	pub btree_index: bool,
+	/// Keep a compact list of free value slots next to each value table. Allows counting and
+	/// listing free slots without walking the removed entries chain. The list is saved on clean
+	/// shutdown and rebuilt from the value table otherwise.
+	pub compact_free_list: bool,
+	/// Keep the keys of all values in a separate file next to the index. Key iteration then reads
+	/// this compact list instead of the front of every value entry. Makes inserts and removals
+	/// more expensive. The file is saved on clean shutdown and rebuilt from the index otherwise.
+	/// Only used for columns with `btree_index` set to `false`.
+	pub key_sidecar: bool,
+	/// Keep a CRC-32 of every value table entry in a file next to the table, and verify entries
+	/// when they are read. Damaged entries are reported as corruption instead of being treated
+	/// as missing values. Makes each enacted write and each read slightly more expensive.
+	pub entry_checksums: bool,
+	/// Values are never removed. Removing a value, or an update that would free a value table
+	/// entry, fails with `Error::InvalidInput`. New values are always appended at the end of the
+	/// value tables, without consulting the removed entries list. Not supported with
+	/// `ref_counted` or `btree_index`, which remove values implicitly.
+	pub append_only: bool,
+	/// Overwrite the whole value table entry with zeroes when a value is removed, instead of only
+	/// its first bytes. Old value bytes are then not left on disk until the entry is reused. Makes
+	/// removals write a full entry to the log.
+	pub secure_erase: bool,
+	/// Entry size of the last value table, which stores values too large for the other tables
+	/// as chains of entries. Larger entries need fewer reads for large values but waste more
+	/// space in the last entry of each chain. Must be between 32 and 32760 bytes. Defaults to
+	/// 4096.
+	pub multipart_entry_size: u16,
 }
 
 /// Database metadata.
@@ -76,13 +206,25 @@ pub struct Metadata {
 	pub version: u32,
 	/// Column metadata.
 	pub columns: Vec<ColumnOptions>,
+	/// Key fingerprints of encrypted columns.
+	pub encryption: HashMap<ColId, [u8; KEY_CHECK_SIZE]>,
 }
 
 impl ColumnOptions {
 	fn as_string(&self) -> String {
 		format!(
-			"preimage: {}, uniform: {}, refc: {}, compression: {}, ordered: {}",
-			self.preimage, self.uniform, self.ref_counted, self.compression as u8, self.btree_index,
+			"preimage: {}, uniform: {}, refc: {}, compression: {}, ordered: {}, free_list: {}, key_sidecar: {}, checksums: {}, append_only: {}, secure_erase: {}, multipart: {}",
+			self.preimage,
+			self.uniform,
+			self.ref_counted,
+			self.compression as u8,
+			self.btree_index,
+			self.compact_free_list,
+			self.key_sidecar,
+			self.entry_checksums,
+			self.append_only,
+			self.secure_erase,
+			self.multipart_entry_size,
 		)
 	}
 
@@ -91,6 +233,15 @@ impl ColumnOptions {
 			log::error!(target: "parity-db", "Using `ref_counted` option without `preimage` enabled is not supported");
 			return false
 		}
+		if self.append_only && (self.ref_counted || self.btree_index) {
+			log::error!(target: "parity-db", "Using `append_only` option with `ref_counted` or `btree_index` enabled is not supported");
+			return false
+		}
+		let multipart = self.multipart_entry_size as usize;
+		if !(MIN_ENTRY_SIZE..=MAX_ENTRY_SIZE).contains(&multipart) {
+			log::error!(target: "parity-db", "`multipart_entry_size` must be between {} and {}", MIN_ENTRY_SIZE, MAX_ENTRY_SIZE);
+			return false
+		}
 		true
 	}
 
@@ -111,6 +262,15 @@ impl ColumnOptions {
 		let ref_counted = vals.get("refc")?.parse().ok()?;
 		let compression: u8 = vals.get("compression").and_then(|c| c.parse().ok()).unwrap_or(0);
 		let btree_index = vals.get("ordered").and_then(|c| c.parse().ok()).unwrap_or(false);
+		let compact_free_list = vals.get("free_list").and_then(|c| c.parse().ok()).unwrap_or(false);
+		let key_sidecar = vals.get("key_sidecar").and_then(|c| c.parse().ok()).unwrap_or(false);
+		let entry_checksums = vals.get("checksums").and_then(|c| c.parse().ok()).unwrap_or(false);
+		let append_only = vals.get("append_only").and_then(|c| c.parse().ok()).unwrap_or(false);
+		let secure_erase = vals.get("secure_erase").and_then(|c| c.parse().ok()).unwrap_or(false);
+		let multipart_entry_size = vals
+			.get("multipart")
+			.and_then(|c| c.parse().ok())
+			.unwrap_or(MULTIPART_ENTRY_SIZE);
 
 		Some(ColumnOptions {
 			preimage,
@@ -118,6 +278,12 @@ impl ColumnOptions {
 			ref_counted,
 			compression: compression.into(),
 			btree_index,
+			compact_free_list,
+			key_sidecar,
+			entry_checksums,
+			append_only,
+			secure_erase,
+			multipart_entry_size,
 		})
 	}
 }
@@ -130,6 +296,12 @@ impl Default for ColumnOptions {
 			ref_counted: false,
 			compression: CompressionType::NoCompression,
 			btree_index: false,
+			compact_free_list: false,
+			key_sidecar: false,
+			entry_checksums: false,
+			append_only: false,
+			secure_erase: false,
+			multipart_entry_size: MULTIPART_ENTRY_SIZE,
 		}
 	}
 }
@@ -144,6 +316,26 @@ impl Options {
 			salt: None,
 			columns: (0..num_columns).map(|_| Default::default()).collect(),
 			compression_threshold: HashMap::new(),
+			encryption: HashMap::new(),
+			reuse_read_buffers: false,
+			dsync_writes: false,
+			prefetch_depth: HashMap::new(),
+			iter_read_ahead: HashMap::new(),
+			grow_step_bytes: HashMap::new(),
+			access_pattern: HashMap::new(),
+			header_durability: HashMap::new(),
+			max_chain_depth: HashMap::new(),
+			max_value_len: HashMap::new(),
+			slot_reuse_grace: HashMap::new(),
+			verify_writes: false,
+			value_write_barrier: false,
+			trusted_open: false,
+			validate_free_lists_on_open: false,
+			replace_freed_as_insert: false,
+			parallel_enact: false,
+			max_log_overlay_bytes: None,
+			direct_io: false,
+			mmap_reads: false,
 			#[cfg(any(test, feature = "instrumentation"))]
 			with_background_thread: true,
 			#[cfg(any(test, feature = "instrumentation"))]
@@ -185,11 +377,38 @@ impl Options {
 		for i in 0..self.columns.len() {
 			metadata.push(format!("col{}={}", i, self.columns[i].as_string()));
 		}
-		try_io!(std::fs::write(path, metadata.join("\n")));
+		if !self.encryption.is_empty() {
+			let mut encrypted: Vec<_> = self.encryption.iter().collect();
+			encrypted.sort_by_key(|(col, _)| **col);
+			let encrypted: Vec<_> = encrypted
+				.into_iter()
+				.map(|(col, e)| format!("{}:{}", col, hex::encode(e.key_check())))
+				.collect();
+			metadata.push(format!("encryption={}", encrypted.join(",")));
+		}
+		// Write a temporary file and move it into place, so that a crash never leaves partly
+		// written metadata.
+		let tmp_path = path.with_extension("tmp");
+		let mut file = try_io!(std::fs::File::create(&tmp_path));
+		try_io!(std::io::Write::write_all(&mut file, metadata.join("\n").as_bytes()));
+		try_io!(file.sync_all());
+		try_io!(std::fs::rename(&tmp_path, path));
 		Ok(())
 	}
 
+	/// Load the metadata and check that it matches the options. A missing metadata file is
+	/// created if `create` is set. The metadata is never written otherwise.
 	pub fn load_and_validate_metadata(&self, create: bool) -> Result<Metadata> {
+		self.load_and_validate_metadata_with_access(create, !create)
+	}
+
+	/// Same as `load_and_validate_metadata`, but the metadata is updated with a changed
+	/// compression type unless `read_only` is set.
+	pub(crate) fn load_and_validate_metadata_with_access(
+		&self,
+		create: bool,
+		read_only: bool,
+	) -> Result<Metadata> {
 		let meta = Self::load_metadata(&self.path)?;
 
 		if let Some(meta) = meta {
@@ -201,7 +420,28 @@ impl Options {
 				)))
 			}
 
+			let mut compression_changed = false;
 			for c in 0..meta.columns.len() {
+				// Compressed values are tagged with the codec since version 9, so the codec can
+				// change.
+				let with_compression = ColumnOptions {
+					compression: self.columns[c].compression,
+					..meta.columns[c].clone()
+				};
+				if meta.version > 8 &&
+					meta.columns[c].compression != self.columns[c].compression &&
+					with_compression == self.columns[c]
+				{
+					log::info!(
+						target: "parity-db",
+						"Column {} compression changed from {:?} to {:?}",
+						c,
+						meta.columns[c].compression,
+						self.columns[c].compression,
+					);
+					compression_changed = true;
+					continue
+				}
 				if meta.columns[c] != self.columns[c] {
 					return Err(Error::IncompatibleColumnConfig {
 						id: c as ColId,
@@ -213,11 +453,32 @@ impl Options {
 					})
 				}
 			}
+			for c in 0..meta.columns.len() as ColId {
+				let reason = match (meta.encryption.get(&c), self.encryption.get(&c)) {
+					(None, None) => continue,
+					(Some(check), Some(e)) if *check == e.key_check() => continue,
+					(Some(_), Some(_)) => "Encryption key mismatch",
+					(Some(_), None) => "Column is encrypted, but no encryption options are set",
+					(None, Some(_)) => "Column is not encrypted, but encryption options are set",
+				};
+				return Err(Error::IncompatibleColumnConfig { id: c, reason: reason.into() })
+			}
+			if compression_changed {
+				if !read_only {
+					self.write_metadata_with_version(&self.path, &meta.salt, Some(meta.version))?;
+				}
+				return Ok(Metadata { columns: self.columns.clone(), ..meta })
+			}
 			Ok(meta)
 		} else if create {
 			let s: Salt = self.salt.unwrap_or_else(|| rand::thread_rng().gen());
 			self.write_metadata(&self.path, &s)?;
-			Ok(Metadata { version: CURRENT_VERSION, columns: self.columns.clone(), salt: s })
+			Ok(Metadata {
+				version: CURRENT_VERSION,
+				columns: self.columns.clone(),
+				salt: s,
+				encryption: self.encryption.iter().map(|(col, e)| (*col, e.key_check())).collect(),
+			})
 		} else {
 			Err(Error::DatabaseNotFound)
 		}
@@ -238,6 +499,7 @@ impl Options {
 		let file = std::io::BufReader::new(try_io!(std::fs::File::open(path)));
 		let mut salt = None;
 		let mut columns = Vec::new();
+		let mut encryption = HashMap::new();
 		let mut version = 0;
 		for l in file.lines() {
 			let l = try_io!(l);
@@ -257,6 +519,19 @@ impl Options {
 				let col = ColumnOptions::from_string(v)
 					.ok_or_else(|| Error::Corruption("Bad column metadata".into()))?;
 				columns.push(col);
+			} else if k == "encryption" {
+				for c in v.split(',') {
+					let (col, check) = c
+						.split_once(':')
+						.and_then(|(col, check)| {
+							Some((ColId::from_str(col).ok()?, hex::decode(check).ok()?))
+						})
+						.filter(|(_, check)| check.len() == KEY_CHECK_SIZE)
+						.ok_or_else(|| Error::Corruption("Bad encryption metadata".into()))?;
+					let mut key_check = [0u8; KEY_CHECK_SIZE];
+					key_check.copy_from_slice(&check);
+					encryption.insert(col, key_check);
+				}
 			}
 		}
 		if version < LAST_SUPPORTED_VERSION {
@@ -265,7 +540,7 @@ impl Options {
 			)))
 		}
 		let salt = salt.ok_or_else(|| Error::InvalidConfiguration("Missing salt value".into()))?;
-		Ok(Some(Metadata { version, columns, salt }))
+		Ok(Some(Metadata { version, columns, salt, encryption }))
 	}
 
 	pub fn is_valid(&self) -> bool {
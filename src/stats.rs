@@ -1,7 +1,10 @@
 // Copyright 2021-2022 Parity Technologies (UK) Ltd.
 // This file is dual-licensed as Apache-2.0 or MIT.
 
-use crate::{column::ColId, table::SIZE_TIERS};
+use crate::{
+	column::ColId,
+	table::{TableStats, WriteCounters, SIZE_TIERS},
+};
 /// Database statistics.
 use std::sync::atomic::{AtomicI64, AtomicU32, AtomicU64, Ordering};
 use std::{
@@ -15,7 +18,7 @@ const HISTOGRAM_BUCKETS: usize = 1024;
 const HISTOGRAM_BUCKET_BITS: u8 = 5;
 
 pub const TOTAL_SIZE: usize =
-	4 * HISTOGRAM_BUCKETS + 8 * HISTOGRAM_BUCKETS + 8 * SIZE_TIERS + 8 * 13;
+	4 * HISTOGRAM_BUCKETS + 8 * HISTOGRAM_BUCKETS + 8 * SIZE_TIERS + 8 * 16;
 
 // TODO: get rid of the struct and use index meta directly.
 #[derive(Debug)]
@@ -36,6 +39,11 @@ pub struct ColumnStats {
 	queries_miss: AtomicU64,
 	uncompressed_bytes: AtomicU64,
 	compression_delta: Vec<AtomicI64>,
+	// Value tables keep their own write counters, these are added here when the stats are read or
+	// written.
+	logical_writes: AtomicU64,
+	entry_writes: AtomicU64,
+	bytes_written: AtomicU64,
 }
 
 /// Database statistics summary.
@@ -55,6 +63,14 @@ pub struct ColumnStatSummary {
 	/// Total size of values in the column before compression. This does not include key size and
 	/// any other overhead.
 	pub uncompressed_bytes: u64,
+	/// Number of values written to the value tables.
+	pub logical_writes: u64,
+	/// Number of value table entries written. Values spanning multiple entries count each entry.
+	pub entry_writes: u64,
+	/// Bytes written to value table entries.
+	pub bytes_written: u64,
+	/// Occupancy of the value tables that have a file, in size tier order.
+	pub value_tables: Vec<TableStats>,
 }
 
 fn read_u32(cursor: &mut Cursor<&[u8]>) -> AtomicU32 {
@@ -125,6 +141,9 @@ impl ColumnStats {
 			iter::repeat_with(|| read_i64(cursor)).take(HISTOGRAM_BUCKETS).collect();
 		let reference_increase_hit = read_u64(cursor);
 		let reference_increase_miss = read_u64(cursor);
+		let logical_writes = read_u64(cursor);
+		let entry_writes = read_u64(cursor);
+		let bytes_written = read_u64(cursor);
 
 		ColumnStats {
 			value_histogram,
@@ -143,6 +162,9 @@ impl ColumnStats {
 			queries_miss,
 			uncompressed_bytes,
 			compression_delta,
+			logical_writes,
+			entry_writes,
+			bytes_written,
 		}
 	}
 
@@ -166,6 +188,9 @@ impl ColumnStats {
 			compression_delta: iter::repeat_with(Default::default)
 				.take(HISTOGRAM_BUCKETS)
 				.collect(),
+			logical_writes: Default::default(),
+			entry_writes: Default::default(),
+			bytes_written: Default::default(),
 		}
 	}
 
@@ -193,6 +218,9 @@ impl ColumnStats {
 		for v in &self.compression_delta {
 			v.store(0, Ordering::Relaxed)
 		}
+		self.logical_writes.store(0, Ordering::Relaxed);
+		self.entry_writes.store(0, Ordering::Relaxed);
+		self.bytes_written.store(0, Ordering::Relaxed);
 	}
 
 	pub fn summary(&self) -> ColumnStatSummary {
@@ -200,9 +228,19 @@ impl ColumnStats {
 			total_values: self.total_values.load(Ordering::Relaxed),
 			total_bytes: self.total_bytes.load(Ordering::Relaxed),
 			uncompressed_bytes: self.uncompressed_bytes.load(Ordering::Relaxed),
+			logical_writes: self.logical_writes.load(Ordering::Relaxed),
+			entry_writes: self.entry_writes.load(Ordering::Relaxed),
+			bytes_written: self.bytes_written.load(Ordering::Relaxed),
+			value_tables: Vec::new(),
 		}
 	}
 
+	pub fn add_writes(&self, counters: &WriteCounters) {
+		self.logical_writes.fetch_add(counters.logical_writes, Ordering::Relaxed);
+		self.entry_writes.fetch_add(counters.entry_writes, Ordering::Relaxed);
+		self.bytes_written.fetch_add(counters.bytes_written, Ordering::Relaxed);
+	}
+
 	pub fn to_slice(&self, data: &mut [u8]) {
 		let mut cursor = Cursor::new(data);
 		for item in &self.value_histogram {
@@ -227,6 +265,9 @@ impl ColumnStats {
 		}
 		write_u64(&mut cursor, &self.reference_increase_hit);
 		write_u64(&mut cursor, &self.reference_increase_miss);
+		write_u64(&mut cursor, &self.logical_writes);
+		write_u64(&mut cursor, &self.entry_writes);
+		write_u64(&mut cursor, &self.bytes_written);
 	}
 
 	pub fn write_stats_text(&self, writer: &mut impl Write, col: ColId) -> std::io::Result<()> {
@@ -257,6 +298,9 @@ impl ColumnStats {
 			self.reference_increase_miss.load(Ordering::Relaxed)
 		)?;
 		writeln!(writer, "Removals: {}", self.removed_hit.load(Ordering::Relaxed))?;
+		writeln!(writer, "Value writes: {}", self.logical_writes.load(Ordering::Relaxed))?;
+		writeln!(writer, "Entry writes: {}", self.entry_writes.load(Ordering::Relaxed))?;
+		writeln!(writer, "Bytes written: {}", self.bytes_written.load(Ordering::Relaxed))?;
 		writeln!(writer, "Missed removals: {}", self.removed_miss.load(Ordering::Relaxed))?;
 		writeln!(
 			writer,
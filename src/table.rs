@@ -5,19 +5,36 @@
 // All numerical values are little endian.
 //
 // Entry 0 (metadata)
-// [LAST_REMOVED: 8][FILLED: 8]
+// [LAST_REMOVED: 8][FILLED: 8][MAGIC: 8][ENTRY_SIZE: 2][FLAGS: 1][RESERVED: 5]
+// [REMOVED: 8][CHECKSUM: 4]
 // LAST_REMOVED - 64-bit index of removed entries linked list head
 // FILLED - highest index filled with live data
+// MAGIC - value table file marker, written when the file is created. Files created by older
+// versions have zeroes here.
+// ENTRY_SIZE - 16-bit size of each entry, written when the file is created.
+// FLAGS - bit 0 is set for multipart tables.
+// Files created before ENTRY_SIZE was introduced have zeroes in ENTRY_SIZE and FLAGS.
+// REMOVED - number of removed entries, saved when the header is enacted. Only present in tables
+// with entries of at least 44 bytes.
+// CHECKSUM - CRC-32 of LAST_REMOVED, FILLED and REMOVED. The removed entries are counted again
+// when it does not match.
 //
 // Complete entry:
 // [SIZE: 2][REFS: 4][KEY: 26][VALUE]
 // SIZE: 15-bit value size. Sizes up to 0x7ffc are allowed.
-// This includes size of REFS and KEY.
+// This includes size of REFS and KEY, so SIZE is never less than the size of the prefix (30 with
+// both REFS and KEY). An empty value is stored as a SIZE equal to the prefix size and is always
+// distinct from the TOMBSTONE and MULTI* markers, which are above the largest allowed size.
 // The highest bit is reserved to indicate if compression is applied.
 // REF: 32-bit reference counter (optional).
 // KEY: lower 26 bytes of the key (optional for btree nodes).
 // VALUE: payload bytes.
 //
+// The first 6 bytes of the key are not stored. Values are found through the index, which checks
+// at least the first 48 bits of the key: the chunk holding the entry is selected by the leading
+// index bits, and the entry holds the bits that follow. Together with KEY this covers the whole
+// key, so a lookup never returns the value of a different key.
+//
 // Partial entry (first part):
 // [MULTIHEAD: 2][NEXT: 8][REFS: 4][KEY: 26][VALUE]
 // MULTIHEAD - Split entry head marker. 0xfffd.
@@ -43,20 +60,41 @@
 // [TOMBSTONE: 2][NEXT: 8]
 // TOMBSTONE - Deleted entry marker. 0xffff
 // NEXT - 64-bit index of the next deleted entry.
+//
+// Compact free list file (optional)
+// [LAST_REMOVED: 8][FILLED: 8][COUNT: 8][SLOT: 8]*COUNT[CHECKSUM: 4]
+// LAST_REMOVED, FILLED - table header the list was saved with.
+// SLOT - deleted entry index, from the end of the removed entries list up to LAST_REMOVED.
+// CHECKSUM - CRC-32 of all preceding bytes.
+//
+// Entry checksum file (optional)
+// [CHECKSUM: 4]*
+// CHECKSUM - CRC-32 of the bytes last written to the entry with the same index, stored as 1 if
+// it is 0. Zero for entries not written since checksums were enabled. Entry 0 is not covered.
+//
+// Table manifest file (optional, one per database)
+// [COUNT: 8]([ID: 2][ENTRY_SIZE: 2][VERSION: 4][LAST_REMOVED: 8][FILLED: 8][REMOVED: 8])*COUNT
+// [CHECKSUM: 4]
+// Headers of all value tables, saved on clean shutdown.
+// REMOVED - number of entries in the removed entries list.
+// CHECKSUM - CRC-32 of all preceding bytes.
+// The manifest is only trusted if the clean shutdown marker file holds the same CHECKSUM.
 
 use crate::{
 	column::ColId,
 	display::hex,
+	encrypt::{self, Cipher},
 	error::{try_io, Result},
-	log::{LogQuery, LogReader, LogWriter},
+	file::{AccessPattern, Durability},
+	log::{LogOverlays, LogQuery, LogReader, LogWriter},
 	options::ColumnOptions as Options,
-	parking_lot::RwLock,
+	parking_lot::{Mutex, RwLock},
 	table::key::{TableKey, TableKeyQuery, PARTIAL_SIZE},
 };
 use std::{
+	collections::{BTreeMap, HashMap},
 	convert::TryInto,
 	io::Read,
-	mem::MaybeUninit,
 	sync::{
 		atomic::{AtomicBool, AtomicU64, Ordering},
 		Arc,
@@ -64,6 +102,8 @@ use std::{
 };
 
 pub const SIZE_TIERS: usize = 1usize << SIZE_TIERS_BITS;
+// Maximum number of bytes read at once by `get_many`.
+const MAX_READ_BLOCK: usize = 256 * 1024;
 pub const SIZE_TIERS_BITS: u8 = 8;
 pub const COMPRESSED_MASK: u16 = 0x80_00;
 pub const MAX_ENTRY_SIZE: usize = 0x7ff8; // Actual max size in V4 was 0x7dfe
@@ -72,6 +112,8 @@ const REFS_SIZE: usize = 4;
 const SIZE_SIZE: usize = 2;
 const INDEX_SIZE: usize = 8;
 const MAX_ENTRY_BUF_SIZE: usize = 0x8000;
+// Number of slots read under a single overlay lock by snapshot iteration.
+const ITER_BATCH: u64 = 64;
 
 const TOMBSTONE: &[u8] = &[0xff, 0xff];
 const MULTIPART_V4: &[u8] = &[0xff, 0xfe];
@@ -82,7 +124,73 @@ const MULTIHEAD_COMPRESSED: &[u8] = &[0xfd, 0x7f];
 // When a rc reach locked ref, it is locked in db.
 const LOCKED_REF: u32 = u32::MAX;
 
-const MULTIPART_ENTRY_SIZE: u16 = 4096;
+pub const MULTIPART_ENTRY_SIZE: u16 = 4096;
+
+/// Entry size of a value table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntrySizing {
+	/// Each value is stored in a single entry of the given size.
+	Single(u16),
+	/// Values are split into chains of `chunk` byte entries.
+	Multipart { chunk: u16 },
+}
+
+impl EntrySizing {
+	fn multipart_and_size(self) -> (bool, u16) {
+		match self {
+			EntrySizing::Single(size) => (false, size),
+			EntrySizing::Multipart { chunk } => (true, chunk),
+		}
+	}
+}
+
+impl From<Option<u16>> for EntrySizing {
+	/// `None` stands for a multipart table with the default 4096 byte entries.
+	fn from(entry_size: Option<u16>) -> Self {
+		match entry_size {
+			Some(size) => EntrySizing::Single(size),
+			None => EntrySizing::Multipart { chunk: MULTIPART_ENTRY_SIZE },
+		}
+	}
+}
+
+/// Settings for opening a value table, see [`ValueTable::open`].
+pub struct TableConfig<'a> {
+	pub db_version: u32,
+	pub cipher: Option<Cipher>,
+	/// Read entries into a per-thread buffer that is kept between reads.
+	pub reuse_read_buffer: bool,
+	/// Open the file for synchronous writes.
+	pub dsync: bool,
+	/// Share the file lock with other read-only handles. The table must not be written to.
+	pub read_only: bool,
+	/// Take the header from the manifest instead of the file when the manifest has a matching
+	/// entry for the table.
+	pub manifest: Option<&'a TableManifest>,
+}
+
+impl Default for TableConfig<'_> {
+	fn default() -> Self {
+		TableConfig {
+			db_version: crate::options::CURRENT_VERSION,
+			cipher: None,
+			reuse_read_buffer: false,
+			dsync: false,
+			read_only: false,
+			manifest: None,
+		}
+	}
+}
+
+pub const TABLE_MAGIC: &[u8; 8] = b"paritydb";
+
+// Table format stored after the marker in entry 0.
+fn table_format(entry_size: u16, multipart: bool) -> [u8; 8] {
+	let mut format = [0u8; 8];
+	format[0..2].copy_from_slice(&entry_size.to_le_bytes());
+	format[2] = multipart as u8;
+	format
+}
 
 pub type Value = Vec<u8>;
 
@@ -110,10 +218,29 @@ impl TableId {
 		format!("table_{:02}_{}", self.col(), hex(&[self.size_tier()]))
 	}
 
+	pub fn free_list_file_name(&self) -> String {
+		format!("{}.free", self.file_name())
+	}
+
+	pub fn checksum_file_name(&self) -> String {
+		format!("{}.crc", self.file_name())
+	}
+
 	pub fn is_file_name(col: ColId, name: &str) -> bool {
 		name.starts_with(&format!("table_{col:02}_"))
 	}
 
+	/// Parse a value table file name, as produced by [`TableId::file_name`].
+	pub fn from_file_name(name: &str) -> Option<TableId> {
+		let (col, tier) = name.strip_prefix("table_")?.split_once('_')?;
+		if tier.len() != 2 {
+			return None
+		}
+		let id = TableId::new(col.parse().ok()?, u8::from_str_radix(tier, 16).ok()?);
+		// Reject names that only parse, such as unpadded column numbers.
+		(id.file_name() == name).then_some(id)
+	}
+
 	pub fn as_u16(&self) -> u16 {
 		self.0
 	}
@@ -132,13 +259,129 @@ pub struct ValueTable {
 	file: crate::file::TableFile,
 	filled: AtomicU64,
 	last_removed: AtomicU64,
+	// Number of entries in the removed entries list.
+	removed: AtomicU64,
+	// Removed entries count for each header added to the log by `complete_plan`, oldest first.
+	// The count is saved in entry 0 when the header is enacted.
+	removed_counts: Mutex<std::collections::VecDeque<(Header, u64)>>,
 	dirty_header: AtomicBool,
 	multipart: bool,
 	ref_counted: bool,
 	db_version: u32,
+	free_list: Option<Mutex<FreeList>>,
+	checksums: Option<RwLock<EntryChecksums>>,
+	cipher: Option<Cipher>,
+	reuse_read_buffer: bool,
+	prefetch_depth: u32,
+	iter_read_ahead: u32,
+	max_chain_depth: Option<u32>,
+	max_value_len: Option<usize>,
+	quarantine: Option<Mutex<Quarantine>>,
+	replace_freed_as_insert: bool,
+	append_only: bool,
+	// Removed entries are overwritten with zeroes as a whole.
+	secure_erase: bool,
+	// Write amplification counters, see `WriteCounters`.
+	logical_writes: AtomicU64,
+	entry_writes: AtomicU64,
+	bytes_written: AtomicU64,
+	// Slots allocated with `next_free` and not freed since, for leak detection. Maps to `true`
+	// once the slot is written.
+	#[cfg(test)]
+	allocated: Mutex<HashMap<u64, bool>>,
+}
+
+/// Value table counters, as stored in the table file header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TableHeader {
+	/// Index of the most recently removed entry, or 0 if there are no removed entries.
+	pub last_removed: u64,
+	/// Index of the first never used entry. Entry 0 holds the header.
+	pub filled: u64,
+	/// Number of used entries, including removed ones.
+	pub entries: u64,
 }
 
-#[derive(Default, Clone, Copy)]
+/// Read the header of the value table for `col` and `size_tier` in the database at `path`. This
+/// is meant for cheap polling by external tools and may be called while the database is open.
+/// Counters lag behind the database until its log is enacted.
+pub fn read_table_header(path: &std::path::Path, col: ColId, size_tier: u8) -> Result<TableHeader> {
+	ValueTable::read_header(path, TableId::new(col, size_tier))
+}
+
+/// List the value tables present in the database directory at `path`, as a map of column to its
+/// size tiers in ascending order. Tables are found by file name only, so this works without the
+/// database options.
+pub fn table_inventory(path: &std::path::Path) -> Result<BTreeMap<ColId, Vec<u8>>> {
+	let mut inventory: BTreeMap<ColId, Vec<u8>> = BTreeMap::new();
+	for entry in try_io!(std::fs::read_dir(path)) {
+		let entry = try_io!(entry);
+		if let Some(id) = entry.file_name().to_str().and_then(TableId::from_file_name) {
+			inventory.entry(id.col()).or_default().push(id.size_tier());
+		}
+	}
+	for tiers in inventory.values_mut() {
+		tiers.sort_unstable();
+	}
+	Ok(inventory)
+}
+
+/// Writes planned for a value table since the counters were last taken, see
+/// [`ValueTable::take_write_counters`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WriteCounters {
+	/// Number of values written.
+	pub logical_writes: u64,
+	/// Number of entries written, including every part of multipart values and removed entries.
+	pub entry_writes: u64,
+	/// Bytes written to entries.
+	pub bytes_written: u64,
+}
+
+/// Occupancy of a value table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableStats {
+	pub entry_size: u16,
+	/// Index of the first never used entry. Entry 0 holds the header.
+	pub filled: u64,
+	/// Index of the most recently removed entry, or 0 if there are no removed entries.
+	pub last_removed: u64,
+	/// Number of entries the file can hold.
+	pub capacity: u64,
+	/// Number of removed entries waiting to be reused.
+	pub free: u64,
+	/// Bytes in entries holding values or parts of values.
+	pub used_bytes: u64,
+	/// Bytes allocated for the file.
+	pub allocated_bytes: u64,
+	/// Number of entries added each time the file grows.
+	pub grow_entries: u64,
+}
+
+/// Problem found while walking the removed entries list, see [`ValueTable::verify_free_list`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FreeListAnomaly {
+	/// `index` links to `next`, which is past the last filled entry.
+	OutOfBounds { index: u64, next: u64 },
+	/// `index` links to `next`, which was already visited.
+	Cycle { index: u64, next: u64 },
+}
+
+/// Result of walking the removed entries list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FreeListReport {
+	/// Number of removed entries visited before reaching the end of the list or an anomaly.
+	pub len: u64,
+	/// First problem found, if any.
+	pub anomaly: Option<FreeListAnomaly>,
+}
+
+thread_local! {
+	// Scratch buffer for value reads. Holds at most `MAX_ENTRY_BUF_SIZE` bytes.
+	static READ_BUFFER: std::cell::RefCell<Vec<u8>> = Default::default();
+}
+
+#[derive(Debug, Default, Clone, Copy)]
 struct Header([u8; 16]);
 
 impl Header {
@@ -154,6 +397,305 @@ impl Header {
 	fn set_filled(&mut self, filled: u64) {
 		self.0[INDEX_SIZE..INDEX_SIZE * 2].copy_from_slice(&filled.to_le_bytes());
 	}
+	// Whether `removed` can be the number of removed entries for this header.
+	fn plausible_removed(&self, removed: u64) -> bool {
+		removed < self.filled().max(1) && (removed == 0) == (self.last_removed() == 0)
+	}
+	fn removed_checksum(&self, removed: u64) -> u32 {
+		let mut hasher = crc32fast::Hasher::new();
+		hasher.update(&self.0);
+		hasher.update(&removed.to_le_bytes());
+		hasher.finalize()
+	}
+	fn encode_removed(&self, removed: u64) -> [u8; REMOVED_COUNT_SIZE] {
+		let mut buf = [0u8; REMOVED_COUNT_SIZE];
+		buf[0..8].copy_from_slice(&removed.to_le_bytes());
+		buf[8..12].copy_from_slice(&self.removed_checksum(removed).to_le_bytes());
+		buf
+	}
+	// Removed entries count saved with `encode_removed`, if it was saved with this header.
+	fn decode_removed(&self, buf: &[u8; REMOVED_COUNT_SIZE]) -> Option<u64> {
+		let removed = u64::from_le_bytes(buf[0..8].try_into().unwrap());
+		let checksum = u32::from_le_bytes(buf[8..12].try_into().unwrap());
+		(checksum == self.removed_checksum(removed) && self.plausible_removed(removed))
+			.then_some(removed)
+	}
+}
+
+// Location of the removed entries count in entry 0, after the table format.
+const REMOVED_COUNT_OFFSET: usize = 32;
+const REMOVED_COUNT_SIZE: usize = 12;
+
+// Whether entry 0 has room for the removed entries count.
+fn has_removed_count(entry_size: u16) -> bool {
+	entry_size as usize >= REMOVED_COUNT_OFFSET + REMOVED_COUNT_SIZE
+}
+
+const MANIFEST_FILE: &str = "manifest";
+const CLEAN_SHUTDOWN_FILE: &str = "clean_shutdown";
+const MANIFEST_ENTRY_SIZE: usize = 2 + 2 + 4 + 24;
+
+/// Value table state saved in the table manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ManifestEntry {
+	pub entry_size: u16,
+	pub db_version: u32,
+	pub last_removed: u64,
+	pub filled: u64,
+	/// Number of entries in the removed entries list.
+	pub removed: u64,
+}
+
+/// Headers of all value tables of a database, saved on clean shutdown. Tables are opened with
+/// these values instead of reading and validating each table header.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct TableManifest {
+	pub tables: HashMap<TableId, ManifestEntry>,
+}
+
+impl TableManifest {
+	/// Load the manifest from the database directory. Returns `None` unless the database was shut
+	/// down cleanly. The directory is not changed. Call `invalidate` before changing any table
+	/// file, so that the manifest is not trusted again after a crash.
+	pub fn load(dir: &std::path::Path) -> Result<Option<TableManifest>> {
+		let marker = match std::fs::read(dir.join(CLEAN_SHUTDOWN_FILE)) {
+			Ok(marker) => marker,
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+			Err(e) => return Err(crate::error::Error::Io(e)),
+		};
+		let data = match std::fs::read(dir.join(MANIFEST_FILE)) {
+			Ok(data) => data,
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+			Err(e) => return Err(crate::error::Error::Io(e)),
+		};
+		if data.len() < 12 || (data.len() - 12) % MANIFEST_ENTRY_SIZE != 0 {
+			return Ok(None)
+		}
+		let (body, checksum) = data.split_at(data.len() - 4);
+		if crc32fast::hash(body).to_le_bytes() != checksum || marker != checksum {
+			return Ok(None)
+		}
+		let count = u64::from_le_bytes(body[0..8].try_into().unwrap());
+		if count != ((body.len() - 8) / MANIFEST_ENTRY_SIZE) as u64 {
+			return Ok(None)
+		}
+		let tables = body[8..]
+			.chunks_exact(MANIFEST_ENTRY_SIZE)
+			.map(|e| {
+				let id = TableId(u16::from_le_bytes(e[0..2].try_into().unwrap()));
+				let entry = ManifestEntry {
+					entry_size: u16::from_le_bytes(e[2..4].try_into().unwrap()),
+					db_version: u32::from_le_bytes(e[4..8].try_into().unwrap()),
+					last_removed: u64::from_le_bytes(e[8..16].try_into().unwrap()),
+					filled: u64::from_le_bytes(e[16..24].try_into().unwrap()),
+					removed: u64::from_le_bytes(e[24..32].try_into().unwrap()),
+				};
+				(id, entry)
+			})
+			.collect();
+		Ok(Some(TableManifest { tables }))
+	}
+
+	/// Save the manifest and mark the database as cleanly shut down. Must only be called when all
+	/// changes are enacted and flushed.
+	pub fn save(&self, dir: &std::path::Path) -> Result<()> {
+		let mut tables: Vec<_> = self.tables.iter().collect();
+		tables.sort_by_key(|(id, _)| id.as_u16());
+		let mut data = Vec::with_capacity(12 + tables.len() * MANIFEST_ENTRY_SIZE);
+		data.extend_from_slice(&(tables.len() as u64).to_le_bytes());
+		for (id, entry) in tables {
+			data.extend_from_slice(&id.as_u16().to_le_bytes());
+			data.extend_from_slice(&entry.entry_size.to_le_bytes());
+			data.extend_from_slice(&entry.db_version.to_le_bytes());
+			data.extend_from_slice(&entry.last_removed.to_le_bytes());
+			data.extend_from_slice(&entry.filled.to_le_bytes());
+			data.extend_from_slice(&entry.removed.to_le_bytes());
+		}
+		let checksum = crc32fast::hash(&data).to_le_bytes();
+		data.extend_from_slice(&checksum);
+		let tmp_path = dir.join(format!("{MANIFEST_FILE}.tmp"));
+		let file = try_io!(std::fs::File::create(&tmp_path));
+		try_io!(std::io::Write::write_all(&mut &file, &data));
+		try_io!(file.sync_data());
+		try_io!(std::fs::rename(&tmp_path, dir.join(MANIFEST_FILE)));
+		let marker = try_io!(std::fs::File::create(dir.join(CLEAN_SHUTDOWN_FILE)));
+		try_io!(std::io::Write::write_all(&mut &marker, &checksum));
+		try_io!(marker.sync_data());
+		Ok(())
+	}
+
+	/// Clear the clean shutdown marker. Must be called before table files are changed while the
+	/// database is closed.
+	pub fn invalidate(dir: &std::path::Path) -> Result<()> {
+		if let Ok(file) =
+			std::fs::OpenOptions::new().write(true).open(dir.join(CLEAN_SHUTDOWN_FILE))
+		{
+			try_io!(file.set_len(0));
+			try_io!(file.sync_data());
+		}
+		Ok(())
+	}
+
+	// Saved header and removed entries count. The count is `None` if it does not match the header.
+	fn header(
+		&self,
+		id: TableId,
+		entry_size: u16,
+		db_version: u32,
+	) -> Option<(Header, Option<u64>)> {
+		let entry = self.tables.get(&id)?;
+		if entry.entry_size != entry_size ||
+			entry.db_version != db_version ||
+			entry.last_removed >= entry.filled.max(1)
+		{
+			return None
+		}
+		let mut header = Header::default();
+		header.set_last_removed(entry.last_removed);
+		header.set_filled(entry.filled);
+		let removed = header.plausible_removed(entry.removed).then_some(entry.removed);
+		Some((header, removed))
+	}
+}
+
+// Freed slots that are not reused until a number of allocations have been made.
+#[derive(Debug)]
+struct Quarantine {
+	grace: u64,
+	allocations: u64,
+	// Slot indices with the allocation count they are released at, oldest first.
+	slots: std::collections::VecDeque<(u64, u64)>,
+}
+
+#[derive(Debug)]
+struct FreeList {
+	path: std::path::PathBuf,
+	// Removed entries stack. The last slot is the head of the list.
+	slots: Vec<u64>,
+}
+
+impl FreeList {
+	// Returns saved slots if the file matches the table header.
+	fn load(path: &std::path::Path, header: &Header) -> Result<Option<Vec<u64>>> {
+		let data = match std::fs::read(path) {
+			Ok(data) => data,
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+			Err(e) => return Err(crate::error::Error::Io(e)),
+		};
+		if data.len() < 28 || (data.len() - 28) % INDEX_SIZE != 0 {
+			return Ok(None)
+		}
+		let (body, checksum) = data.split_at(data.len() - 4);
+		if crc32fast::hash(body).to_le_bytes() != checksum {
+			return Ok(None)
+		}
+		let mut saved = Header::default();
+		saved.0.copy_from_slice(&body[0..16]);
+		let count = u64::from_le_bytes(body[16..24].try_into().unwrap());
+		let slots: Vec<u64> = body[24..]
+			.chunks_exact(INDEX_SIZE)
+			.map(|s| u64::from_le_bytes(s.try_into().unwrap()))
+			.collect();
+		if saved.last_removed() != header.last_removed() ||
+			saved.filled() != header.filled() ||
+			count != slots.len() as u64 ||
+			slots.last().copied().unwrap_or(0) != header.last_removed()
+		{
+			return Ok(None)
+		}
+		Ok(Some(slots))
+	}
+
+	fn save(&self, header: &Header) -> Result<()> {
+		let mut data = Vec::with_capacity(28 + self.slots.len() * INDEX_SIZE);
+		data.extend_from_slice(&header.0);
+		data.extend_from_slice(&(self.slots.len() as u64).to_le_bytes());
+		for slot in &self.slots {
+			data.extend_from_slice(&slot.to_le_bytes());
+		}
+		data.extend_from_slice(&crc32fast::hash(&data).to_le_bytes());
+		let file = try_io!(std::fs::File::create(&self.path));
+		try_io!(std::io::Write::write_all(&mut &file, &data));
+		try_io!(file.sync_data());
+		Ok(())
+	}
+
+	// Saved list is only valid until the table is modified. Make sure a stale list is never
+	// loaded after a crash.
+	fn invalidate(&self) -> Result<()> {
+		if let Ok(file) = std::fs::OpenOptions::new().write(true).open(&self.path) {
+			try_io!(file.set_len(0));
+			try_io!(file.sync_data());
+		}
+		Ok(())
+	}
+}
+
+#[derive(Debug)]
+struct EntryChecksums {
+	path: std::path::PathBuf,
+	// Created on the first write.
+	file: Option<std::fs::File>,
+	checksums: Vec<u32>,
+}
+
+impl EntryChecksums {
+	fn open(path: std::path::PathBuf) -> Result<EntryChecksums> {
+		let (file, data) = match std::fs::OpenOptions::new().read(true).write(true).open(&path) {
+			Ok(mut file) => {
+				let mut data = Vec::new();
+				try_io!(file.read_to_end(&mut data));
+				(Some(file), data)
+			},
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => (None, Vec::new()),
+			Err(e) => return Err(crate::error::Error::Io(e)),
+		};
+		let checksums = data
+			.chunks_exact(4)
+			.map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+			.collect();
+		Ok(EntryChecksums { path, file, checksums })
+	}
+
+	fn checksum(data: &[u8]) -> u32 {
+		crc32fast::hash(data).max(1)
+	}
+
+	fn record(&mut self, index: u64, data: &[u8]) -> Result<()> {
+		use std::io::{Seek, SeekFrom, Write};
+		let checksum = Self::checksum(data);
+		if self.file.is_none() {
+			self.file = Some(try_io!(std::fs::OpenOptions::new()
+				.create(true)
+				.truncate(false)
+				.read(true)
+				.write(true)
+				.open(&self.path)));
+		}
+		let file = self.file.as_mut().expect("Created above");
+		try_io!(file.seek(SeekFrom::Start(index * 4)));
+		try_io!(file.write_all(&checksum.to_le_bytes()));
+		let index = index as usize;
+		if index >= self.checksums.len() {
+			self.checksums.resize(index + 1, 0);
+		}
+		self.checksums[index] = checksum;
+		Ok(())
+	}
+
+	fn verify(&self, index: u64, data: &[u8]) -> bool {
+		match self.checksums.get(index as usize) {
+			None | Some(0) => true,
+			Some(checksum) => *checksum == Self::checksum(data),
+		}
+	}
+
+	fn flush(&self) -> Result<()> {
+		if let Some(file) = &self.file {
+			try_io!(file.sync_data());
+		}
+		Ok(())
+	}
 }
 
 pub struct Entry<B: AsRef<[u8]> + AsMut<[u8]>>(usize, B);
@@ -166,23 +708,22 @@ type PartialKeyEntry = Entry<[u8; 40]>; // 2 + 4 + 26 + 8
 
 impl<const C: usize> Entry<[u8; C]> {
 	#[inline(always)]
-	#[allow(clippy::uninit_assumed_init)]
-	pub fn new_uninit() -> Self {
-		Entry(0, unsafe { MaybeUninit::uninit().assume_init() })
+	pub fn new_zeroed() -> Self {
+		Entry(0, [0; C])
 	}
 }
 
 #[cfg(feature = "loom")]
 impl Entry<Vec<u8>> {
-	pub fn new_uninit_full_entry() -> Self {
+	pub fn new_full_entry() -> Self {
 		Entry(0, vec![0; MAX_ENTRY_BUF_SIZE])
 	}
 }
 
 #[cfg(not(feature = "loom"))]
 impl Entry<[u8; MAX_ENTRY_BUF_SIZE]> {
-	pub fn new_uninit_full_entry() -> Self {
-		Self::new_uninit()
+	pub fn new_full_entry() -> Self {
+		Self::new_zeroed()
 	}
 }
 
@@ -281,6 +822,8 @@ impl<B: AsRef<[u8]> + AsMut<[u8]>> Entry<B> {
 	}
 
 	fn write_size(&mut self, mut size: u16, compressed: bool) {
+		// Sizes must not overlap with the compression flag and entry markers. This is checked when
+		// the write is planned, by `ValueTable::check_value_len`.
 		if compressed {
 			size |= COMPRESSED_MASK;
 		}
@@ -367,28 +910,49 @@ impl<B: AsRef<[u8]> + AsMut<[u8]>> std::ops::IndexMut<std::ops::Range<usize>> fo
 }
 
 impl ValueTable {
+	/// Open a table, creating its file when it is first written to.
 	pub fn open(
 		path: Arc<std::path::PathBuf>,
 		id: TableId,
-		entry_size: Option<u16>,
+		entry_size: impl Into<EntrySizing>,
 		options: &Options,
-		db_version: u32,
+		config: TableConfig,
 	) -> Result<ValueTable> {
-		let (multipart, entry_size) = match entry_size {
-			Some(s) => (false, s),
-			None => (true, MULTIPART_ENTRY_SIZE),
-		};
+		let (multipart, entry_size) = entry_size.into().multipart_and_size();
 		assert!(entry_size >= MIN_ENTRY_SIZE as u16);
 		assert!(entry_size <= MAX_ENTRY_SIZE as u16);
 
 		let mut filepath: std::path::PathBuf = std::path::PathBuf::clone(&*path);
 		filepath.push(id.file_name());
-		let file = crate::file::TableFile::open(filepath, entry_size, id)?;
+		let format = table_format(entry_size, multipart);
+		let file = crate::file::TableFile::open(
+			filepath,
+			entry_size,
+			format,
+			id,
+			config.dsync,
+			config.read_only,
+		)?;
+		let TableConfig { db_version, cipher, reuse_read_buffer, manifest, .. } = config;
 		let mut filled = 1;
 		let mut last_removed = 0;
-		if let Some(file) = &mut *file.file.write() {
-			let mut header = Header::default();
+		let mut header = Header::default();
+		let mut removed = None;
+		let saved = manifest.and_then(|m| m.header(id, entry_size, db_version)).filter(|(h, _)| {
+			// Files may have been replaced without updating the manifest.
+			let capacity = file.capacity.load(Ordering::Relaxed);
+			file.file.read().is_some() && h.filled() <= capacity.max(1)
+		});
+		if let Some((saved, saved_removed)) = saved {
+			header = saved;
+			removed = saved_removed;
+			last_removed = header.last_removed();
+			filled = header.filled().max(1);
+			log::debug!(target: "parity-db", "Opened value table {} from manifest with {} entries, entry_size={}, removed={}", id, filled, entry_size, last_removed);
+		} else if let Some(file) = &mut *file.file.write() {
 			try_io!(file.read_exact(&mut header.0));
+			Self::check_magic(file, id)?;
+			Self::check_format(file, id, &format)?;
 			last_removed = header.last_removed();
 			filled = header.filled();
 			if filled == 0 {
@@ -400,24 +964,274 @@ impl ValueTable {
 					last_removed, filled
 				)))
 			}
+			if has_removed_count(entry_size) {
+				let mut buf = [0u8; REMOVED_COUNT_SIZE];
+				try_io!(file.read_exact(&mut buf));
+				removed = header.decode_removed(&buf);
+			}
 			log::debug!(target: "parity-db", "Opened value table {} with {} entries, entry_size={}, removed={}", id, filled, entry_size, last_removed);
 		}
 
-		Ok(ValueTable {
+		let mut free_list = None;
+		let mut saved_slots = None;
+		if options.compact_free_list {
+			let mut free_list_path: std::path::PathBuf = std::path::PathBuf::clone(&*path);
+			free_list_path.push(id.free_list_file_name());
+			let list = FreeList { path: free_list_path, slots: Vec::new() };
+			saved_slots = FreeList::load(&list.path, &header)?;
+			list.invalidate()?;
+			free_list = Some(Mutex::new(list));
+		}
+		let checksums = if options.entry_checksums {
+			let mut checksums_path: std::path::PathBuf = std::path::PathBuf::clone(&*path);
+			checksums_path.push(id.checksum_file_name());
+			Some(RwLock::new(EntryChecksums::open(checksums_path)?))
+		} else {
+			None
+		};
+
+		let table = ValueTable {
 			id,
 			entry_size,
 			file,
 			filled: AtomicU64::new(filled),
 			last_removed: AtomicU64::new(last_removed),
+			removed: AtomicU64::new(0),
+			removed_counts: Default::default(),
 			dirty_header: AtomicBool::new(false),
 			multipart,
 			ref_counted: options.ref_counted,
 			db_version,
-		})
+			free_list,
+			checksums,
+			cipher,
+			reuse_read_buffer,
+			prefetch_depth: 0,
+			iter_read_ahead: 0,
+			max_chain_depth: None,
+			max_value_len: None,
+			quarantine: None,
+			replace_freed_as_insert: false,
+			append_only: options.append_only,
+			secure_erase: options.secure_erase,
+			logical_writes: AtomicU64::new(0),
+			entry_writes: AtomicU64::new(0),
+			bytes_written: AtomicU64::new(0),
+			#[cfg(test)]
+			allocated: Default::default(),
+		};
+		if let Some(free_list) = &table.free_list {
+			match saved_slots {
+				Some(slots) => {
+					log::debug!(target: "parity-db", "{}: Loaded {} free slots", id, slots.len());
+					free_list.lock().slots = slots;
+				},
+				None => table.rebuild_free_list()?,
+			}
+		}
+		match removed {
+			Some(removed) => table.removed.store(removed, Ordering::Relaxed),
+			None => table.count_removed()?,
+		}
+		Ok(table)
+	}
+
+	/// Read the header counters of an existing table file. Unlike `open` this does not create,
+	/// preallocate or modify the file.
+	pub fn read_header(path: &std::path::Path, id: TableId) -> Result<TableHeader> {
+		let mut path: std::path::PathBuf = path.into();
+		path.push(id.file_name());
+		let mut file = try_io!(std::fs::OpenOptions::new().read(true).open(path.as_path()));
+		Self::read_file_header(&mut file, id)
+	}
+
+	fn read_file_header(file: &mut std::fs::File, id: TableId) -> Result<TableHeader> {
+		let len = try_io!(file.metadata()).len();
+		let mut header = Header::default();
+		if len >= header.0.len() as u64 {
+			try_io!(file.read_exact(&mut header.0));
+		}
+		if len >= (header.0.len() + TABLE_MAGIC.len()) as u64 {
+			Self::check_magic(file, id)?;
+		}
+		let filled = header.filled().max(1);
+		let last_removed = header.last_removed();
+		if last_removed >= filled {
+			return Err(crate::error::Error::Corruption(format!(
+				"Bad removed ref {} out of {}",
+				last_removed, filled
+			)))
+		}
+		Ok(TableHeader { last_removed, filled, entries: filled - 1 })
+	}
+
+	// Expects the file position to be right after the header.
+	fn check_magic(file: &mut std::fs::File, id: TableId) -> Result<()> {
+		let mut magic = [0u8; TABLE_MAGIC.len()];
+		try_io!(file.read_exact(&mut magic));
+		// Files created before the marker was added have zeros here. New files, and empty files
+		// found on open, are always written with the marker.
+		if &magic != TABLE_MAGIC && magic != [0u8; TABLE_MAGIC.len()] {
+			return Err(crate::error::Error::FormatMismatch(format!(
+				"{}: Unexpected value table marker {}",
+				id,
+				hex(&magic)
+			)))
+		}
+		Ok(())
+	}
+
+	// Expects the file position to be right after the marker.
+	fn check_format(file: &mut std::fs::File, id: TableId, expected: &[u8; 8]) -> Result<()> {
+		let mut format = [0u8; 8];
+		try_io!(file.read_exact(&mut format));
+		if &format != expected && format != [0u8; 8] {
+			let describe = |format: &[u8; 8]| {
+				let entry_size = u16::from_le_bytes([format[0], format[1]]);
+				format!("entry_size={}, multipart={}", entry_size, format[2] & 1 != 0)
+			};
+			return Err(crate::error::Error::FormatMismatch(format!(
+				"{}: Table was created with {}, opened with {}",
+				id,
+				describe(&format),
+				describe(expected)
+			)))
+		}
+		Ok(())
+	}
+
+	/// Set the read ahead advice for the table file. Tables are opened for random access.
+	pub fn set_access_pattern(&mut self, pattern: AccessPattern) -> Result<()> {
+		self.file.set_access_pattern(pattern)
+	}
+
+	/// Read and write the table file with direct I/O.
+	pub fn enable_direct_io(&mut self) -> Result<()> {
+		self.file.enable_direct_io()
+	}
+
+	/// Read the table file through a memory map.
+	pub fn enable_mmap_reads(&mut self) -> Result<()> {
+		self.file.enable_mmap_reads()
+	}
+
+	/// Set the number of bytes the table file grows by when it is full. Rounded down to whole
+	/// entries, with at least one entry.
+	pub fn set_grow_step(&mut self, bytes: u64) {
+		self.file.grow_step = bytes;
+	}
+
+	/// Set how the table file is synced when a header planned by `complete_plan` is enacted.
+	pub fn set_durability(&mut self, durability: Durability) {
+		self.file.durability = durability;
+	}
+
+	/// Set the number of entries hinted for read ahead when reading a part of a multipart value.
+	pub fn set_prefetch_depth(&mut self, depth: u32) {
+		self.prefetch_depth = depth;
+	}
+
+	/// Set the number of entries hinted for read ahead in front of the cursor when iterating the
+	/// table. 0 disables the hints.
+	pub fn set_iter_read_ahead(&mut self, window: u32) {
+		self.iter_read_ahead = window;
+	}
+
+	/// Set the maximum number of entries a multipart value may span. `None` disables the limit.
+	pub fn set_max_chain_depth(&mut self, depth: Option<u32>) {
+		self.max_chain_depth = depth;
+	}
+
+	/// Set the maximum length of a value, multipart or not. `None` disables the limit.
+	pub fn set_max_value_len(&mut self, len: Option<usize>) {
+		self.max_value_len = len;
+	}
+
+	/// Check that a value of `len` bytes can be written to this table, without writing anything.
+	/// Fails with `Error::ValueTooLarge` if the value does not fit in a single part table or is
+	/// longer than allowed by `set_max_value_len`, and with `Error::InvalidInput` if the value
+	/// needs more entries than allowed by `set_max_chain_depth`.
+	pub fn check_value_len(&self, key: &TableKey, len: usize) -> Result<()> {
+		if !self.multipart {
+			// The stored size must not overlap with the compression flag and entry markers.
+			let header = self.ref_size() + key.encoded_size() + self.cipher_overhead();
+			let max = (self.value_size(key).unwrap_or(0) as usize)
+				.min((MAX_ENTRY_SIZE - SIZE_SIZE).saturating_sub(header));
+			if len > max {
+				return Err(crate::error::Error::ValueTooLarge { max, got: len })
+			}
+		}
+		if let Some(max) = self.max_value_len.filter(|max| len > *max) {
+			return Err(crate::error::Error::ValueTooLarge { max, got: len })
+		}
+		if let Some(max_depth) = self.max_chain_depth {
+			let len = len + self.cipher_overhead();
+			let parts = self.chain_depth(len + self.ref_size() + key.encoded_size());
+			if parts > max_depth as usize {
+				return Err(crate::error::Error::InvalidInput(format!(
+					"{}: Value of {} bytes needs {} entries, more than the maximum of {}. Use larger entries or raise the limit",
+					self.id,
+					len,
+					parts,
+					max_depth,
+				)))
+			}
+		}
+		Ok(())
+	}
+
+	/// Set the number of allocations a freed slot is held back from reuse. `None` reuses freed
+	/// slots right away. Slots that were still held back when the table was closed are released
+	/// with the next allocation.
+	pub fn set_slot_reuse_grace(&mut self, grace: Option<u64>) -> Result<()> {
+		self.quarantine = grace.map(|grace| {
+			Mutex::new(Quarantine { grace, allocations: 0, slots: Default::default() })
+		});
+		self.recover_quarantined()
+	}
+
+	// Find removed entries that are not in the removed entries list and queue them for release.
+	// These are slots that were held back when the table was closed. The whole table is scanned.
+	fn recover_quarantined(&self) -> Result<()> {
+		let Some(quarantine) = &self.quarantine else { return Ok(()) };
+		if self.file.file.read().is_none() {
+			return Ok(())
+		}
+		let mut linked = std::collections::HashSet::new();
+		let listed = self.count_free_refs(|index| {
+			linked.insert(index);
+		})?;
+		let mut quarantine = quarantine.lock();
+		quarantine.slots.clear();
+		let mut buf = PartialKeyEntry::new_zeroed();
+		for index in 1..self.filled.load(Ordering::Relaxed) {
+			if linked.contains(&index) {
+				continue
+			}
+			self.file.read_at(buf.as_mut(), index * self.entry_size as u64)?;
+			if buf.is_tombstone() {
+				quarantine.slots.push_back((index, 0));
+			}
+		}
+		self.removed.store(listed + quarantine.slots.len() as u64, Ordering::Relaxed);
+		Ok(())
+	}
+
+	/// Set whether replacing a value at a freed slot inserts it at a new slot instead of failing.
+	pub fn set_replace_freed_as_insert(&mut self, insert: bool) {
+		self.replace_freed_as_insert = insert;
+	}
+
+	/// Sync the containing directory when the table file is created.
+	pub fn set_sync_directory(&mut self, sync: bool) {
+		self.file.sync_dir = sync;
 	}
 
 	pub fn value_size(&self, key: &TableKey) -> Option<u16> {
-		let base = self.entry_size - SIZE_SIZE as u16 - self.ref_size() as u16;
+		let base = self.entry_size -
+			SIZE_SIZE as u16 -
+			self.ref_size() as u16 -
+			self.cipher_overhead() as u16;
 		let k_encoded = key.encoded_size() as u16;
 		if base < k_encoded {
 			None
@@ -431,18 +1245,53 @@ impl ValueTable {
 	fn for_parts(
 		&self,
 		key: &mut TableKeyQuery,
+		index: u64,
+		log: &impl LogQuery,
+		f: impl FnMut(&[u8]) -> bool,
+	) -> Result<(u32, bool)> {
+		// Buffers are initialized once and reused, so that reads don't pay for clearing a large
+		// buffer. Without `reuse_read_buffer` the buffer has the maximum entry size.
+		let size =
+			if self.reuse_read_buffer { self.entry_size as usize } else { MAX_ENTRY_BUF_SIZE };
+		// The buffer is taken out while in use, so that nested reads on the same thread get their
+		// own buffer. Buffers for large entries are not kept.
+		let mut data = if size <= MAX_ENTRY_BUF_SIZE {
+			READ_BUFFER.with(|buffer| std::mem::take(&mut *buffer.borrow_mut()))
+		} else {
+			Vec::new()
+		};
+		if data.len() < size {
+			data.resize(size, 0);
+		}
+		let mut buf = Entry::new(&mut data[..size]);
+		let result = self.for_parts_with(&mut buf, key, index, log, f);
+		if data.len() <= MAX_ENTRY_BUF_SIZE {
+			READ_BUFFER.with(|buffer| {
+				let mut buffer = buffer.borrow_mut();
+				if buffer.len() < data.len() {
+					*buffer = data;
+				}
+			});
+		}
+		result
+	}
+
+	#[inline(always)]
+	fn for_parts_with<B: AsRef<[u8]> + AsMut<[u8]>>(
+		&self,
+		buf: &mut Entry<B>,
+		key: &mut TableKeyQuery,
 		mut index: u64,
 		log: &impl LogQuery,
 		mut f: impl FnMut(&[u8]) -> bool,
 	) -> Result<(u32, bool)> {
-		let mut buf = FullEntry::new_uninit_full_entry();
 		let mut part = 0;
 		let mut compressed = false;
 		let mut rc = 1;
 		let entry_size = self.entry_size as usize;
 		loop {
 			let buf = if log.value(self.id, index, buf.as_mut()) {
-				&mut buf
+				&mut *buf
 			} else {
 				log::trace!(
 					target: "parity-db",
@@ -451,7 +1300,8 @@ impl ValueTable {
 					index,
 				);
 				self.file.read_at(&mut buf[0..entry_size], index * self.entry_size as u64)?;
-				&mut buf
+				self.verify_checksum(index, buf)?;
+				&mut *buf
 			};
 
 			buf.set_offset(0);
@@ -514,6 +1364,17 @@ impl ValueTable {
 				)))
 			}
 
+			if next != 0 && self.prefetch_depth > 0 {
+				// Only the next part address is known. Entries after it are hinted as well, as
+				// parts are often allocated next to each other.
+				if let Err(e) = self.file.prefetch(
+					next * entry_size as u64,
+					self.prefetch_depth as u64 * entry_size as u64,
+				) {
+					log::debug!(target: "parity-db", "{}: Prefetch error: {:?}", self.id, e);
+				}
+			}
+
 			if !f(buf.remaining_to(entry_end)) {
 				break
 			};
@@ -542,9 +1403,67 @@ impl ValueTable {
 		}
 	}
 
+	/// Get values for a batch of keys and indices, with results in request order. Requests are
+	/// served in index order, and runs of adjacent entries are read from the file with a single
+	/// read into a buffer shared by the batch. Only the first entry of each value is read this
+	/// way; further parts of multipart values are read one by one, as in `get`.
+	pub fn get_many(
+		&self,
+		keys: &[(TableKey, u64)],
+		log: &impl LogQuery,
+	) -> Result<Vec<Option<(Value, bool)>>> {
+		let filled = self.filled.load(Ordering::Relaxed);
+		let entry_size = self.entry_size as usize;
+		let mut order: Vec<usize> = (0..keys.len()).collect();
+		order.sort_unstable_by_key(|i| keys[*i].1);
+		let mut values = vec![None; keys.len()];
+		let mut data = Vec::new();
+		let mut next = 0;
+		while next < order.len() {
+			let start = keys[order[next]].1;
+			let run = next;
+			next += 1;
+			if start == 0 || start >= filled {
+				values[order[run]] = self.get(&keys[order[run]].0, start, log)?;
+				continue
+			}
+			let mut end = start + 1;
+			while let Some(&i) = order.get(next) {
+				let index = keys[i].1;
+				if index > end ||
+					index >= filled || (index + 1 - start) as usize * entry_size > MAX_READ_BLOCK
+				{
+					break
+				}
+				end = index + 1;
+				next += 1;
+			}
+			if end == start + 1 {
+				for i in &order[run..next] {
+					values[*i] = self.get(&keys[*i].0, start, log)?;
+				}
+				continue
+			}
+			data.resize((end - start) as usize * entry_size, 0);
+			self.file.read_at(&mut data, start * entry_size as u64)?;
+			for (n, chunk) in data.chunks_exact_mut(entry_size).enumerate() {
+				let index = start + n as u64;
+				// Entries in the log are taken from the log.
+				if !log.value(self.id, index, &mut []) {
+					self.verify_checksum(index, &mut Entry::new(chunk))?;
+				}
+			}
+			let block = ReadBlock { log, id: self.id, start, entry_size, data: &data };
+			for i in &order[run..next] {
+				values[*i] = self.get(&keys[*i].0, keys[*i].1, &block)?;
+			}
+		}
+		Ok(values)
+	}
+
 	pub fn dump_entry(&self, index: u64) -> Result<Vec<u8>> {
 		let entry_size = self.entry_size as usize;
-		let mut buf = FullEntry::new_uninit_full_entry();
+		let mut buf = FullEntry::new_full_entry();
 		self.file.read_at(&mut buf[0..entry_size], index * self.entry_size as u64)?;
 		Ok(buf[0..entry_size].to_vec())
 	}
@@ -561,7 +1480,7 @@ impl ValueTable {
 			true
 		})?;
 		if rc > 0 {
-			return Ok(Some((result, compressed, rc)))
+			return Ok(Some((self.decrypt(result)?, compressed, rc)))
 		}
 		Ok(None)
 	}
@@ -594,7 +1513,7 @@ impl ValueTable {
 				true
 			})?;
 		if rc > 0 {
-			return Ok(Some((result, compressed)))
+			return Ok(Some((result - self.cipher_overhead() as u32, compressed)))
 		}
 		Ok(None)
 	}
@@ -623,7 +1542,7 @@ impl ValueTable {
 	}
 
 	pub fn is_tombstone(&self, index: u64, log: &impl LogQuery) -> Result<bool> {
-		let mut buf = PartialKeyEntry::new_uninit();
+		let mut buf = PartialKeyEntry::new_zeroed();
 		let buf = if log.value(self.id, index, buf.as_mut()) {
 			&mut buf
 		} else {
@@ -633,8 +1552,8 @@ impl ValueTable {
 		Ok(buf.is_tombstone())
 	}
 
-	pub fn read_next_free(&self, index: u64, log: &LogWriter) -> Result<u64> {
-		let mut buf = PartialEntry::new_uninit();
+	pub fn read_next_free(&self, index: u64, log: &impl LogQuery) -> Result<u64> {
+		let mut buf = PartialEntry::new_zeroed();
 		let filled = self.filled.load(Ordering::Relaxed);
 		if !log.value(self.id, index, buf.as_mut()) {
 			self.file.read_at(buf.as_mut(), index * self.entry_size as u64)?;
@@ -650,9 +1569,35 @@ impl ValueTable {
 		Ok(next)
 	}
 
-	pub fn read_next_part(&self, index: u64, log: &LogWriter) -> Result<Option<u64>> {
-		let mut buf = PartialEntry::new_uninit();
-		if !log.value(self.id, index, buf.as_mut()) {
+	/// Iterate removed entries, starting from the most recently removed one.
+	pub fn tombstoned_slots<'a, L: LogQuery>(&'a self, log: &'a L) -> TombstonedSlots<'a, L> {
+		TombstonedSlots {
+			table: self,
+			log,
+			next: self.last_removed.load(Ordering::Relaxed),
+			remaining: self.filled.load(Ordering::Relaxed),
+		}
+	}
+
+	/// Check if a removed entry still holds bytes of the value it used to contain.
+	pub fn has_residual_data(&self, index: u64, log: &impl LogQuery) -> Result<bool> {
+		let entry_size = self.entry_size as usize;
+		let mut buf = FullEntry::new_full_entry();
+		self.file.read_at(&mut buf[0..entry_size], index * self.entry_size as u64)?;
+		// Removal may not be enacted yet.
+		log.value(self.id, index, &mut buf[0..SIZE_SIZE + INDEX_SIZE]);
+		if !buf.is_tombstone() {
+			return Err(crate::error::Error::InvalidInput(format!(
+				"{}: Slot {} is not removed",
+				self.id, index
+			)))
+		}
+		Ok(buf[SIZE_SIZE + INDEX_SIZE..entry_size].iter().any(|b| *b != 0))
+	}
+
+	pub fn read_next_part(&self, index: u64, log: &impl LogQuery) -> Result<Option<u64>> {
+		let mut buf = PartialEntry::new_zeroed();
+		if !log.value(self.id, index, buf.as_mut()) {
 			self.file.read_at(buf.as_mut(), index * self.entry_size as u64)?;
 		}
 		if self.multipart && buf.is_multi(self.db_version) {
@@ -664,9 +1609,17 @@ impl ValueTable {
 	}
 
 	pub fn next_free(&self, log: &mut LogWriter) -> Result<u64> {
+		if let Some(quarantine) = &self.quarantine {
+			let mut quarantine = quarantine.lock();
+			while quarantine.slots.front().is_some_and(|(_, at)| *at <= quarantine.allocations) {
+				let (index, _) = quarantine.slots.pop_front().expect("Checked above");
+				self.link_free(index, log);
+			}
+		}
 		let filled = self.filled.load(Ordering::Relaxed);
 		let last_removed = self.last_removed.load(Ordering::Relaxed);
-		let index = if last_removed != 0 {
+		// Append only tables never have removed entries.
+		let index = if last_removed != 0 && !self.append_only {
 			let next_removed = self.read_next_free(last_removed, log)?;
 			log::trace!(
 				target: "parity-db",
@@ -674,7 +1627,18 @@ impl ValueTable {
 				self.id,
 				last_removed,
 			);
+			if let Some(free_list) = &self.free_list {
+				let mut free_list = free_list.lock();
+				let head = free_list.slots.last().copied();
+				if head != Some(last_removed) {
+					return Err(crate::error::Error::Corruption(format!(
+						"Free list mismatch. Expected {last_removed}, got {head:?}"
+					)))
+				}
+				free_list.slots.pop();
+			}
 			self.last_removed.store(next_removed, Ordering::Relaxed);
+			self.removed.fetch_sub(1, Ordering::Relaxed);
 			last_removed
 		} else {
 			log::trace!(
@@ -683,13 +1647,79 @@ impl ValueTable {
 				self.id,
 				filled,
 			);
+			self.reserve_slots(1)?;
 			self.filled.store(filled + 1, Ordering::Relaxed);
 			filled
 		};
+		if let Some(quarantine) = &self.quarantine {
+			quarantine.lock().allocations += 1;
+		}
 		self.dirty_header.store(true, Ordering::Relaxed);
+		#[cfg(test)]
+		self.allocated.lock().insert(index, false);
 		Ok(index)
 	}
 
+	// Grow the file to fit `count` more new entries. This is done before taking any slots, so that
+	// running out of space leaves the table as it was.
+	fn reserve_slots(&self, count: u64) -> Result<()> {
+		let needed = self.filled.load(Ordering::Relaxed) + count;
+		while needed > self.file.capacity.load(Ordering::Relaxed) {
+			self.file.grow(self.entry_size)?;
+		}
+		Ok(())
+	}
+
+	/// Return slots allocated by this table instance that were neither freed nor are part of a
+	/// live value, in ascending order.
+	#[cfg(test)]
+	pub fn leaked_slots(&self, log: &impl LogQuery) -> Result<Vec<u64>> {
+		let mut unreachable = self.allocated.lock().clone();
+		let filled = self.filled.load(Ordering::Relaxed);
+		for index in 1..filled {
+			if unreachable.is_empty() {
+				break
+			}
+			let mut buf = PartialEntry::new_zeroed();
+			if !log.value(self.id, index, buf.as_mut()) {
+				self.file.read_at(buf.as_mut(), index * self.entry_size as u64)?;
+			}
+			if buf.is_tombstone() {
+				continue
+			}
+			if self.multipart && buf.is_multi(self.db_version) {
+				if !(buf.is_multihead() || (self.db_version <= 4 && buf.is_multihead_v4())) {
+					// Continuation of another value.
+					continue
+				}
+			} else if buf.read_size().0 == 0 && unreachable.get(&index) == Some(&false) {
+				// Never written. Can't be told apart from an empty value by the contents.
+				continue
+			}
+			let mut part = index;
+			for _ in 0..filled {
+				unreachable.remove(&part);
+				match self.read_next_part(part, log)? {
+					Some(next) if next != 0 => part = next,
+					_ => break,
+				}
+			}
+		}
+		let mut leaked: Vec<u64> = unreachable.into_keys().collect();
+		leaked.sort_unstable();
+		Ok(leaked)
+	}
+
+	/// Panic if any slot allocated by this table instance was leaked. Intended to be called after
+	/// each commit. Checked slots are forgotten, so that the next check only covers slots
+	/// allocated since.
+	#[cfg(test)]
+	pub fn assert_no_leaked_slots(&self, log: &impl LogQuery) {
+		let leaked = self.leaked_slots(log).expect("Error checking for leaked slots");
+		assert!(leaked.is_empty(), "{}: Leaked slots {:?}", self.id, leaked);
+		self.allocated.lock().clear();
+	}
+
 	fn overwrite_chain(
 		&self,
 		key: &TableKey,
@@ -698,10 +1728,23 @@ impl ValueTable {
 		at: Option<u64>,
 		compressed: bool,
 	) -> Result<u64> {
+		self.check_value_len(key, value.len())?;
+		let encrypted;
+		let value = if let Some(cipher) = &self.cipher {
+			encrypted = cipher.encrypt(value);
+			&encrypted[..]
+		} else {
+			value
+		};
 		let mut remainder = value.len() + self.ref_size() + key.encoded_size();
+		let part_len = self.entry_size as usize - SIZE_SIZE - INDEX_SIZE;
+		if self.multipart && at.is_none() {
+			// Room for the whole chain, in case no part can reuse a slot.
+			self.reserve_slots(remainder.div_ceil(part_len) as u64)?;
+		}
+		self.logical_writes.fetch_add(1, Ordering::Relaxed);
 		let mut offset = 0;
 		let mut start = 0;
-		assert!(self.multipart || value.len() <= self.value_size(key).unwrap() as usize);
 		let (mut index, mut follow) = match at {
 			Some(index) => (index, true),
 			None => (self.next_free(log)?, false),
@@ -716,6 +1759,11 @@ impl ValueTable {
 					},
 					None => {
 						follow = false;
+						if self.multipart && remainder > self.entry_size as usize - SIZE_SIZE {
+							// The existing chain ends here. Room for the rest of the value, before
+							// any new part is allocated.
+							self.reserve_slots(remainder.div_ceil(part_len) as u64 - 1)?;
+						}
 					},
 				}
 			}
@@ -726,7 +1774,7 @@ impl ValueTable {
 				index,
 				key,
 			);
-			let mut buf = FullEntry::new_uninit_full_entry();
+			let mut buf = FullEntry::new_full_entry();
 			let free_space = self.entry_size as usize - SIZE_SIZE;
 			let value_len = if remainder > free_space {
 				if !follow {
@@ -759,6 +1807,11 @@ impl ValueTable {
 			buf.write_slice(&value[offset..offset + value_len - written]);
 			offset += value_len - written;
 			log.insert_value(self.id, index, buf[0..buf.offset()].to_vec());
+			self.count_entry_write(buf.offset());
+			#[cfg(test)]
+			if let Some(written) = self.allocated.lock().get_mut(&index) {
+				*written = true;
+			}
 			remainder -= value_len;
 			if start == 0 {
 				start = index;
@@ -792,7 +1845,12 @@ impl ValueTable {
 	}
 
 	fn clear_slot(&self, index: u64, log: &mut LogWriter) -> Result<()> {
-		let last_removed = self.last_removed.load(Ordering::Relaxed);
+		if self.append_only {
+			return Err(crate::error::Error::InvalidInput(format!(
+				"{}: Can't free slot {} in an append only table",
+				self.id, index
+			)))
+		}
 		log::trace!(
 			target: "parity-db",
 			"{}: Freeing slot {}",
@@ -800,14 +1858,69 @@ impl ValueTable {
 			index,
 		);
 
-		let mut buf = PartialEntry::new_uninit();
+		#[cfg(test)]
+		self.allocated.lock().remove(&index);
+		self.count_entry_write(self.tombstone_len());
+		self.removed.fetch_add(1, Ordering::Relaxed);
+		if let Some(quarantine) = &self.quarantine {
+			// Mark the slot removed now, it is added to the removed entries list on release.
+			let mut quarantine = quarantine.lock();
+			log.insert_value(self.id, index, self.tombstone(0));
+			let release = quarantine.allocations + quarantine.grace;
+			quarantine.slots.push_back((index, release));
+			// The header is unchanged, but the saved count is not.
+			self.dirty_header.store(true, Ordering::Relaxed);
+			return Ok(())
+		}
+		self.link_free(index, log);
+		Ok(())
+	}
+
+	// Removed entry linking to `next`. With `secure_erase` it covers the whole entry, so that the
+	// old value is overwritten when the removal is enacted.
+	fn tombstone(&self, next: u64) -> Vec<u8> {
+		let mut buf = PartialEntry::new_zeroed();
 		buf.write_tombstone();
-		buf.write_next(last_removed);
+		buf.write_next(next);
+		let mut data = buf[0..buf.offset()].to_vec();
+		if self.secure_erase {
+			data.resize(self.entry_size as usize, 0);
+		}
+		data
+	}
+
+	// Size of a removed entry in the log.
+	fn tombstone_len(&self) -> usize {
+		if self.secure_erase {
+			self.entry_size as usize
+		} else {
+			SIZE_SIZE + INDEX_SIZE
+		}
+	}
 
-		log.insert_value(self.id, index, buf[0..buf.offset()].to_vec());
+	fn count_entry_write(&self, len: usize) {
+		self.entry_writes.fetch_add(1, Ordering::Relaxed);
+		self.bytes_written.fetch_add(len as u64, Ordering::Relaxed);
+	}
+
+	/// Return the write amplification counters and reset them to zero. These are kept in memory
+	/// only, the column moves them to its persisted stats.
+	pub fn take_write_counters(&self) -> WriteCounters {
+		WriteCounters {
+			logical_writes: self.logical_writes.swap(0, Ordering::Relaxed),
+			entry_writes: self.entry_writes.swap(0, Ordering::Relaxed),
+			bytes_written: self.bytes_written.swap(0, Ordering::Relaxed),
+		}
+	}
+
+	fn link_free(&self, index: u64, log: &mut LogWriter) {
+		let last_removed = self.last_removed.load(Ordering::Relaxed);
+		log.insert_value(self.id, index, self.tombstone(last_removed));
+		if let Some(free_list) = &self.free_list {
+			free_list.lock().slots.push(index);
+		}
 		self.last_removed.store(index, Ordering::Relaxed);
 		self.dirty_header.store(true, Ordering::Relaxed);
-		Ok(())
 	}
 
 	pub fn write_insert_plan(
@@ -820,6 +1933,13 @@ impl ValueTable {
 		self.overwrite_chain(key, value, log, None, compressed)
 	}
 
+	/// Replace the value at `index`. The new value starts at `index`, so its address does not
+	/// change. The chain is extended with new entries when the new value is larger and shortened
+	/// when it is smaller. The reference counter is reset to 1. Returns the value index.
+	///
+	/// A freed slot at `index` is part of the free list and can't be overwritten. The value is
+	/// then inserted at a new index if `replace_freed_as_insert` is set, and
+	/// `Error::ReplaceTombstone` is returned otherwise.
 	pub fn write_replace_plan(
 		&self,
 		index: u64,
@@ -827,9 +1947,27 @@ impl ValueTable {
 		value: &[u8],
 		log: &mut LogWriter,
 		compressed: bool,
-	) -> Result<()> {
-		self.overwrite_chain(key, value, log, Some(index), compressed)?;
-		Ok(())
+	) -> Result<u64> {
+		if self.is_tombstone(index, log)? {
+			if !self.replace_freed_as_insert {
+				return Err(crate::error::Error::ReplaceTombstone(format!(
+					"{}: Slot {} is free",
+					self.id, index
+				)))
+			}
+			log::debug!(target: "parity-db", "{}: Replacing freed slot {} with an insert", self.id, index);
+			return self.overwrite_chain(key, value, log, None, compressed)
+		}
+		self.overwrite_chain(key, value, log, Some(index), compressed)
+	}
+
+	/// Slots of the value at `index`, in chain order.
+	pub fn chain_slots(&self, index: u64, log: &impl LogQuery) -> Result<Vec<u64>> {
+		let mut slots = vec![index];
+		while let Some(next) = self.read_next_part(*slots.last().expect("Not empty"), log)? {
+			slots.push(next);
+		}
+		Ok(slots)
 	}
 
 	pub fn write_remove_plan(&self, index: u64, log: &mut LogWriter) -> Result<()> {
@@ -855,7 +1993,7 @@ impl ValueTable {
 	}
 
 	pub fn change_ref(&self, index: u64, delta: i32, log: &mut LogWriter) -> Result<bool> {
-		let mut buf = FullEntry::new_uninit_full_entry();
+		let mut buf = FullEntry::new_full_entry();
 		let buf = if log.value(self.id, index, buf.as_mut()) {
 			&mut buf
 		} else {
@@ -899,40 +2037,173 @@ impl ValueTable {
 		Ok(true)
 	}
 
-	pub fn enact_plan(&self, index: u64, log: &mut LogReader) -> Result<()> {
-		while index >= self.file.capacity.load(Ordering::Relaxed) {
-			self.file.grow(self.entry_size)?;
+	// Write an entry at `index`, recording its checksum when checksums are enabled.
+	fn write_entry(&self, index: u64, data: &[u8]) -> Result<()> {
+		self.file.write_at(data, index * self.entry_size as u64)?;
+		if let Some(checksums) = &self.checksums {
+			if index != 0 {
+				checksums.write().record(index, data)?;
+			}
+		}
+		Ok(())
+	}
+
+	// Check an entry read from the file against its recorded checksum.
+	fn verify_checksum<B: AsRef<[u8]> + AsMut<[u8]>>(
+		&self,
+		index: u64,
+		buf: &mut Entry<B>,
+	) -> Result<()> {
+		let Some(checksums) = &self.checksums else { return Ok(()) };
+		let len = self.enacted_len(buf).min(self.entry_size as usize);
+		buf.set_offset(0);
+		if !checksums.read().verify(index, &buf[0..len]) {
+			return Err(crate::error::Error::Corruption(format!(
+				"{}: Checksum mismatch in slot {}",
+				self.id, index
+			)))
 		}
+		Ok(())
+	}
+
+	pub fn enact_plan(&self, index: u64, log: &mut LogReader) -> Result<()> {
+		self.prepare_enact(index)?;
 		if index == 0 {
 			let mut header = Header::default();
 			log.read(&mut header.0)?;
-			self.file.write_at(&header.0, 0)?;
-			log::trace!(target: "parity-db", "{}: Enacted header, {} filled", self.id, header.filled());
-			return Ok(())
+			return self.enact_header(&header)
 		}
 
-		let mut buf = FullEntry::new_uninit_full_entry();
+		let mut buf = FullEntry::new_full_entry();
 		log.read(&mut buf[0..SIZE_SIZE])?;
 		if buf.is_tombstone() {
-			log.read(&mut buf[SIZE_SIZE..SIZE_SIZE + INDEX_SIZE])?;
-			self.file
-				.write_at(&buf[0..SIZE_SIZE + INDEX_SIZE], index * (self.entry_size as u64))?;
+			let len = self.tombstone_len();
+			log.read(&mut buf[SIZE_SIZE..len])?;
+			self.write_entry(index, &buf[0..len])?;
 			log::trace!(target: "parity-db", "{}: Enacted tombstone in slot {}", self.id, index);
 		} else if self.multipart && buf.is_multi(self.db_version) {
 			let entry_size = self.entry_size as usize;
 			log.read(&mut buf[SIZE_SIZE..entry_size])?;
-			self.file.write_at(&buf[0..entry_size], index * (entry_size as u64))?;
+			self.write_entry(index, &buf[0..entry_size])?;
 			log::trace!(target: "parity-db", "{}: Enacted multipart in slot {}", self.id, index);
 		} else {
 			let (len, _compressed) = buf.read_size();
 			log.read(&mut buf[SIZE_SIZE..SIZE_SIZE + len as usize])?;
-			self.file
-				.write_at(&buf[0..(SIZE_SIZE + len as usize)], index * (self.entry_size as u64))?;
+			self.write_entry(index, &buf[0..(SIZE_SIZE + len as usize)])?;
 			log::trace!(target: "parity-db", "{}: Enacted {}: {}, {} bytes", self.id, index, hex(&buf.1[6..32]), len);
 		}
 		Ok(())
 	}
 
+	/// Read the entry for `index` from the log without writing it. The entry is written later with
+	/// `enact_entries`.
+	pub fn read_plan(&self, index: u64, log: &mut LogReader) -> Result<Vec<u8>> {
+		if index == 0 {
+			let mut header = Header::default();
+			log.read(&mut header.0)?;
+			return Ok(header.0.to_vec())
+		}
+		let mut buf = FullEntry::new_full_entry();
+		log.read(&mut buf[0..SIZE_SIZE])?;
+		let len = self.enacted_len(&mut buf);
+		log.read(&mut buf[SIZE_SIZE..len])?;
+		Ok(buf[0..len].to_vec())
+	}
+
+	/// Write entries read with `read_plan`, in order. Same as enacting them with `enact_plan`.
+	pub fn enact_entries(&self, entries: &[(u64, Vec<u8>)]) -> Result<()> {
+		for (index, data) in entries {
+			self.prepare_enact(*index)?;
+			if *index == 0 {
+				let mut header = Header::default();
+				header.0.copy_from_slice(data);
+				self.enact_header(&header)?;
+			} else {
+				self.write_entry(*index, data)?;
+				log::trace!(target: "parity-db", "{}: Enacted slot {}, {} bytes", self.id, index, data.len());
+			}
+		}
+		Ok(())
+	}
+
+	fn prepare_enact(&self, index: u64) -> Result<()> {
+		while index >= self.file.capacity.load(Ordering::Relaxed) {
+			self.file.grow(self.entry_size)?;
+		}
+		Ok(())
+	}
+
+	fn enact_header(&self, header: &Header) -> Result<()> {
+		self.file.write_at(&header.0, 0)?;
+		// Headers replayed from the log after a restart have no count. The saved count then does
+		// not match the header and the removed entries are counted on open.
+		let removed = {
+			let mut counts = self.removed_counts.lock();
+			counts
+				.iter()
+				.position(|(h, _)| h.0 == header.0)
+				.and_then(|pos| counts.drain(..=pos).next_back())
+				.map(|(_, removed)| removed)
+		};
+		if let Some(removed) = removed {
+			self.file
+				.write_at(&header.encode_removed(removed), REMOVED_COUNT_OFFSET as u64)?;
+		}
+		self.file.sync_header()?;
+		log::trace!(target: "parity-db", "{}: Enacted header, {} filled", self.id, header.filled());
+		Ok(())
+	}
+
+	// Number of bytes of a value entry that are written to the file on enactment. Only the size
+	// field of `buf` is used.
+	fn enacted_len<B: AsRef<[u8]> + AsMut<[u8]>>(&self, buf: &mut Entry<B>) -> usize {
+		buf.set_offset(0);
+		if buf.is_tombstone() {
+			self.tombstone_len()
+		} else if self.multipart && buf.is_multi(self.db_version) {
+			self.entry_size as usize
+		} else {
+			SIZE_SIZE + buf.read_size().0 as usize
+		}
+	}
+
+	/// Check that an enacted log entry matches the data on disk.
+	pub fn verify_plan(&self, index: u64, log: &mut LogReader) -> Result<()> {
+		let mut buf = FullEntry::new_full_entry();
+		let len = if index == 0 {
+			let len = std::mem::size_of::<Header>();
+			log.read(&mut buf[0..len])?;
+			len
+		} else {
+			log.read(&mut buf[0..SIZE_SIZE])?;
+			let len = self.enacted_len(&mut buf);
+			log.read(&mut buf[SIZE_SIZE..len])?;
+			len
+		};
+		let mut written = FullEntry::new_full_entry();
+		self.file.read_uncached(&mut written[0..len], index * self.entry_size as u64)?;
+		if buf[0..len] != written[0..len] {
+			return Err(crate::error::Error::Corruption(format!(
+				"{}: Slot {} does not match the enacted log entry",
+				self.id, index
+			)))
+		}
+		log::trace!(target: "parity-db", "{}: Verified slot {}", self.id, index);
+		Ok(())
+	}
+
+	pub fn skip_plan(&self, index: u64, log: &mut LogReader) -> Result<()> {
+		let mut buf = FullEntry::new_full_entry();
+		if index == 0 {
+			log.read(&mut buf[0..std::mem::size_of::<Header>()])?;
+		} else {
+			log.read(&mut buf[0..SIZE_SIZE])?;
+			let len = self.enacted_len(&mut buf);
+			log.read(&mut buf[SIZE_SIZE..len])?;
+		}
+		Ok(())
+	}
+
 	pub fn validate_plan(&self, index: u64, log: &mut LogReader) -> Result<()> {
 		if index == 0 {
 			let mut header = Header::default();
@@ -940,10 +2211,10 @@ impl ValueTable {
 			// TODO: sanity check last_removed and filled
 			return Ok(())
 		}
-		let mut buf = FullEntry::new_uninit_full_entry();
+		let mut buf = FullEntry::new_full_entry();
 		log.read(&mut buf[0..SIZE_SIZE])?;
 		if buf.is_tombstone() {
-			log.read(&mut buf[SIZE_SIZE..SIZE_SIZE + INDEX_SIZE])?;
+			log.read(&mut buf[SIZE_SIZE..self.tombstone_len()])?;
 			log::trace!(target: "parity-db", "{}: Validated tombstone in slot {}", self.id, index);
 		} else if self.multipart && buf.is_multi(self.db_version) {
 			let entry_size = self.entry_size as usize;
@@ -971,9 +2242,18 @@ impl ValueTable {
 		}
 		self.last_removed.store(last_removed, Ordering::Relaxed);
 		self.filled.store(filled, Ordering::Relaxed);
-		Ok(())
+		if self.free_list.is_some() {
+			self.rebuild_free_list()?;
+		}
+		match self.read_removed_count(&header)? {
+			Some(removed) => self.removed.store(removed, Ordering::Relaxed),
+			None => self.count_removed()?,
+		}
+		self.recover_quarantined()
 	}
 
+	/// Add the table header to the log if it was changed by the plan. The header is written to
+	/// the file when the log record is enacted, and synced as set with `set_durability`.
 	pub fn complete_plan(&self, log: &mut LogWriter) -> Result<()> {
 		if let Ok(true) =
 			self.dirty_header
@@ -986,14 +2266,151 @@ impl ValueTable {
 			buf.set_last_removed(last_removed);
 			buf.set_filled(filled);
 			log.insert_value(self.id, 0, buf.0.to_vec());
+			if has_removed_count(self.entry_size) {
+				let removed = self.removed.load(Ordering::Relaxed);
+				self.removed_counts.lock().push_back((buf, removed));
+			}
 		}
 		Ok(())
 	}
 
 	pub fn flush(&self) -> Result<()> {
+		if let Some(checksums) = &self.checksums {
+			checksums.read().flush()?;
+		}
 		self.file.flush()
 	}
 
+	/// Add the header of this table to `manifest`. Must only be called when all changes are
+	/// enacted. Tables that have no file yet are skipped.
+	pub fn write_manifest_entry(&self, manifest: &mut TableManifest) {
+		if self.file.file.read().is_none() {
+			return
+		}
+		let entry = ManifestEntry {
+			entry_size: self.entry_size,
+			db_version: self.db_version,
+			last_removed: self.last_removed.load(Ordering::Relaxed),
+			filled: self.filled.load(Ordering::Relaxed),
+			removed: self.removed.load(Ordering::Relaxed),
+		};
+		manifest.tables.insert(self.id, entry);
+	}
+
+	/// Save the compact free list. Must only be called when all changes are enacted.
+	pub fn persist_free_list(&self) -> Result<()> {
+		if let Some(free_list) = &self.free_list {
+			if !self.is_init() {
+				return Ok(())
+			}
+			let mut header = Header::default();
+			header.set_last_removed(self.last_removed.load(Ordering::Relaxed));
+			header.set_filled(self.filled.load(Ordering::Relaxed));
+			free_list.lock().save(&header)?;
+		}
+		Ok(())
+	}
+
+	/// Number of removed entries, including slots held back from reuse by the slot reuse grace
+	/// period. The count is updated as entries are removed and reused, and saved with the table
+	/// header and in the table manifest. When neither saved count matches the header, it is
+	/// rebuilt when the table is opened, from the compact free list if it is enabled and by
+	/// walking the removed entries otherwise.
+	pub fn free_count(&self) -> u64 {
+		self.removed.load(Ordering::Relaxed)
+	}
+
+	// Removed entries count saved in entry 0 along with `header`, if any.
+	fn read_removed_count(&self, header: &Header) -> Result<Option<u64>> {
+		if !has_removed_count(self.entry_size) {
+			return Ok(None)
+		}
+		let mut buf = [0u8; REMOVED_COUNT_SIZE];
+		self.file.read_at(&mut buf, REMOVED_COUNT_OFFSET as u64)?;
+		Ok(header.decode_removed(&buf))
+	}
+
+	// Rebuild the removed entries count. The walk is bounded by `filled`.
+	fn count_removed(&self) -> Result<()> {
+		let removed = match &self.free_list {
+			Some(free_list) => free_list.lock().slots.len() as u64,
+			None => self.count_free_refs(|_| ())?,
+		};
+		self.removed.store(removed, Ordering::Relaxed);
+		Ok(())
+	}
+
+	// Same as `for_free_refs`, but a damaged removed entries list is only logged. Entries up to
+	// the bad link are counted, so that the table can still be opened.
+	fn count_free_refs(&self, mut f: impl FnMut(u64)) -> Result<u64> {
+		let mut len = 0;
+		let result = self.for_free_refs(|index| {
+			len += 1;
+			f(index)
+		});
+		match result {
+			Ok(len) => Ok(len),
+			Err(crate::error::Error::Corruption(e)) => {
+				log::warn!(target: "parity-db", "{}: Can't count removed entries: {}", self.id, e);
+				Ok(len)
+			},
+			Err(e) => Err(e),
+		}
+	}
+
+	/// Free slots in allocation order, if the compact free list is enabled.
+	#[cfg(test)]
+	pub fn free_slots(&self) -> Option<Vec<u64>> {
+		self.free_list.as_ref().map(|l| l.lock().slots.iter().rev().copied().collect())
+	}
+
+	/// Estimated number of bytes that compacting the table would reclaim. This is the size of all
+	/// free slots. Unused space at the end of occupied entries is not tracked and not included.
+	pub fn reclaimable_bytes(&self) -> u64 {
+		self.free_count() * self.entry_size as u64
+	}
+
+	/// Occupancy counters for the table. Free slots are counted with `free_count`.
+	pub fn stats(&self) -> TableStats {
+		let filled = self.filled.load(Ordering::Relaxed);
+		let free = self.free_count();
+		let capacity = self.file.capacity.load(Ordering::Relaxed);
+		let entry_size = self.entry_size as u64;
+		TableStats {
+			entry_size: self.entry_size,
+			filled,
+			last_removed: self.last_removed.load(Ordering::Relaxed),
+			capacity,
+			free,
+			used_bytes: filled.saturating_sub(1 + free) * entry_size,
+			allocated_bytes: capacity * entry_size,
+			grow_entries: self.file.grow_entries(self.entry_size),
+		}
+	}
+
+	fn rebuild_free_list(&self) -> Result<()> {
+		if let Some(free_list) = &self.free_list {
+			let mut slots = Vec::new();
+			if self.is_init() {
+				self.for_free_refs(|index| slots.push(index))?;
+			}
+			slots.reverse();
+			log::debug!(target: "parity-db", "{}: Rebuilt free list, {} slots", self.id, slots.len());
+			free_list.lock().slots = slots;
+		}
+		Ok(())
+	}
+
+	// Number of entries needed to store an entry payload of `len` bytes.
+	fn chain_depth(&self, len: usize) -> usize {
+		let free_space = self.entry_size as usize - SIZE_SIZE;
+		if len <= free_space {
+			1
+		} else {
+			1 + (len - free_space).div_ceil(free_space - INDEX_SIZE)
+		}
+	}
+
 	fn ref_size(&self) -> usize {
 		if self.ref_counted {
 			REFS_SIZE
@@ -1002,6 +2419,37 @@ impl ValueTable {
 		}
 	}
 
+	/// Number of entries a value of `len` bytes spans when it is written.
+	pub fn entries_for(&self, key: &TableKey, len: usize) -> u64 {
+		if !self.multipart {
+			return 1
+		}
+		let remainder = len + self.cipher_overhead() + self.ref_size() + key.encoded_size();
+		let free_space = self.entry_size as usize - SIZE_SIZE;
+		// All parts but the last one end with a link to the next part.
+		if remainder <= free_space {
+			1
+		} else {
+			(remainder - free_space).div_ceil(free_space - INDEX_SIZE) as u64 + 1
+		}
+	}
+
+	fn cipher_overhead(&self) -> usize {
+		if self.cipher.is_some() {
+			encrypt::OVERHEAD
+		} else {
+			0
+		}
+	}
+
+	fn decrypt(&self, value: Vec<u8>) -> Result<Vec<u8>> {
+		match &self.cipher {
+			Some(cipher) => cipher.decrypt(value),
+			None => Ok(value),
+		}
+	}
+
+	#[cfg(test)]
 	pub fn iter_while(
 		&self,
 		log: &impl LogQuery,
@@ -1009,29 +2457,117 @@ impl ValueTable {
 	) -> Result<()> {
 		let filled = self.filled.load(Ordering::Relaxed);
 		for index in 1..filled {
-			let mut result = Vec::new();
-			// expect only indexed key.
-			let mut _fetch_key = Default::default();
-			match self.for_parts(
-				&mut TableKeyQuery::Fetch(Some(&mut _fetch_key)),
-				index,
-				log,
-				|buf| {
-					result.extend_from_slice(buf);
-					true
-				},
-			) {
-				Ok((rc, compressed)) =>
-					if rc > 0 && !f(index, rc, result, compressed) {
-						break
-					},
-				Err(crate::error::Error::InvalidValueData) => (), // ignore, can be external index.
-				Err(e) => return Err(e),
+			if let Some((rc, value, compressed)) = self.iter_entry(index, log)? {
+				if !f(index, rc, value, compressed) {
+					break
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Same as `iter_while`, but safe to use while other threads commit and enact changes.
+	///
+	/// Values are reassembled under the overlay read lock, `ITER_BATCH` slots at a time. While it
+	/// is held no record can be added to or cleared from the overlay, and entries enacted to disk
+	/// stay shadowed by their overlay copy, so a multipart chain is never observed half updated.
+	/// `f` is called with the lock released.
+	pub fn iter_snapshot_while(
+		&self,
+		log: &RwLock<LogOverlays>,
+		mut f: impl FnMut(u64, u32, Vec<u8>, bool) -> bool,
+	) -> Result<()> {
+		let filled = self.filled.load(Ordering::Relaxed);
+		let mut hinted = 0;
+		let mut batch = Vec::with_capacity(ITER_BATCH as usize);
+		let mut start = 1;
+		while start < filled {
+			let batch_end = (start + ITER_BATCH).min(filled);
+			{
+				let overlays = log.read();
+				for index in start..batch_end {
+					self.read_ahead(index, filled, &mut hinted);
+					if let Some(entry) = self.iter_entry(index, &*overlays)? {
+						batch.push((index, entry));
+					}
+				}
 			}
+			for (index, (rc, value, compressed)) in batch.drain(..) {
+				if !f(index, rc, value, compressed) {
+					return Ok(())
+				}
+			}
+			start = batch_end;
 		}
 		Ok(())
 	}
 
+	/// Same as `iter_snapshot_while`, but entries that can not be read are passed to `f` as errors
+	/// and iteration continues.
+	pub fn iter_snapshot_checked_while(
+		&self,
+		log: &RwLock<LogOverlays>,
+		mut f: impl FnMut(u64, Result<(u32, Vec<u8>, bool)>) -> bool,
+	) {
+		let filled = self.filled.load(Ordering::Relaxed);
+		let mut hinted = 0;
+		let mut batch = Vec::with_capacity(ITER_BATCH as usize);
+		let mut start = 1;
+		while start < filled {
+			let batch_end = (start + ITER_BATCH).min(filled);
+			{
+				let overlays = log.read();
+				for index in start..batch_end {
+					self.read_ahead(index, filled, &mut hinted);
+					match self.iter_entry(index, &*overlays) {
+						Ok(Some(entry)) => batch.push((index, Ok(entry))),
+						Ok(None) => (),
+						Err(e) => batch.push((index, Err(e))),
+					}
+				}
+			}
+			for (index, entry) in batch.drain(..) {
+				if !f(index, entry) {
+					return
+				}
+			}
+			start = batch_end;
+		}
+	}
+
+	// Keep up to `iter_read_ahead` entries in front of `index` hinted for read ahead. Hints are
+	// issued for half a window at a time, once the cursor has consumed half of the hinted
+	// entries. `hinted` is the end of the range hinted so far.
+	fn read_ahead(&self, index: u64, end: u64, hinted: &mut u64) {
+		let window = self.iter_read_ahead as u64;
+		if window == 0 || *hinted >= end || index + window / 2 < *hinted {
+			return
+		}
+		let from = (*hinted).max(index);
+		let to = (index + window).min(end);
+		let entry_size = self.entry_size as u64;
+		if let Err(e) = self.file.prefetch(from * entry_size, (to - from) * entry_size) {
+			log::debug!(target: "parity-db", "{}: Prefetch error: {:?}", self.id, e);
+		}
+		*hinted = to;
+	}
+
+	fn iter_entry(&self, index: u64, log: &impl LogQuery) -> Result<Option<(u32, Vec<u8>, bool)>> {
+		let mut result = Vec::new();
+		// expect only indexed key.
+		let mut _fetch_key = Default::default();
+		match self.for_parts(&mut TableKeyQuery::Fetch(Some(&mut _fetch_key)), index, log, |buf| {
+			result.extend_from_slice(buf);
+			true
+		}) {
+			Ok((rc, compressed)) if rc > 0 => Ok(Some((rc, self.decrypt(result)?, compressed))),
+			Ok(_) => Ok(None),
+			// ignore, can be external index.
+			Err(crate::error::Error::InvalidValueData) => Ok(None),
+			Err(e) => Err(e),
+		}
+	}
+
 	pub fn is_init(&self) -> bool {
 		self.file.file.read().is_some()
 	}
@@ -1056,13 +2592,47 @@ impl ValueTable {
 		let log = log.drain();
 		let change = log.local_values_changes(self.id).expect("entry written above");
 		for (at, (_rec_id, entry)) in change.map.iter() {
-			self.file.write_at(entry.as_slice(), *at * (self.entry_size as u64))?;
+			self.write_entry(*at, entry.as_slice())?;
 		}
 		Ok(())
 	}
 
+	/// Walk the removed entries list from the most recently removed entry and report the first
+	/// link that points past the filled entries or back to an already visited entry. The head
+	/// link from the table header is reported with index 0.
+	pub fn verify_free_list(&self, log: &impl LogQuery) -> Result<FreeListReport> {
+		let filled = self.filled.load(Ordering::Relaxed);
+		let mut visited = std::collections::HashSet::new();
+		let mut index = 0;
+		let mut next = self.last_removed.load(Ordering::Relaxed);
+		let mut len = 0;
+		while next != 0 {
+			if next >= filled {
+				let anomaly = FreeListAnomaly::OutOfBounds { index, next };
+				return Ok(FreeListReport { len, anomaly: Some(anomaly) })
+			}
+			if !visited.insert(next) {
+				let anomaly = FreeListAnomaly::Cycle { index, next };
+				return Ok(FreeListReport { len, anomaly: Some(anomaly) })
+			}
+			len += 1;
+			index = next;
+			let mut buf = PartialEntry::new_zeroed();
+			if !log.value(self.id, index, buf.as_mut()) {
+				self.file.read_at(buf.as_mut(), index * self.entry_size as u64)?;
+			}
+			buf.skip_size();
+			next = buf.read_next();
+		}
+		Ok(FreeListReport { len, anomaly: None })
+	}
+
 	/// Validate free records sequence.
 	pub fn check_free_refs(&self) -> Result<u64> {
+		self.for_free_refs(|_| ())
+	}
+
+	fn for_free_refs(&self, mut f: impl FnMut(u64)) -> Result<u64> {
 		let filled = self.filled.load(Ordering::Relaxed);
 		let mut next = self.last_removed.load(Ordering::Relaxed);
 		let mut len = 0;
@@ -1073,7 +2643,14 @@ impl ValueTable {
 					next, filled
 				)))
 			}
-			let mut buf = PartialEntry::new_uninit();
+			if len >= filled {
+				return Err(crate::error::Error::Corruption(format!(
+					"{}: Removed entries list loops",
+					self.id
+				)))
+			}
+			f(next);
+			let mut buf = PartialEntry::new_zeroed();
 			self.file.read_at(buf.as_mut(), next * self.entry_size as u64)?;
 			buf.skip_size();
 			next = buf.read_next();
@@ -1083,8 +2660,76 @@ impl ValueTable {
 	}
 }
 
+pub struct TombstonedSlots<'a, L> {
+	table: &'a ValueTable,
+	log: &'a L,
+	next: u64,
+	remaining: u64,
+}
+
+impl<'a, L: LogQuery> Iterator for TombstonedSlots<'a, L> {
+	type Item = Result<u64>;
+
+	fn next(&mut self) -> Option<Result<u64>> {
+		if self.next == 0 {
+			return None
+		}
+		let index = self.next;
+		if self.remaining == 0 {
+			self.next = 0;
+			return Some(Err(crate::error::Error::Corruption(format!(
+				"{}: Removed entries list loops at {}",
+				self.table.id, index
+			))))
+		}
+		self.remaining -= 1;
+		match self.table.read_next_free(index, self.log) {
+			Ok(next) => self.next = next,
+			Err(e) => {
+				self.next = 0;
+				return Some(Err(e))
+			},
+		}
+		Some(Ok(index))
+	}
+}
+
+/// Entries read from the file in one block, served like log entries. The log takes precedence.
+struct ReadBlock<'a, L> {
+	log: &'a L,
+	id: TableId,
+	start: u64,
+	entry_size: usize,
+	data: &'a [u8],
+}
+
+impl<'a, L: LogQuery> LogQuery for ReadBlock<'a, L> {
+	fn with_index<R, F: FnOnce(&crate::index::Chunk) -> R>(
+		&self,
+		table: crate::index::TableId,
+		index: u64,
+		f: F,
+	) -> Option<R> {
+		self.log.with_index(table, index, f)
+	}
+
+	fn value(&self, table: TableId, index: u64, dest: &mut [u8]) -> bool {
+		if self.log.value(table, index, dest) {
+			return true
+		}
+		if table != self.id || index < self.start {
+			return false
+		}
+		let offset = (index - self.start) as usize * self.entry_size;
+		let Some(entry) = self.data.get(offset..offset + self.entry_size) else { return false };
+		let len = dest.len().min(self.entry_size);
+		dest[0..len].copy_from_slice(&entry[0..len]);
+		true
+	}
+}
+
 pub mod key {
-	use super::FullEntry;
+	use super::{Entry, FullEntry};
 	use crate::{Key, Result};
 
 	pub const PARTIAL_SIZE: usize = 26;
@@ -1118,9 +2763,11 @@ pub mod key {
 			}
 		}
 
-		pub fn fetch_partial(buf: &mut FullEntry) -> Result<[u8; PARTIAL_SIZE]> {
+		pub fn fetch_partial<B: AsRef<[u8]> + AsMut<[u8]>>(
+			buf: &mut Entry<B>,
+		) -> Result<[u8; PARTIAL_SIZE]> {
 			let mut result = [0u8; PARTIAL_SIZE];
-			if buf.1.len() >= PARTIAL_SIZE {
+			if buf.1.as_ref().len() >= PARTIAL_SIZE {
 				let pks = buf.read_partial();
 				result.copy_from_slice(pks);
 				return Ok(result)
@@ -1128,7 +2775,10 @@ pub mod key {
 			Err(crate::error::Error::InvalidValueData)
 		}
 
-		pub fn fetch(&self, buf: &mut FullEntry) -> Result<Option<[u8; PARTIAL_SIZE]>> {
+		pub fn fetch<B: AsRef<[u8]> + AsMut<[u8]>>(
+			&self,
+			buf: &mut Entry<B>,
+		) -> Result<Option<[u8; PARTIAL_SIZE]>> {
 			match self {
 				TableKey::Partial(_k) => Ok(Some(Self::fetch_partial(buf)?)),
 				TableKey::NoHash => Ok(None),
@@ -1164,19 +2814,28 @@ pub mod key {
 mod test {
 	const ENTRY_SIZE: u16 = 64;
 
-	use super::{TableId, Value, ValueTable, MULTIPART_ENTRY_SIZE};
+	use super::{
+		TableConfig, TableId, Value, ValueTable, INDEX_SIZE, MAX_ENTRY_SIZE, MULTIPART_ENTRY_SIZE,
+		REFS_SIZE, SIZE_SIZE,
+	};
 	use crate::{
+		file::Durability,
 		log::{Log, LogAction, LogWriter},
 		options::{ColumnOptions, Options, CURRENT_VERSION},
-		table::key::TableKey,
+		table::key::{TableKey, TableKeyQuery},
 		Key,
 	};
 	use std::sync::{atomic::Ordering, Arc};
 	use tempfile::{tempdir, TempDir};
 
+	#[cfg(feature = "bench")]
+	use test::Bencher;
+	#[cfg(feature = "bench")]
+	extern crate test;
+
 	fn new_table(dir: &TempDir, size: Option<u16>, options: &ColumnOptions) -> ValueTable {
 		let id = TableId::new(0, 0);
-		ValueTable::open(Arc::new(dir.path().to_path_buf()), id, size, options, CURRENT_VERSION)
+		ValueTable::open(Arc::new(dir.path().to_path_buf()), id, size, options, Default::default())
 			.unwrap()
 	}
 
@@ -1209,6 +2868,19 @@ mod test {
 				},
 			}
 		}
+		table.assert_no_leaked_slots(log.overlays());
+	}
+
+	// Insert `value` for each of `keys` in a single record. Returns the index of each value.
+	fn insert_all(table: &ValueTable, log: &Log, keys: &[TableKey], value: &[u8]) -> Vec<u64> {
+		let mut indices = Vec::with_capacity(keys.len());
+		write_ops(table, log, |writer| {
+			for key in keys {
+				indices.push(table.write_insert_plan(key, value, writer, false).unwrap());
+			}
+			table.complete_plan(writer).unwrap();
+		});
+		indices
 	}
 
 	fn key(k: u32) -> Key {
@@ -1263,6 +2935,166 @@ mod test {
 		assert_eq!(table.filled.load(Ordering::Relaxed), 2);
 	}
 
+	#[test]
+	fn compact_overlay() {
+		let dir = tempdir().unwrap();
+		let table = new_table(&dir, Some(ENTRY_SIZE), &Default::default());
+		let log = new_log(&dir);
+		let key = &TableKey::Partial(key(1));
+
+		let mut latest = Vec::new();
+		for i in 0..10 {
+			latest = value(10 + i);
+			let mut writer = log.begin_record();
+			if i == 0 {
+				table.write_insert_plan(key, &latest, &mut writer, false).unwrap();
+			} else {
+				table.write_replace_plan(1, key, &latest, &mut writer, false).unwrap();
+			}
+			table.complete_plan(&mut writer).unwrap();
+			log.end_record(writer.drain()).unwrap();
+		}
+		// Only the header and the latest version of the slot are kept.
+		assert_eq!(log.compact_overlay(table.id), 2);
+		assert_eq!(table.get(key, 1, log.overlays()).unwrap(), Some((latest.clone(), false)));
+
+		log.flush_one(0).unwrap();
+		while let Some(mut reader) = log.read_next(false).unwrap() {
+			loop {
+				match reader.next().unwrap() {
+					LogAction::InsertValue(insertion) =>
+						table.enact_plan(insertion.index, &mut reader).unwrap(),
+					LogAction::EndRecord => break,
+					_ => panic!("Unexpected log entry"),
+				}
+			}
+			let record_id = reader.record_id();
+			log.end_read(reader.drain(), record_id);
+		}
+		// The drained overlay is dropped when the log is enacted.
+		assert!(log.overlays().read().value_overlay(table.id).is_none());
+		assert_eq!(log.compact_overlay(table.id), 0);
+		assert_eq!(table.get(key, 1, log.overlays()).unwrap(), Some((latest, false)));
+	}
+
+	#[test]
+	fn reclaimable_bytes() {
+		for options in [
+			ColumnOptions::default(),
+			ColumnOptions { compact_free_list: true, ..Default::default() },
+		] {
+			let dir = tempdir().unwrap();
+			let table = new_table(&dir, Some(ENTRY_SIZE), &options);
+			let log = new_log(&dir);
+			assert_eq!(table.reclaimable_bytes(), 0);
+
+			write_ops(&table, &log, |writer| {
+				for k in 0..10 {
+					table
+						.write_insert_plan(&simple_key(key(k)), &value(19), writer, false)
+						.unwrap();
+				}
+			});
+			write_ops(&table, &log, |writer| {
+				for index in [2, 5, 7] {
+					table.write_remove_plan(index, writer).unwrap();
+				}
+				assert_eq!(table.reclaimable_bytes(), 3 * ENTRY_SIZE as u64);
+			});
+			assert_eq!(table.reclaimable_bytes(), 3 * ENTRY_SIZE as u64);
+
+			write_ops(&table, &log, |writer| {
+				table
+					.write_insert_plan(&simple_key(key(10)), &value(19), writer, false)
+					.unwrap();
+			});
+			assert_eq!(table.reclaimable_bytes(), 2 * ENTRY_SIZE as u64);
+		}
+	}
+
+	#[test]
+	fn max_chain_depth() {
+		let dir = tempdir().unwrap();
+		let mut table = new_table(&dir, None, &Default::default());
+		table.set_max_chain_depth(Some(3));
+		let log = new_log(&dir);
+		let key1 = &TableKey::Partial(key(1));
+		let key2 = &TableKey::Partial(key(2));
+		// Three entries: two with a NEXT link and the last one with a size.
+		let free_space = MULTIPART_ENTRY_SIZE as usize - 2;
+		let max_len = 2 * (free_space - 8) + free_space - key1.encoded_size();
+
+		let val = value(max_len);
+		write_ops(&table, &log, |writer| {
+			table.write_insert_plan(key1, &val, writer, false).unwrap();
+		});
+		let mut parts = 1;
+		let mut index = 1;
+		while let Some(next) = table.read_next_part(index, log.overlays()).unwrap() {
+			parts += 1;
+			index = next;
+		}
+		assert_eq!(parts, 3);
+		assert_eq!(table.get(key1, 1, log.overlays()).unwrap(), Some((val.clone(), false)));
+
+		let filled = table.filled.load(Ordering::Relaxed);
+		write_ops(&table, &log, |writer| {
+			let err = table.write_insert_plan(key2, &value(max_len + 1), writer, false);
+			assert!(matches!(err, Err(crate::Error::InvalidInput(_))));
+			let err = table.write_replace_plan(1, key1, &value(max_len + 1), writer, false);
+			assert!(matches!(err, Err(crate::Error::InvalidInput(_))));
+		});
+		assert_eq!(table.filled.load(Ordering::Relaxed), filled);
+		assert_eq!(table.get(key1, 1, log.overlays()).unwrap(), Some((val, false)));
+
+		table.set_max_chain_depth(None);
+		write_ops(&table, &log, |writer| {
+			table.write_insert_plan(key2, &value(max_len + 1), writer, false).unwrap();
+		});
+	}
+
+	#[test]
+	fn value_too_large() {
+		let dir = tempdir().unwrap();
+		let table = new_table(&dir, Some(ENTRY_SIZE), &rc_options());
+		let log = new_log(&dir);
+		let key1 = &simple_key(key(1));
+		let max = table.value_size(key1).unwrap() as usize;
+		write_ops(&table, &log, |writer| {
+			table.write_insert_plan(key1, &value(max), writer, false).unwrap();
+			let err = table.write_insert_plan(key1, &value(max + 1), writer, false);
+			assert!(matches!(
+				err,
+				Err(crate::Error::ValueTooLarge { max: m, got }) if m == max && got == max + 1
+			));
+			let err = table.write_replace_plan(1, key1, &value(max + 1), writer, false);
+			assert!(matches!(err, Err(crate::Error::ValueTooLarge { .. })));
+		});
+		assert_eq!(table.filled.load(Ordering::Relaxed), 2);
+
+		// Sizes stay clear of the entry markers in the largest single part tables.
+		let dir = tempdir().unwrap();
+		let table = new_table(&dir, Some(MAX_ENTRY_SIZE as u16), &rc_options());
+		let log = new_log(&dir);
+		let max = MAX_ENTRY_SIZE - SIZE_SIZE - REFS_SIZE - key1.encoded_size();
+		write_ops(&table, &log, |writer| {
+			table.write_insert_plan(key1, &value(max), writer, false).unwrap();
+			let err = table.write_insert_plan(key1, &value(max + 1), writer, false);
+			assert!(matches!(err, Err(crate::Error::ValueTooLarge { max: m, .. }) if m == max));
+		});
+
+		// Multipart values can be limited as well.
+		let dir = tempdir().unwrap();
+		let mut table = new_table(&dir, None, &rc_options());
+		table.set_max_value_len(Some(10_000));
+		let log = new_log(&dir);
+		write_ops(&table, &log, |writer| {
+			table.write_insert_plan(key1, &value(10_000), writer, false).unwrap();
+			let err = table.write_insert_plan(key1, &value(10_001), writer, false);
+			assert!(matches!(err, Err(crate::Error::ValueTooLarge { max: 10_000, got: 10_001 })));
+		});
+	}
+
 	#[test]
 	#[should_panic(expected = "assertion failed: entry_size <= MAX_ENTRY_SIZE as u16")]
 	fn oversized_into_fixed_panics() {
@@ -1341,6 +3173,203 @@ mod test {
 		assert_eq!(table.last_removed.load(Ordering::Relaxed), 0);
 	}
 
+	#[test]
+	fn entry_checksums() {
+		let flip = |table: &ValueTable, offset: u64| {
+			let mut byte = [0u8];
+			table.file.read_at(&mut byte, offset).unwrap();
+			table.file.write_at(&[!byte[0]], offset).unwrap();
+		};
+		for entry_size in [Some(ENTRY_SIZE), None] {
+			let dir = tempdir().unwrap();
+			let options = ColumnOptions { entry_checksums: true, ..rc_options() };
+			let table = new_table(&dir, entry_size, &options);
+			let log = new_log(&dir);
+			let len = if entry_size.is_some() { 20 } else { 10000 };
+			let (key1, key2) = (&simple_key(key(1)), &simple_key(key(2)));
+			let (val1, val2) = (value(len), value(len));
+			let (mut index1, mut index2) = (0, 0);
+			write_ops(&table, &log, |writer| {
+				index1 = table.write_insert_plan(key1, &val1, writer, false).unwrap();
+				index2 = table.write_insert_plan(key2, &val2, writer, false).unwrap();
+			});
+			drop(table);
+
+			// Checksums are loaded on open.
+			let table = new_table(&dir, entry_size, &options);
+			let empty = crate::log::LogOverlays::default();
+			assert_eq!(table.get(key1, index1, &empty).unwrap(), Some((val1.clone(), false)));
+			assert_eq!(table.get(key2, index2, &empty).unwrap(), Some((val2.clone(), false)));
+			assert!(dir.path().join(table.id.checksum_file_name()).exists());
+
+			// Damage the last part of the first value and the key of the second one.
+			let mut last = index1;
+			while let Some(next) = table.read_next_part(last, &empty).unwrap() {
+				last = next;
+			}
+			let size = table.entry_size as u64;
+			flip(&table, last * size + 12);
+			let key_offset = if entry_size.is_some() { 2 + 4 } else { 2 + 8 + 4 };
+			flip(&table, index2 * size + key_offset);
+			for (key, index) in [(key1, index1), (key2, index2)] {
+				assert!(matches!(
+					table.get(key, index, &empty),
+					Err(crate::Error::Corruption(e)) if e.contains("Checksum mismatch")
+				));
+			}
+
+			// Rewriting the value records a new checksum.
+			write_ops(&table, &log, |writer| {
+				table.write_replace_plan(index2, key2, &val2, writer, false).unwrap();
+			});
+			assert_eq!(table.get(key2, index2, &empty).unwrap(), Some((val2, false)));
+			write_ops(&table, &log, |writer| {
+				table.write_remove_plan(index2, writer).unwrap();
+			});
+			assert_eq!(table.get(key2, index2, &empty).unwrap(), None);
+		}
+
+		// Without checksums a damaged key reads as a missing value.
+		let dir = tempdir().unwrap();
+		let table = new_table(&dir, Some(ENTRY_SIZE), &rc_options());
+		let log = new_log(&dir);
+		let key1 = &simple_key(key(1));
+		write_ops(&table, &log, |writer| {
+			table.write_insert_plan(key1, &value(20), writer, false).unwrap();
+		});
+		flip(&table, ENTRY_SIZE as u64 + 2 + 4);
+		let empty = crate::log::LogOverlays::default();
+		assert_eq!(table.get(key1, 1, &empty).unwrap(), None);
+	}
+
+	#[test]
+	fn replace_freed() {
+		let dir = tempdir().unwrap();
+		let mut table = new_table(&dir, None, &ColumnOptions::default());
+		let log = new_log(&dir);
+		let key1 = &simple_key(key(1));
+		let key2 = &simple_key(key(2));
+		let val1 = value(5000);
+		let val2 = value(5000);
+		write_ops(&table, &log, |writer| {
+			table.write_insert_plan(key1, &val1, writer, false).unwrap();
+			table.write_insert_plan(key2, &val2, writer, false).unwrap();
+		});
+		write_ops(&table, &log, |writer| {
+			table.write_remove_plan(1, writer).unwrap();
+		});
+		let free = table.last_removed.load(Ordering::Relaxed);
+
+		let val = value(6000);
+		let mut writer = log.begin_record();
+		assert!(matches!(
+			table.write_replace_plan(1, key1, &val, &mut writer, false),
+			Err(crate::error::Error::ReplaceTombstone(_))
+		));
+		drop(writer);
+		assert_eq!(table.last_removed.load(Ordering::Relaxed), free);
+
+		table.set_replace_freed_as_insert(true);
+		let mut index = 0;
+		write_ops(&table, &log, |writer| {
+			index = table.write_replace_plan(1, key1, &val, writer, false).unwrap();
+		});
+		assert_eq!(table.get(key1, index, log.overlays()).unwrap(), Some((val, false)));
+		assert_eq!(table.get(key2, 3, log.overlays()).unwrap(), Some((val2, false)));
+		table.assert_no_leaked_slots(log.overlays());
+	}
+
+	// Exercises value reads and writes through the entry buffers. Run with
+	// `MIRIFLAGS=-Zmiri-disable-isolation cargo +nightly miri test --lib read_write_buffers`.
+	#[test]
+	fn read_write_buffers() {
+		for reuse_read_buffer in [false, true] {
+			let dir = tempdir().unwrap();
+			let table = ValueTable::open(
+				Arc::new(dir.path().to_path_buf()),
+				TableId::new(0, 255),
+				None,
+				&ColumnOptions::default(),
+				TableConfig { reuse_read_buffer, ..Default::default() },
+			)
+			.unwrap();
+			let log = new_log(&dir);
+			let key1 = &simple_key(key(1));
+			let val1 = value(10000);
+			write_ops(&table, &log, |writer| {
+				table.write_insert_plan(key1, &val1, writer, false).unwrap();
+			});
+			assert_eq!(table.get(key1, 1, log.overlays()).unwrap(), Some((val1, false)));
+			let val2 = value(5000);
+			write_ops(&table, &log, |writer| {
+				table.write_replace_plan(1, key1, &val2, writer, false).unwrap();
+			});
+			assert_eq!(table.get(key1, 1, log.overlays()).unwrap(), Some((val2, false)));
+		}
+	}
+
+	#[test]
+	fn entry_boundaries() {
+		for options in [ColumnOptions::default(), rc_options()] {
+			// Fixed size entries: values up to the exact capacity fit in a single entry.
+			let dir = tempdir().unwrap();
+			let table = new_table(&dir, Some(ENTRY_SIZE), &options);
+			let log = new_log(&dir);
+			let table_key = TableKey::Partial(key(1));
+			let capacity = table.value_size(&table_key).unwrap() as usize;
+			assert_eq!(
+				capacity + table.ref_size() + table_key.encoded_size(),
+				ENTRY_SIZE as usize - SIZE_SIZE
+			);
+			for len in [capacity - 1, capacity] {
+				let val: Vec<u8> = (0..len).map(|i| i as u8).collect();
+				let mut index = 0;
+				write_ops(&table, &log, |writer| {
+					index = table.write_insert_plan(&table_key, &val, writer, false).unwrap();
+				});
+				assert_eq!(
+					table.get(&table_key, index, log.overlays()).unwrap(),
+					Some((val, false))
+				);
+			}
+
+			// Multipart entries: a head or middle part holds `part` bytes, the last part up to
+			// `single` bytes. Multipart tables only hold values that don't fit in a single entry.
+			let dir = tempdir().unwrap();
+			let table = new_table(&dir, None, &options);
+			let log = new_log(&dir);
+			let single = MULTIPART_ENTRY_SIZE as usize - SIZE_SIZE;
+			let part = single - INDEX_SIZE;
+			let parts = |index: u64| {
+				let mut parts = 1;
+				let mut next = index;
+				while let Some(n) = table.read_next_part(next, log.overlays()).unwrap() {
+					parts += 1;
+					next = n;
+				}
+				parts
+			};
+			for (total, expected_parts) in [
+				(single + 1, 2),
+				(part + single, 2),
+				(part + single + 1, 3),
+				(part * 2 + single, 3),
+				(part * 2 + single + 1, 4),
+			] {
+				assert_eq!(table.chain_depth(total), expected_parts);
+				let len = total - table.ref_size() - table_key.encoded_size();
+				let val: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+				let mut index = 0;
+				write_ops(&table, &log, |writer| {
+					index = table.write_insert_plan(&table_key, &val, writer, false).unwrap();
+				});
+				assert_eq!(parts(index), expected_parts);
+				let fetched = table.get(&table_key, index, log.overlays()).unwrap();
+				assert!(fetched == Some((val, false)), "Mismatch for {} bytes", total);
+			}
+		}
+	}
+
 	#[test]
 	fn replace_multipart_shorter() {
 		replace_multipart_shorter_inner(&Default::default());
@@ -1501,6 +3530,1809 @@ mod test {
 		}
 	}
 
+	#[test]
+	fn out_of_space() {
+		let dir = tempdir().unwrap();
+		let mut table = new_table(&dir, Some(ENTRY_SIZE), &rc_options());
+		table.set_grow_step(ENTRY_SIZE.into());
+		let log = new_log(&dir);
+		write_ops(&table, &log, |writer| {
+			table.write_insert_plan(&simple_key(key(1)), &value(20), writer, false).unwrap();
+		});
+		let state = |table: &ValueTable| {
+			(
+				table.filled.load(Ordering::Relaxed),
+				table.last_removed.load(Ordering::Relaxed),
+				table.file.capacity.load(Ordering::Relaxed),
+			)
+		};
+		let before = state(&table);
+		assert_eq!(before.0, before.2);
+
+		table.file.fail_grow.store(true, Ordering::Relaxed);
+		let mut writer = log.begin_record();
+		let result = table.write_insert_plan(&simple_key(key(2)), &value(20), &mut writer, false);
+		assert!(matches!(result, Err(crate::error::Error::OutOfSpace(_))));
+		assert_eq!(state(&table), before);
+		drop(writer);
+
+		table.file.fail_grow.store(false, Ordering::Relaxed);
+		let mut index = 0;
+		write_ops(&table, &log, |writer| {
+			index =
+				table.write_insert_plan(&simple_key(key(2)), &value(20), writer, false).unwrap();
+		});
+		assert_eq!(index, before.0);
+
+		// A multipart value fails before any part is allocated.
+		let dir = tempdir().unwrap();
+		let mut table = new_table(&dir, None, &rc_options());
+		let log = new_log(&dir);
+		table.set_grow_step(MULTIPART_ENTRY_SIZE.into());
+		let mut index = 0;
+		write_ops(&table, &log, |writer| {
+			index = table
+				.write_insert_plan(&simple_key(key(1)), &value(5000), writer, false)
+				.unwrap();
+		});
+		let before = state(&table);
+		assert_eq!(before.0, before.2);
+		table.file.fail_grow.store(true, Ordering::Relaxed);
+		let mut writer = log.begin_record();
+		let result =
+			table.write_insert_plan(&simple_key(key(2)), &value(10_000), &mut writer, false);
+		assert!(matches!(result, Err(crate::error::Error::OutOfSpace(_))));
+		assert_eq!(state(&table), before);
+		drop(writer);
+
+		// Growing a value fails before any new part is allocated.
+		let mut writer = log.begin_record();
+		let result = table.write_replace_plan(
+			index,
+			&simple_key(key(1)),
+			&value(10_000),
+			&mut writer,
+			false,
+		);
+		assert!(matches!(result, Err(crate::error::Error::OutOfSpace(_))));
+		assert_eq!(state(&table), before);
+		drop(writer);
+
+		// A replace that fits the existing chain needs no new space.
+		write_ops(&table, &log, |writer| {
+			table
+				.write_replace_plan(index, &simple_key(key(1)), &value(4000), writer, false)
+				.unwrap();
+		});
+		assert_eq!(state(&table).2, before.2);
+	}
+
+	#[test]
+	fn multipart_chunk_size() {
+		let dir = tempdir().unwrap();
+		let path = Arc::new(dir.path().to_path_buf());
+		let sizing = super::EntrySizing::Multipart { chunk: 16384 };
+		let table = ValueTable::open(
+			path.clone(),
+			TableId::new(0, 0),
+			sizing,
+			&rc_options(),
+			Default::default(),
+		)
+		.unwrap();
+		let log = new_log(&dir);
+		let key1 = &simple_key(key(1));
+		let key2 = &simple_key(key(2));
+		let part = 16384 - SIZE_SIZE - INDEX_SIZE;
+		let (val1, val2) = (value(3 * part), value(20_000));
+		let (mut index1, mut index2) = (0, 0);
+		write_ops(&table, &log, |writer| {
+			index1 = table.write_insert_plan(key1, &val1, writer, false).unwrap();
+			index2 = table.write_insert_plan(key2, &val2, writer, false).unwrap();
+		});
+		assert_eq!(table.entry_size, 16384);
+		// The first part also holds the reference counter and the key, so the values take 4 and 2
+		// entries after the header.
+		assert_eq!(table.filled.load(Ordering::Relaxed), 7);
+		// Read back from the file.
+		let overlays = crate::log::LogOverlays::default();
+		assert_eq!(table.get(key1, index1, &overlays).unwrap(), Some((val1, false)));
+		assert_eq!(table.get(key2, index2, &overlays).unwrap(), Some((val2, false)));
+		drop(table);
+
+		// The chunk size is stored in the header.
+		let reopen = |sizing: super::EntrySizing| {
+			ValueTable::open(
+				path.clone(),
+				TableId::new(0, 0),
+				sizing,
+				&rc_options(),
+				Default::default(),
+			)
+		};
+		assert!(matches!(reopen(None.into()), Err(crate::error::Error::FormatMismatch(_))));
+		assert!(reopen(sizing).is_ok());
+	}
+
+	#[test]
+	fn write_counters() {
+		let dir = tempdir().unwrap();
+		let table = new_table(&dir, None, &rc_options());
+		let log = new_log(&dir);
+		let key1 = &simple_key(key(1));
+		let part = MULTIPART_ENTRY_SIZE as usize - SIZE_SIZE - INDEX_SIZE;
+		let val = value(4 * part + 10);
+		let mut index = 0;
+		write_ops(&table, &log, |writer| {
+			index = table.write_insert_plan(key1, &val, writer, false).unwrap();
+		});
+		// The header and 5 entries.
+		assert_eq!(table.filled.load(Ordering::Relaxed), 6);
+		let header = super::REFS_SIZE + key1.encoded_size();
+		let inserted = (val.len() + header + 5 * SIZE_SIZE + 4 * INDEX_SIZE) as u64;
+		assert_eq!(
+			table.take_write_counters(),
+			super::WriteCounters { logical_writes: 1, entry_writes: 5, bytes_written: inserted }
+		);
+
+		write_ops(&table, &log, |writer| {
+			table.write_remove_plan(index, writer).unwrap();
+		});
+		assert_eq!(
+			table.take_write_counters(),
+			super::WriteCounters {
+				logical_writes: 0,
+				entry_writes: 5,
+				bytes_written: 5 * (SIZE_SIZE + INDEX_SIZE) as u64,
+			}
+		);
+		assert_eq!(table.take_write_counters(), Default::default());
+	}
+
+	#[test]
+	fn snapshot_iteration_with_concurrent_writes() {
+		let dir = tempdir().unwrap();
+		let table = new_table(&dir, None, &Default::default());
+		let log = new_log(&dir);
+
+		let key = &TableKey::Partial(key(1));
+		let len = MULTIPART_ENTRY_SIZE as usize * 3;
+		write_ops(&table, &log, |writer| {
+			table.write_insert_plan(key, &vec![0u8; len], writer, false).unwrap();
+		});
+
+		let done = std::sync::atomic::AtomicBool::new(false);
+		std::thread::scope(|s| {
+			s.spawn(|| {
+				for i in 1..=200u8 {
+					let mut writer = log.begin_record();
+					table.write_replace_plan(1, key, &vec![i; len], &mut writer, false).unwrap();
+					table.complete_plan(&mut writer).unwrap();
+					log.end_record(writer.drain()).unwrap();
+					log.flush_one(0).unwrap();
+					// The first read reaches the end of the previous log file.
+					let mut reader = match log.read_next(false).unwrap() {
+						Some(reader) => reader,
+						None => log.read_next(false).unwrap().unwrap(),
+					};
+					let record_id = reader.record_id();
+					loop {
+						match reader.next().unwrap() {
+							LogAction::EndRecord => break,
+							LogAction::InsertValue(insertion) => {
+								table.enact_plan(insertion.index, &mut reader).unwrap();
+							},
+							_ => panic!("Unexpected log entry"),
+						}
+					}
+					log.end_read(reader.drain(), record_id);
+				}
+				done.store(true, Ordering::Relaxed);
+			});
+
+			while !done.load(Ordering::Relaxed) {
+				table
+					.iter_snapshot_while(log.overlays(), |_index, _rc, v, _cmpr| {
+						assert_eq!(v.len(), len);
+						assert!(v.iter().all(|b| *b == v[0]), "Torn value");
+						true
+					})
+					.unwrap();
+			}
+		});
+	}
+
+	#[test]
+	fn compact_free_list() {
+		let dir = tempdir().unwrap();
+		let options = ColumnOptions { compact_free_list: true, ..Default::default() };
+		let table = new_table(&dir, Some(ENTRY_SIZE), &options);
+		let log = new_log(&dir);
+		let chain = |table: &ValueTable| {
+			let mut slots = Vec::new();
+			table.for_free_refs(|index| slots.push(index)).unwrap();
+			slots
+		};
+
+		write_ops(&table, &log, |writer| {
+			for k in 0..20 {
+				table
+					.write_insert_plan(&TableKey::Partial(key(k)), &value(10), writer, false)
+					.unwrap();
+			}
+		});
+		write_ops(&table, &log, |writer| {
+			for index in (1..20).step_by(2) {
+				table.write_remove_plan(index, writer).unwrap();
+			}
+		});
+		write_ops(&table, &log, |writer| {
+			for k in 20..25 {
+				table
+					.write_insert_plan(&TableKey::Partial(key(k)), &value(10), writer, false)
+					.unwrap();
+			}
+			table.write_remove_plan(4, writer).unwrap();
+			table.write_remove_plan(20, writer).unwrap();
+			table.complete_plan(writer).unwrap();
+		});
+
+		let expected = chain(&table);
+		assert_eq!(expected.len(), 7);
+		assert_eq!(table.free_count(), 7);
+		assert_eq!(table.free_slots().unwrap(), expected);
+
+		table.persist_free_list().unwrap();
+		drop(table);
+		let table = new_table(&dir, Some(ENTRY_SIZE), &options);
+		assert_eq!(table.free_slots().unwrap(), expected);
+		// Saved list is invalidated once loaded.
+		let free_path = dir.path().join(TableId::new(0, 0).free_list_file_name());
+		assert_eq!(std::fs::metadata(&free_path).unwrap().len(), 0);
+
+		write_ops(&table, &log, |writer| {
+			table
+				.write_insert_plan(&TableKey::Partial(key(30)), &value(10), writer, false)
+				.unwrap();
+			table.write_remove_plan(2, writer).unwrap();
+			table.complete_plan(writer).unwrap();
+		});
+		assert_eq!(table.free_slots().unwrap(), chain(&table));
+
+		// Unclean shutdown: the list is rebuilt from the table.
+		drop(table);
+		let table = new_table(&dir, Some(ENTRY_SIZE), &options);
+		assert_eq!(table.free_count(), 7);
+		assert_eq!(table.free_slots().unwrap(), chain(&table));
+
+		// A mismatched head is reported without taking it off the list.
+		let saved = table.free_slots().unwrap();
+		table.free_list.as_ref().unwrap().lock().slots.push(1000);
+		let mut writer = log.begin_record();
+		for _ in 0..2 {
+			assert!(matches!(
+				table.next_free(&mut writer),
+				Err(crate::error::Error::Corruption(_))
+			));
+		}
+		table.free_list.as_ref().unwrap().lock().slots.pop();
+		assert_eq!(table.free_slots().unwrap(), saved);
+	}
+
+	#[test]
+	fn tombstoned_slots() {
+		let dir = tempdir().unwrap();
+		let table = new_table(&dir, Some(ENTRY_SIZE), &rc_options());
+		let log = new_log(&dir);
+
+		write_ops(&table, &log, |writer| {
+			for k in 0..5 {
+				table
+					.write_insert_plan(&TableKey::Partial(key(k)), &value(20), writer, false)
+					.unwrap();
+			}
+		});
+		write_ops(&table, &log, |writer| {
+			table.write_remove_plan(1, writer).unwrap();
+			table.write_remove_plan(3, writer).unwrap();
+			table.complete_plan(writer).unwrap();
+		});
+		write_ops(&table, &log, |writer| {
+			table.write_remove_plan(4, writer).unwrap();
+			table.complete_plan(writer).unwrap();
+			// Removal is only visible through the log.
+			let slots: Vec<u64> =
+				table.tombstoned_slots(writer).collect::<crate::error::Result<_>>().unwrap();
+			assert_eq!(slots, vec![4, 3, 1]);
+			assert!(table.has_residual_data(4, writer).unwrap());
+		});
+
+		let slots: Vec<u64> = table
+			.tombstoned_slots(log.overlays())
+			.collect::<crate::error::Result<_>>()
+			.unwrap();
+		assert_eq!(slots, vec![4, 3, 1]);
+		for index in &slots {
+			assert!(table.has_residual_data(*index, log.overlays()).unwrap());
+		}
+		assert!(table.has_residual_data(2, log.overlays()).is_err());
+
+		let zeros = vec![0u8; ENTRY_SIZE as usize - super::SIZE_SIZE - super::INDEX_SIZE];
+		table
+			.file
+			.write_at(&zeros, 3 * ENTRY_SIZE as u64 + (super::SIZE_SIZE + super::INDEX_SIZE) as u64)
+			.unwrap();
+		assert!(!table.has_residual_data(3, log.overlays()).unwrap());
+		assert!(table.has_residual_data(1, log.overlays()).unwrap());
+	}
+
+	#[test]
+	fn secure_erase() {
+		let dir = tempdir().unwrap();
+		let options = ColumnOptions { secure_erase: true, ..rc_options() };
+		let table = new_table(&dir, Some(ENTRY_SIZE), &options);
+		let log = new_log(&dir);
+
+		write_ops(&table, &log, |writer| {
+			for k in 0..3 {
+				let val = value(ENTRY_SIZE as usize - 40);
+				table
+					.write_insert_plan(&TableKey::Partial(key(k)), &val, writer, false)
+					.unwrap();
+			}
+		});
+		write_ops(&table, &log, |writer| {
+			table.write_remove_plan(2, writer).unwrap();
+			table.complete_plan(writer).unwrap();
+		});
+		assert!(!table.has_residual_data(2, log.overlays()).unwrap());
+		let entry = table.dump_entry(2).unwrap();
+		assert!(entry[super::SIZE_SIZE + super::INDEX_SIZE..].iter().all(|b| *b == 0));
+		assert!(table.dump_entry(3).unwrap().iter().any(|b| *b != 0));
+
+		// Removed entries are reused as usual.
+		let key3 = &TableKey::Partial(key(3));
+		write_ops(&table, &log, |writer| {
+			assert_eq!(table.write_insert_plan(key3, &value(20), writer, false).unwrap(), 2);
+		});
+		assert_eq!(table.get(key3, 2, log.overlays()).unwrap().map(|(v, _)| v.len()), Some(20));
+	}
+
+	#[test]
+	fn get_many() {
+		let dir = tempdir().unwrap();
+		let table = new_table(&dir, Some(ENTRY_SIZE), &rc_options());
+		let log = new_log(&dir);
+		let values: Vec<_> = (0..20).map(|_| value(20)).collect();
+		write_ops(&table, &log, |writer| {
+			for (k, val) in values.iter().enumerate() {
+				table
+					.write_insert_plan(&TableKey::Partial(key(k as u32)), val, writer, false)
+					.unwrap();
+			}
+		});
+		write_ops(&table, &log, |writer| {
+			table.write_remove_plan(6, writer).unwrap();
+			table.complete_plan(writer).unwrap();
+		});
+
+		// Value `k` is at index `k + 1`.
+		let requests: Vec<_> = [(3, 4), (0, 1), (5, 6), (1, 2), (3, 4), (2, 3), (9, 9), (18, 19)]
+			.into_iter()
+			.map(|(k, index)| (TableKey::Partial(key(k)), index))
+			.collect();
+		let expected = |k: usize| Some((values[k].clone(), false));
+		let results = vec![
+			expected(3),
+			expected(0),
+			None,
+			expected(1),
+			expected(3),
+			expected(2),
+			None,
+			expected(18),
+		];
+		// Removal is only in the log overlay.
+		assert_eq!(table.get_many(&requests, log.overlays()).unwrap(), results);
+
+		let disk = crate::log::LogOverlays::default();
+		table.file.reads.store(0, Ordering::Relaxed);
+		assert_eq!(table.get_many(&requests, &disk).unwrap(), results);
+		// Indices 1 to 4 are read at once, then 6, 9 and 19.
+		assert_eq!(table.file.reads.load(Ordering::Relaxed), 4);
+		for ((key, index), result) in requests.iter().zip(&results) {
+			assert_eq!(&table.get(key, *index, &disk).unwrap(), result);
+		}
+	}
+
+	#[test]
+	fn grow_step() {
+		let dir = tempdir().unwrap();
+		let mut table = new_table(&dir, Some(ENTRY_SIZE), &rc_options());
+		let log = new_log(&dir);
+		let val = value(20);
+		let keys: Vec<_> = (0..20_000).map(|k| simple_key(key(k))).collect();
+		insert_all(&table, &log, &keys[..10_000], &val);
+		let default_grows = table.file.grows.load(Ordering::Relaxed);
+		assert_eq!(table.stats().grow_entries, 4096);
+
+		table.set_grow_step(4 * 1024 * 1024);
+		table.file.grows.store(0, Ordering::Relaxed);
+		insert_all(&table, &log, &keys[10_000..], &val);
+		assert_eq!(table.file.grows.load(Ordering::Relaxed), 1);
+		assert!(default_grows > 1);
+		assert_eq!(table.stats().grow_entries, 65536);
+
+		// Steps smaller than an entry still grow the file.
+		table.set_grow_step(1);
+		assert_eq!(table.stats().grow_entries, 1);
+	}
+
+	#[test]
+	fn append_only() {
+		let dir = tempdir().unwrap();
+		let options = ColumnOptions { append_only: true, ..Default::default() };
+		let table = new_table(&dir, Some(ENTRY_SIZE), &options);
+		let log = new_log(&dir);
+
+		let mut indices = Vec::new();
+		write_ops(&table, &log, |writer| {
+			for k in 0..5 {
+				let index = table
+					.write_insert_plan(&TableKey::Partial(key(k)), &value(20), writer, false)
+					.unwrap();
+				indices.push(index);
+			}
+		});
+		write_ops(&table, &log, |writer| {
+			assert!(matches!(
+				table.write_remove_plan(indices[1], writer),
+				Err(crate::error::Error::InvalidInput(_))
+			));
+		});
+		write_ops(&table, &log, |writer| {
+			for k in 5..10 {
+				let index = table
+					.write_insert_plan(&TableKey::Partial(key(k)), &value(20), writer, false)
+					.unwrap();
+				indices.push(index);
+			}
+		});
+		assert!(indices.windows(2).all(|w| w[0] < w[1]));
+		assert_eq!(table.verify_free_list(log.overlays()).unwrap().len, 0);
+	}
+
+	#[test]
+	fn verify_free_list() {
+		use super::{FreeListAnomaly, FreeListReport};
+		let dir = tempdir().unwrap();
+		let table = new_table(&dir, Some(ENTRY_SIZE), &rc_options());
+		let log = new_log(&dir);
+
+		write_ops(&table, &log, |writer| {
+			for k in 0..5 {
+				table
+					.write_insert_plan(&TableKey::Partial(key(k)), &value(20), writer, false)
+					.unwrap();
+			}
+		});
+		write_ops(&table, &log, |writer| {
+			table.write_remove_plan(1, writer).unwrap();
+			table.write_remove_plan(3, writer).unwrap();
+			table.write_remove_plan(4, writer).unwrap();
+			table.complete_plan(writer).unwrap();
+		});
+		assert_eq!(
+			table.verify_free_list(log.overlays()).unwrap(),
+			FreeListReport { len: 3, anomaly: None }
+		);
+
+		// Links are read from disk, the log still holds the original entries.
+		let empty = crate::log::LogOverlays::default();
+		let set_next = |index: u64, next: u64| {
+			let offset = index * ENTRY_SIZE as u64 + super::SIZE_SIZE as u64;
+			table.file.write_at(&next.to_le_bytes(), offset).unwrap();
+		};
+		set_next(1, 4);
+		assert_eq!(
+			table.verify_free_list(&empty).unwrap(),
+			FreeListReport { len: 3, anomaly: Some(FreeListAnomaly::Cycle { index: 1, next: 4 }) }
+		);
+		set_next(1, 100);
+		assert_eq!(
+			table.verify_free_list(&empty).unwrap(),
+			FreeListReport {
+				len: 3,
+				anomaly: Some(FreeListAnomaly::OutOfBounds { index: 1, next: 100 })
+			}
+		);
+	}
+
+	#[test]
+	fn table_stats() {
+		let dir = tempdir().unwrap();
+		let table = new_table(&dir, Some(ENTRY_SIZE), &rc_options());
+		let log = new_log(&dir);
+		let size = ENTRY_SIZE as u64;
+		let stats = table.stats();
+		assert_eq!(
+			(stats.filled, stats.free, stats.used_bytes, stats.allocated_bytes),
+			(1, 0, 0, 0)
+		);
+
+		write_ops(&table, &log, |writer| {
+			for k in 0..10 {
+				table
+					.write_insert_plan(&TableKey::Partial(key(k)), &value(20), writer, false)
+					.unwrap();
+			}
+		});
+		write_ops(&table, &log, |writer| {
+			for index in [2, 5, 9] {
+				table.write_remove_plan(index, writer).unwrap();
+			}
+			table.complete_plan(writer).unwrap();
+		});
+		let capacity = table.file.capacity.load(Ordering::Relaxed);
+		assert_eq!(
+			table.stats(),
+			super::TableStats {
+				entry_size: ENTRY_SIZE,
+				filled: 11,
+				last_removed: 9,
+				capacity,
+				free: 3,
+				used_bytes: 7 * size,
+				allocated_bytes: capacity * size,
+				grow_entries: 256 * 1024 / size,
+			}
+		);
+	}
+
+	#[test]
+	fn verify_enacted_writes() {
+		let dir = tempdir().unwrap();
+		let table = new_table(&dir, Some(ENTRY_SIZE), &rc_options());
+		let log = new_log(&dir);
+
+		for drop_write in [false, true] {
+			let mut writer = log.begin_record();
+			for k in 0..3 {
+				table
+					.write_insert_plan(&TableKey::Partial(key(k)), &value(20), &mut writer, false)
+					.unwrap();
+			}
+			table.complete_plan(&mut writer).unwrap();
+			log.end_record(writer.drain()).unwrap();
+			let _ = log.read_next(false);
+			log.flush_one(0).unwrap();
+			let mut reader = log.read_next(false).unwrap().unwrap();
+			let mut enacted = 0;
+			loop {
+				match reader.next().unwrap() {
+					LogAction::InsertValue(insertion) => {
+						// Lose the second entry write.
+						enacted += 1;
+						table
+							.file
+							.drop_next_write
+							.store(drop_write && enacted == 2, Ordering::Relaxed);
+						table.enact_plan(insertion.index, &mut reader).unwrap();
+					},
+					LogAction::EndRecord => break,
+					_ => panic!("Unexpected log entry"),
+				}
+			}
+			table.flush().unwrap();
+
+			reader.reset().unwrap();
+			assert!(matches!(reader.next().unwrap(), LogAction::BeginRecord));
+			let mut result = Ok(());
+			loop {
+				match reader.next().unwrap() {
+					LogAction::InsertValue(insertion) =>
+						if let Err(e) = table.verify_plan(insertion.index, &mut reader) {
+							result = Err(e);
+							break
+						},
+					LogAction::EndRecord => break,
+					_ => panic!("Unexpected log entry"),
+				}
+			}
+			assert_eq!(result.is_err(), drop_write);
+		}
+	}
+
+	#[test]
+	fn free_count() {
+		use super::{ManifestEntry, TableManifest};
+
+		let dir = tempdir().unwrap();
+		let id = TableId::new(0, 0);
+		let table = new_table(&dir, Some(ENTRY_SIZE), &rc_options());
+		let log = new_log(&dir);
+		write_ops(&table, &log, |writer| {
+			for k in 0..10 {
+				table
+					.write_insert_plan(&TableKey::Partial(key(k)), &value(20), writer, false)
+					.unwrap();
+			}
+		});
+		assert_eq!(table.free_count(), 0);
+		write_ops(&table, &log, |writer| {
+			for index in [2, 5, 7] {
+				table.write_remove_plan(index, writer).unwrap();
+			}
+			assert_eq!(table.free_count(), 3);
+			table
+				.write_insert_plan(&TableKey::Partial(key(10)), &value(20), writer, false)
+				.unwrap();
+			table.complete_plan(writer).unwrap();
+		});
+		assert_eq!(table.free_count(), 2);
+		assert_eq!(table.reclaimable_bytes(), 2 * ENTRY_SIZE as u64);
+		let mut manifest = TableManifest::default();
+		table.write_manifest_entry(&mut manifest);
+		assert_eq!(manifest.tables[&id].removed, 2);
+		drop(table);
+
+		let open = |manifest: Option<&TableManifest>| {
+			ValueTable::open(
+				Arc::new(dir.path().to_path_buf()),
+				id,
+				Some(ENTRY_SIZE),
+				&rc_options(),
+				TableConfig { manifest, ..Default::default() },
+			)
+			.unwrap()
+			.free_count()
+		};
+		// Rebuilt from the removed entries without a manifest.
+		assert_eq!(open(None), 2);
+		// Saved count is used as is, unless it does not match the header.
+		let entry = manifest.tables[&id];
+		let mut manifest = TableManifest::default();
+		manifest.tables.insert(id, ManifestEntry { removed: 1, ..entry });
+		assert_eq!(open(Some(&manifest)), 1);
+		manifest.tables.insert(id, ManifestEntry { removed: 0, ..entry });
+		assert_eq!(open(Some(&manifest)), 2);
+		manifest.tables.insert(id, ManifestEntry { removed: 100, ..entry });
+		assert_eq!(open(Some(&manifest)), 2);
+	}
+
+	#[test]
+	fn free_count_in_header() {
+		let dir = tempdir().unwrap();
+		let id = TableId::new(0, 0);
+		let table = new_table(&dir, Some(ENTRY_SIZE), &rc_options());
+		let log = new_log(&dir);
+		write_ops(&table, &log, |writer| {
+			for k in 0..10 {
+				table
+					.write_insert_plan(&TableKey::Partial(key(k)), &value(20), writer, false)
+					.unwrap();
+			}
+			table.complete_plan(writer).unwrap();
+		});
+		write_ops(&table, &log, |writer| {
+			for index in [2, 5, 7] {
+				table.write_remove_plan(index, writer).unwrap();
+			}
+			table.complete_plan(writer).unwrap();
+		});
+		// Point the head of the removed entries list past the filled entries.
+		let next_offset = 7 * ENTRY_SIZE as u64 + SIZE_SIZE as u64;
+		table.file.write_at(&1000u64.to_le_bytes(), next_offset).unwrap();
+		drop(table);
+
+		let open = || {
+			ValueTable::open(
+				Arc::new(dir.path().to_path_buf()),
+				id,
+				Some(ENTRY_SIZE),
+				&rc_options(),
+				Default::default(),
+			)
+			.unwrap()
+		};
+		// The saved count is used without walking the removed entries.
+		let table = open();
+		assert_eq!(table.free_count(), 3);
+		// Without it the removed entries are counted up to the bad link.
+		table.file.write_at(&[0; 12], 32).unwrap();
+		table.refresh_metadata().unwrap();
+		assert_eq!(table.free_count(), 1);
+		drop(table);
+		assert_eq!(open().free_count(), 1);
+	}
+
+	#[test]
+	fn open_from_manifest() {
+		use super::{ManifestEntry, TableManifest};
+
+		let dir = tempdir().unwrap();
+		let id = TableId::new(0, 0);
+		let table = new_table(&dir, Some(ENTRY_SIZE), &rc_options());
+		let log = new_log(&dir);
+		write_ops(&table, &log, |writer| {
+			for k in 0..10 {
+				table
+					.write_insert_plan(&TableKey::Partial(key(k)), &value(20), writer, false)
+					.unwrap();
+			}
+			table.write_remove_plan(4, writer).unwrap();
+			table.complete_plan(writer).unwrap();
+		});
+		drop(table);
+		let open = |manifest: Option<&TableManifest>| {
+			let table = ValueTable::open(
+				Arc::new(dir.path().to_path_buf()),
+				id,
+				Some(ENTRY_SIZE),
+				&rc_options(),
+				TableConfig { manifest, ..Default::default() },
+			)
+			.unwrap();
+			(table.filled.load(Ordering::Relaxed), table.last_removed.load(Ordering::Relaxed))
+		};
+
+		// Different from the file header, to tell where the values came from.
+		let entry = ManifestEntry {
+			entry_size: ENTRY_SIZE,
+			db_version: CURRENT_VERSION,
+			last_removed: 3,
+			filled: 9,
+			removed: 1,
+		};
+		let manifest = TableManifest { tables: [(id, entry)].into_iter().collect() };
+		assert_eq!(TableManifest::load(dir.path()).unwrap(), None);
+		manifest.save(dir.path()).unwrap();
+		let loaded = TableManifest::load(dir.path()).unwrap().unwrap();
+		assert_eq!(loaded, manifest);
+		assert_eq!(open(Some(&loaded)), (9, 3));
+
+		// Loading leaves the marker. Once cleared, the manifest is not used again.
+		assert_eq!(TableManifest::load(dir.path()).unwrap(), Some(loaded));
+		TableManifest::invalidate(dir.path()).unwrap();
+		assert_eq!(TableManifest::load(dir.path()).unwrap(), None);
+		assert_eq!(open(None), (11, 4));
+
+		// Entries saved with a different layout are ignored.
+		let other = ManifestEntry { entry_size: ENTRY_SIZE + 1, ..entry };
+		let manifest = TableManifest { tables: [(id, other)].into_iter().collect() };
+		assert_eq!(open(Some(&manifest)), (11, 4));
+
+		// A marker left from an older manifest is not trusted.
+		manifest.save(dir.path()).unwrap();
+		let marker = std::fs::read(dir.path().join(super::CLEAN_SHUTDOWN_FILE)).unwrap();
+		TableManifest { tables: [(id, entry)].into_iter().collect() }
+			.save(dir.path())
+			.unwrap();
+		std::fs::write(dir.path().join(super::CLEAN_SHUTDOWN_FILE), marker).unwrap();
+		assert_eq!(TableManifest::load(dir.path()).unwrap(), None);
+	}
+
+	#[test]
+	fn read_header() {
+		let dir = tempdir().unwrap();
+		let id = TableId::new(0, 0);
+		assert!(ValueTable::read_header(dir.path(), id).is_err());
+
+		let table = new_table(&dir, Some(ENTRY_SIZE), &rc_options());
+		let log = new_log(&dir);
+		write_ops(&table, &log, |writer| {
+			for k in 0..12 {
+				table
+					.write_insert_plan(&TableKey::Partial(key(k)), &value(20), writer, false)
+					.unwrap();
+			}
+			table.write_remove_plan(3, writer).unwrap();
+			table.write_remove_plan(7, writer).unwrap();
+			table.complete_plan(writer).unwrap();
+		});
+		let header = ValueTable::read_header(dir.path(), id).unwrap();
+		let counters = |table: &ValueTable| {
+			(table.filled.load(Ordering::Relaxed), table.last_removed.load(Ordering::Relaxed))
+		};
+		assert_eq!((header.filled, header.last_removed), counters(&table));
+		assert_eq!(header, super::TableHeader { last_removed: 7, filled: 13, entries: 12 });
+		drop(table);
+
+		let table = new_table(&dir, Some(ENTRY_SIZE), &rc_options());
+		assert_eq!((header.filled, header.last_removed), counters(&table));
+		assert_eq!(super::read_table_header(dir.path(), 0, 0).unwrap(), header);
+	}
+
+	#[test]
+	fn table_inventory() {
+		let dir = tempdir().unwrap();
+		let tables = [(0, 0), (0, 1), (0, 0xff), (3, 0x0a), (3, 2), (12, 0), (255, 0x10)];
+		for (col, tier) in tables {
+			let id = TableId::new(col, tier);
+			assert_eq!(TableId::from_file_name(&id.file_name()), Some(id));
+			std::fs::write(dir.path().join(id.file_name()), []).unwrap();
+		}
+		// Files that are not value tables.
+		let free_list = TableId::new(5, 0).free_list_file_name();
+		for name in
+			["index_01_16", "metadata", "table_1_00", "table_04_1", "table_04_zz", &free_list]
+		{
+			assert_eq!(TableId::from_file_name(name), None);
+			std::fs::write(dir.path().join(name), []).unwrap();
+		}
+		std::fs::create_dir(dir.path().join("table_06")).unwrap();
+
+		let expected =
+			[(0, vec![0, 1, 0xff]), (3, vec![2, 0x0a]), (12, vec![0]), (255, vec![0x10])];
+		assert_eq!(super::table_inventory(dir.path()).unwrap(), expected.into_iter().collect());
+	}
+
+	#[test]
+	fn access_pattern() {
+		use crate::file::AccessPattern;
+
+		let dir = tempdir().unwrap();
+		let mut table = new_table(&dir, Some(ENTRY_SIZE), &rc_options());
+		assert_eq!(table.file.access_pattern, AccessPattern::Random);
+		// Applied when the file is created by the first write.
+		table.set_access_pattern(AccessPattern::Sequential).unwrap();
+		let log = new_log(&dir);
+		let vals: Vec<_> = (0..3).map(|_| value(20)).collect();
+		write_ops(&table, &log, |writer| {
+			for (k, val) in vals.iter().enumerate() {
+				table
+					.write_insert_plan(&TableKey::Partial(key(k as u32)), val, writer, false)
+					.unwrap();
+			}
+		});
+		assert_eq!(table.file.access_pattern, AccessPattern::Sequential);
+
+		// And to the open file.
+		table.set_access_pattern(AccessPattern::Random).unwrap();
+		assert_eq!(table.file.access_pattern, AccessPattern::Random);
+		for (k, val) in vals.into_iter().enumerate() {
+			let key = TableKey::Partial(key(k as u32));
+			assert_eq!(table.get(&key, k as u64 + 1, log.overlays()).unwrap(), Some((val, false)));
+		}
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn double_open() {
+		let dir = tempdir().unwrap();
+		let open_with = |read_only| {
+			ValueTable::open(
+				Arc::new(dir.path().to_path_buf()),
+				TableId::new(0, 0),
+				Some(ENTRY_SIZE),
+				&rc_options(),
+				TableConfig { read_only, ..Default::default() },
+			)
+		};
+		let open = || open_with(false);
+		let table = open().unwrap();
+		let log = new_log(&dir);
+		// The file is created and locked on first write.
+		let other = open().unwrap();
+		let val = value(20);
+		write_ops(&table, &log, |writer| {
+			table
+				.write_insert_plan(&TableKey::Partial(key(1)), &val, writer, false)
+				.unwrap();
+		});
+		assert!(matches!(other.file.grow(ENTRY_SIZE), Err(crate::Error::AlreadyOpen(_))));
+		drop(other);
+
+		assert!(matches!(open(), Err(crate::Error::AlreadyOpen(_))));
+		assert!(matches!(open_with(true), Err(crate::Error::AlreadyOpen(_))));
+		drop(table);
+
+		// Read-only handles share the lock.
+		let reader = open_with(true).unwrap();
+		let other = open_with(true).unwrap();
+		assert!(matches!(open(), Err(crate::Error::AlreadyOpen(_))));
+		assert_eq!(
+			reader.get(&TableKey::Partial(key(1)), 1, log.overlays()).unwrap(),
+			Some((val.clone(), false))
+		);
+		drop((reader, other));
+
+		let table = open().unwrap();
+		assert_eq!(
+			table.get(&TableKey::Partial(key(1)), 1, log.overlays()).unwrap(),
+			Some((val, false))
+		);
+	}
+
+	#[test]
+	#[should_panic(expected = "Leaked slots [3]")]
+	fn leaked_slot_detection() {
+		let dir = tempdir().unwrap();
+		let table = new_table(&dir, Some(ENTRY_SIZE), &rc_options());
+		let log = new_log(&dir);
+		write_ops(&table, &log, |writer| {
+			for k in 0..2 {
+				table
+					.write_insert_plan(&TableKey::Partial(key(k)), &value(20), writer, false)
+					.unwrap();
+			}
+			table.complete_plan(writer).unwrap();
+		});
+		write_ops(&table, &log, |writer| {
+			// Allocated, but never written or freed.
+			assert_eq!(table.next_free(writer).unwrap(), 3);
+			table.write_remove_plan(1, writer).unwrap();
+			table.complete_plan(writer).unwrap();
+		});
+	}
+
+	#[test]
+	fn directory_sync_on_create() {
+		let dir = tempdir().unwrap();
+		let log = new_log(&dir);
+		for sync in [false, true] {
+			let mut table = ValueTable::open(
+				Arc::new(dir.path().to_path_buf()),
+				TableId::new(0, sync as u8),
+				Some(ENTRY_SIZE),
+				&rc_options(),
+				Default::default(),
+			)
+			.unwrap();
+			table.set_sync_directory(sync);
+			for k in 0..2 {
+				write_ops(&table, &log, |writer| {
+					table
+						.write_insert_plan(&TableKey::Partial(key(k)), &value(20), writer, false)
+						.unwrap();
+				});
+			}
+			assert!(dir.path().join(table.id.file_name()).exists());
+			// Only the file creation syncs the directory.
+			assert_eq!(table.file.dir_syncs.load(Ordering::Relaxed), sync as u64);
+		}
+	}
+
+	#[cfg(all(feature = "direct_io", target_os = "linux"))]
+	#[test]
+	fn direct_io() {
+		use std::os::unix::io::AsRawFd;
+		let dir = tempdir().unwrap();
+		let log = new_log(&dir);
+		let open = |direct: bool| {
+			// Not a divisor of the block size, so that some entries span two blocks.
+			let mut table = new_table(&dir, Some(100), &rc_options());
+			if direct {
+				table.enable_direct_io()?;
+			}
+			Ok::<_, crate::error::Error>(table)
+		};
+		let is_direct = |table: &ValueTable| {
+			let fd = table.file.file.read().as_ref().unwrap().as_raw_fd();
+			let info = std::fs::read_to_string(format!("/proc/self/fdinfo/{fd}")).unwrap();
+			let flags = info.lines().find_map(|l| l.strip_prefix("flags:")).unwrap();
+			let flags = i32::from_str_radix(flags.trim(), 8).unwrap();
+			flags & libc::O_DIRECT == libc::O_DIRECT
+		};
+		let file_len = || {
+			std::fs::metadata(dir.path().join(TableId::new(0, 0).file_name()))
+				.unwrap()
+				.len()
+		};
+		let values: Vec<_> = (0..200).map(|k| value(10 + k % 20)).collect();
+
+		// The file is created by the first write.
+		let table = match open(true) {
+			Ok(table) => table,
+			// The file system does not support direct I/O, as with tmpfs before Linux 6.6.
+			Err(crate::error::Error::Io(e)) if e.raw_os_error() == Some(libc::EINVAL) => return,
+			Err(e) => panic!("{e:?}"),
+		};
+		let mut indexes = Vec::new();
+		write_ops(&table, &log, |writer| {
+			for (k, val) in values.iter().enumerate() {
+				indexes.push(
+					table
+						.write_insert_plan(&TableKey::Partial(key(k as u32)), val, writer, false)
+						.unwrap(),
+				);
+			}
+			table.complete_plan(writer).unwrap();
+		});
+		assert!(is_direct(&table));
+		assert_eq!(file_len() % table.file.block_size, 0);
+		let check = |table: &ValueTable| {
+			for (k, (val, index)) in values.iter().zip(&indexes).enumerate() {
+				let key = simple_key(key(k as u32));
+				assert_eq!(
+					table.get(&key, *index, log.overlays()).unwrap(),
+					Some((val.clone(), false))
+				);
+			}
+		};
+		check(&table);
+		drop(table);
+
+		// Buffered reads see the same data.
+		let table = open(false).unwrap();
+		assert!(!is_direct(&table));
+		check(&table);
+		drop(table);
+
+		// Reopened existing file.
+		let table = open(true).unwrap();
+		assert!(is_direct(&table));
+		check(&table);
+		write_ops(&table, &log, |writer| {
+			table.write_remove_plan(indexes[1], writer).unwrap();
+			table.complete_plan(writer).unwrap();
+		});
+		assert_eq!(table.get(&simple_key(key(1)), indexes[1], log.overlays()).unwrap(), None);
+		assert_eq!(
+			table.get(&simple_key(key(0)), indexes[0], log.overlays()).unwrap(),
+			Some((values[0].clone(), false))
+		);
+	}
+
+	#[test]
+	fn dsync_writes() {
+		let dir = tempdir().unwrap();
+		let log = new_log(&dir);
+		let open = || {
+			ValueTable::open(
+				Arc::new(dir.path().to_path_buf()),
+				TableId::new(0, 0),
+				Some(ENTRY_SIZE),
+				&rc_options(),
+				TableConfig { dsync: true, ..Default::default() },
+			)
+			.unwrap()
+		};
+		#[cfg(target_os = "linux")]
+		let assert_dsync = |table: &ValueTable| {
+			use std::os::unix::io::AsRawFd;
+			let fd = table.file.file.read().as_ref().unwrap().as_raw_fd();
+			let info = std::fs::read_to_string(format!("/proc/self/fdinfo/{fd}")).unwrap();
+			let flags = info.lines().find_map(|l| l.strip_prefix("flags:")).unwrap();
+			let flags = i32::from_str_radix(flags.trim(), 8).unwrap();
+			assert_eq!(flags & libc::O_DSYNC, libc::O_DSYNC);
+		};
+
+		// The file is created by the first write.
+		let table = open();
+		let val1 = value(20);
+		write_ops(&table, &log, |writer| {
+			table
+				.write_insert_plan(&TableKey::Partial(key(1)), &val1, writer, false)
+				.unwrap();
+			table.complete_plan(writer).unwrap();
+		});
+		#[cfg(target_os = "linux")]
+		assert_dsync(&table);
+		drop(table);
+
+		// Reopened existing file.
+		let table = open();
+		#[cfg(target_os = "linux")]
+		assert_dsync(&table);
+		let val2 = value(30);
+		write_ops(&table, &log, |writer| {
+			table
+				.write_insert_plan(&TableKey::Partial(key(2)), &val2, writer, false)
+				.unwrap();
+			table.complete_plan(writer).unwrap();
+		});
+		drop(table);
+
+		let table = open();
+		let log = log.overlays();
+		assert_eq!(table.get(&simple_key(key(1)), 1, log).unwrap(), Some((val1, false)));
+		assert_eq!(table.get(&simple_key(key(2)), 2, log).unwrap(), Some((val2, false)));
+	}
+
+	#[test]
+	fn header_durability() {
+		let dir = tempdir().unwrap();
+		let log = new_log(&dir);
+		let cases = [
+			(Durability::None, false, 0),
+			(Durability::Data, false, 2),
+			(Durability::Full, false, 2),
+			// Writes are synced already.
+			(Durability::Data, true, 0),
+			(Durability::Full, true, 2),
+		];
+		for (i, (durability, dsync, syncs)) in cases.into_iter().enumerate() {
+			let mut table = ValueTable::open(
+				Arc::new(dir.path().to_path_buf()),
+				TableId::new(0, i as u8),
+				Some(ENTRY_SIZE),
+				&rc_options(),
+				TableConfig { dsync, ..Default::default() },
+			)
+			.unwrap();
+			table.set_durability(durability);
+			for k in 0..2 {
+				write_ops(&table, &log, |writer| {
+					table
+						.write_insert_plan(&TableKey::Partial(key(k)), &value(20), writer, false)
+						.unwrap();
+					table.complete_plan(writer).unwrap();
+				});
+			}
+			// Only header writes are synced.
+			write_ops(&table, &log, |writer| {
+				table.write_inc_ref(1, writer).unwrap();
+				table.complete_plan(writer).unwrap();
+			});
+			assert_eq!(*table.file.header_syncs.lock(), vec![durability; syncs]);
+		}
+	}
+
+	#[test]
+	fn entry_size_mismatch() {
+		let dir = tempdir().unwrap();
+		let path = Arc::new(dir.path().to_path_buf());
+		let log = new_log(&dir);
+		let open = |id, entry_size| {
+			ValueTable::open(path.clone(), id, entry_size, &rc_options(), Default::default())
+		};
+		for (id, created, opened) in [
+			(TableId::new(0, 0), Some(512), Some(1024)),
+			(TableId::new(0, 1), None, Some(MULTIPART_ENTRY_SIZE)),
+		] {
+			let table = open(id, created).unwrap();
+			write_ops(&table, &log, |writer| {
+				table
+					.write_insert_plan(&TableKey::Partial(key(1)), &value(20), writer, false)
+					.unwrap();
+				table.complete_plan(writer).unwrap();
+			});
+			drop(table);
+
+			assert!(matches!(open(id, opened), Err(crate::error::Error::FormatMismatch(_))));
+			let table = open(id, created).unwrap();
+			assert_eq!(table.filled.load(Ordering::Relaxed), 2);
+			drop(table);
+		}
+
+		// Tables created before the format was stored can't be checked.
+		let id = TableId::new(0, 0);
+		let file = dir.path().join(id.file_name());
+		let mut contents = std::fs::read(&file).unwrap();
+		assert_eq!(&contents[24..32], &[0, 2, 0, 0, 0, 0, 0, 0]);
+		contents[24..32].copy_from_slice(&[0; 8]);
+		std::fs::write(&file, &contents).unwrap();
+		assert!(open(id, Some(1024)).is_ok());
+	}
+
+	#[test]
+	fn table_magic() {
+		let dir = tempdir().unwrap();
+		let table = new_table(&dir, Some(ENTRY_SIZE), &rc_options());
+		let log = new_log(&dir);
+		write_ops(&table, &log, |writer| {
+			table
+				.write_insert_plan(&TableKey::Partial(key(1)), &value(20), writer, false)
+				.unwrap();
+			table.complete_plan(writer).unwrap();
+		});
+		table.flush().unwrap();
+		drop(table);
+
+		let id = TableId::new(0, 0);
+		let path = dir.path().join(id.file_name());
+		let mut contents = std::fs::read(&path).unwrap();
+		assert_eq!(&contents[16..24], super::TABLE_MAGIC);
+
+		// Tables created before the marker was introduced have zeroes there.
+		contents[16..24].copy_from_slice(&[0; 8]);
+		std::fs::write(&path, &contents).unwrap();
+		let table = new_table(&dir, Some(ENTRY_SIZE), &rc_options());
+		assert_eq!(table.filled.load(Ordering::Relaxed), 2);
+		drop(table);
+
+		contents[16..24].copy_from_slice(b"notadbvt");
+		std::fs::write(&path, &contents).unwrap();
+		let options = rc_options();
+		for result in [
+			ValueTable::open(
+				Arc::new(dir.path().to_path_buf()),
+				id,
+				Some(ENTRY_SIZE),
+				&options,
+				Default::default(),
+			)
+			.map(|_| ()),
+			ValueTable::read_header(dir.path(), id).map(|_| ()),
+		] {
+			assert!(matches!(result, Err(crate::error::Error::FormatMismatch(_))));
+		}
+		assert_eq!(std::fs::read(&path).unwrap(), contents);
+
+		// An empty file gets the header of a new file.
+		std::fs::write(&path, []).unwrap();
+		drop(new_table(&dir, Some(ENTRY_SIZE), &rc_options()));
+		let contents = std::fs::read(&path).unwrap();
+		assert_eq!(&contents[16..24], super::TABLE_MAGIC);
+		assert_eq!(&contents[24..32], &super::table_format(ENTRY_SIZE, false));
+		let result = ValueTable::open(
+			Arc::new(dir.path().to_path_buf()),
+			id,
+			Some(ENTRY_SIZE * 2),
+			&options,
+			Default::default(),
+		);
+		assert!(matches!(result, Err(crate::error::Error::FormatMismatch(_))));
+	}
+
+	#[test]
+	fn slot_reuse_grace() {
+		let dir = tempdir().unwrap();
+		let mut table = new_table(&dir, Some(ENTRY_SIZE), &rc_options());
+		table.set_slot_reuse_grace(Some(3)).unwrap();
+		let log = new_log(&dir);
+		let insert = |k: u32| {
+			let mut index = 0;
+			write_ops(&table, &log, |writer| {
+				index = table
+					.write_insert_plan(&TableKey::Partial(key(k)), &value(20), writer, false)
+					.unwrap();
+				table.complete_plan(writer).unwrap();
+			});
+			index
+		};
+
+		for k in 1..=5 {
+			assert_eq!(insert(k), k as u64);
+		}
+		write_ops(&table, &log, |writer| {
+			table.write_remove_plan(2, writer).unwrap();
+		});
+		// A stale address reads a removed entry while the slot is held back.
+		for k in 6..=8 {
+			assert_eq!(insert(k), k as u64);
+			assert_eq!(table.get(&TableKey::Partial(key(2)), 2, log.overlays()).unwrap(), None);
+			assert_eq!(table.tombstoned_slots(log.overlays()).count(), 0);
+		}
+		assert_eq!(table.free_count(), 1);
+		assert_eq!(insert(9), 2);
+		assert_eq!(insert(10), 9);
+		assert_eq!(table.free_count(), 0);
+
+		// Slots held back at shutdown are released with the first allocation after reopening.
+		write_ops(&table, &log, |writer| {
+			table.write_remove_plan(4, writer).unwrap();
+		});
+		assert_eq!(table.free_count(), 1);
+		drop(table);
+		let mut table = new_table(&dir, Some(ENTRY_SIZE), &rc_options());
+		table.set_slot_reuse_grace(Some(3)).unwrap();
+		assert_eq!(table.free_count(), 1);
+		let mut index = 0;
+		write_ops(&table, &log, |writer| {
+			index = table
+				.write_insert_plan(&TableKey::Partial(key(11)), &value(20), writer, false)
+				.unwrap();
+		});
+		assert_eq!(index, 4);
+		assert_eq!(table.free_count(), 0);
+	}
+
+	#[test]
+	fn iter_read_ahead() {
+		let dir = tempdir().unwrap();
+		let mut table = new_table(&dir, Some(ENTRY_SIZE), &rc_options());
+		let log = new_log(&dir);
+		write_ops(&table, &log, |writer| {
+			for k in 0..40 {
+				table
+					.write_insert_plan(&TableKey::Partial(key(k)), &value(20), writer, false)
+					.unwrap();
+			}
+		});
+		let overlays = log.overlays();
+		let hints = |table: &ValueTable, stop: u64| {
+			table.file.prefetches.lock().clear();
+			table.iter_snapshot_while(overlays, |index, _, _, _| index < stop).unwrap();
+			std::mem::take(&mut *table.file.prefetches.lock())
+		};
+		let bytes = |ranges: &[(u64, u64)]| {
+			let size = ENTRY_SIZE as u64;
+			ranges
+				.iter()
+				.map(|(from, to)| (from * size, (to - from) * size))
+				.collect::<Vec<_>>()
+		};
+
+		assert!(hints(&table, u64::MAX).is_empty());
+
+		// The first window is hinted in full, then half a window each time the cursor
+		// consumes half of the hinted entries. Only filled slots are hinted.
+		table.set_iter_read_ahead(8);
+		let mut expected = vec![(1, 9)];
+		expected.extend((9..41).step_by(4).map(|from| (from, from + 4)));
+		assert_eq!(hints(&table, u64::MAX), bytes(&expected));
+		// Slots are read a batch at a time, so the whole batch is hinted even if iteration stops
+		// early.
+		assert_eq!(hints(&table, 6), bytes(&expected));
+
+		table.file.prefetches.lock().clear();
+		table.iter_snapshot_checked_while(overlays, |_, _| true);
+		assert_eq!(*table.file.prefetches.lock(), bytes(&expected));
+	}
+
+	#[test]
+	fn mmap_reads() {
+		let dir = tempdir().unwrap();
+		let mut table = new_table(&dir, Some(ENTRY_SIZE), &rc_options());
+		table.set_grow_step(16 * ENTRY_SIZE as u64);
+		table.enable_mmap_reads().unwrap();
+		let log = new_log(&dir);
+		let disk = crate::log::LogOverlays::default();
+		let values: Vec<_> = (0..10u32).map(|k| (simple_key(key(k)), value(20))).collect();
+		let mut indexes = Vec::new();
+		write_ops(&table, &log, |writer| {
+			for (k, v) in &values {
+				indexes.push(table.write_insert_plan(k, v, writer, false).unwrap());
+			}
+		});
+		assert_eq!(table.file.mapped_len(), None);
+		for ((k, v), index) in values.iter().zip(&indexes) {
+			assert_eq!(table.get(k, *index, &disk).unwrap(), Some((v.clone(), false)));
+		}
+		assert_eq!(table.file.mapped_len(), Some(16 * ENTRY_SIZE as usize));
+
+		// Entries past the end of the mapping are read after remapping the grown file.
+		let (k, v) = (simple_key(key(100)), value(30));
+		let mut index = 0;
+		write_ops(&table, &log, |writer| {
+			for _ in 0..10 {
+				index = table.write_insert_plan(&k, &v, writer, false).unwrap();
+			}
+		});
+		assert!(index >= 16);
+		assert_eq!(table.get(&k, index, &disk).unwrap(), Some((v.clone(), false)));
+		assert_eq!(table.file.mapped_len(), Some(32 * ENTRY_SIZE as usize));
+
+		// Overlay entries take precedence over the mapped file.
+		let replaced = value(25);
+		let mut writer = log.begin_record();
+		table.write_replace_plan(index, &k, &replaced, &mut writer, false).unwrap();
+		log.end_record(writer.drain()).unwrap();
+		assert_eq!(table.get(&k, index, log.overlays()).unwrap(), Some((replaced, false)));
+		assert_eq!(table.get(&k, index, &disk).unwrap(), Some((v, false)));
+	}
+
+	#[test]
+	fn size_field_invariants() {
+		let size_field = |table: &ValueTable, index: u64| {
+			let entry = table.dump_entry(index).unwrap();
+			u16::from_le_bytes([entry[0], entry[1]])
+		};
+
+		let dir = tempdir().unwrap();
+		let table = new_table(&dir, Some(ENTRY_SIZE), &rc_options());
+		let log = new_log(&dir);
+		let prefix = (super::REFS_SIZE + super::key::PARTIAL_SIZE) as u16;
+		assert_eq!(prefix, 30);
+		for len in 0..3 {
+			let key = TableKey::Partial(key(len as u32));
+			let val = value(len);
+			let index = len as u64 + 1;
+			write_ops(&table, &log, |writer| {
+				assert_eq!(table.write_insert_plan(&key, &val, writer, false).unwrap(), index);
+			});
+			assert_eq!(size_field(&table, index), prefix + len as u16);
+			assert_eq!(table.get(&key, index, log.overlays()).unwrap(), Some((val, false)));
+			assert!(!table.is_tombstone(index, log.overlays()).unwrap());
+		}
+		write_ops(&table, &log, |writer| {
+			table.write_remove_plan(1, writer).unwrap();
+		});
+		assert!(table.is_tombstone(1, log.overlays()).unwrap());
+		assert_eq!(size_field(&table, 1), 0xffff);
+
+		// Without a prefix an empty value has zero size.
+		let dir = tempdir().unwrap();
+		let table = new_table(&dir, Some(ENTRY_SIZE), &Default::default());
+		let log = new_log(&dir);
+		write_ops(&table, &log, |writer| {
+			assert_eq!(table.write_insert_plan(&TableKey::NoHash, &[], writer, false).unwrap(), 1);
+		});
+		assert_eq!(size_field(&table, 1), 0);
+		assert!(!table.is_tombstone(1, log.overlays()).unwrap());
+		assert_eq!(table.get(&TableKey::NoHash, 1, log.overlays()).unwrap(), Some((vec![], false)));
+	}
+
+	#[test]
+	fn reused_read_buffers() {
+		let dir = tempdir().unwrap();
+		let log = new_log(&dir);
+		let sizes = [Some(ENTRY_SIZE), Some(400), None];
+		let tables: Vec<ValueTable> = sizes
+			.iter()
+			.enumerate()
+			.map(|(tier, size)| {
+				ValueTable::open(
+					Arc::new(dir.path().to_path_buf()),
+					TableId::new(0, tier as u8),
+					*size,
+					&rc_options(),
+					TableConfig { reuse_read_buffer: true, ..Default::default() },
+				)
+				.unwrap()
+			})
+			.collect();
+		let values = [value(20), value(300), value(50000)];
+		let mut indices = vec![Vec::new(); tables.len()];
+
+		for (i, table) in tables.iter().enumerate() {
+			write_ops(table, &log, |writer| {
+				for k in 0..4 {
+					let index = table
+						.write_insert_plan(&TableKey::Partial(key(k)), &values[i], writer, false)
+						.unwrap();
+					indices[i].push(index);
+				}
+			});
+		}
+
+		for k in 0..4 {
+			for (i, table) in tables.iter().enumerate().chain(tables.iter().enumerate().rev()) {
+				let table_key = TableKey::Partial(key(k));
+				let index = indices[i][k as usize];
+				assert_eq!(
+					table.get(&table_key, index, log.overlays()).unwrap(),
+					Some((values[i].clone(), false))
+				);
+				assert_eq!(
+					table.size(&table_key, index, log.overlays()).unwrap(),
+					Some((values[i].len() as u32, false))
+				);
+				assert_eq!(
+					table.get(&TableKey::Partial(key(9)), index, log.overlays()).unwrap(),
+					None
+				);
+			}
+		}
+		// A single buffer of the largest size used is kept.
+		assert_eq!(
+			super::READ_BUFFER.with(|buffer| buffer.borrow().len()),
+			MULTIPART_ENTRY_SIZE as usize
+		);
+	}
+
+	// Write a multipart value into slots freed from other values, so that its parts are scattered.
+	fn write_scattered_chain(table: &ValueTable, log: &Log, val: &[u8]) -> u64 {
+		let mut indexes = Vec::new();
+		write_ops(table, log, |writer| {
+			for k in 0..(val.len() / 4000) as u32 {
+				let index = table
+					.write_insert_plan(&TableKey::Partial(key(k)), &value(10000), writer, false)
+					.unwrap();
+				indexes.push(index);
+			}
+		});
+		write_ops(table, log, |writer| {
+			for index in indexes.iter().step_by(2) {
+				table.write_remove_plan(*index, writer).unwrap();
+			}
+		});
+		let mut index = 0;
+		write_ops(table, log, |writer| {
+			index = table
+				.write_insert_plan(&TableKey::Partial(key(u32::MAX)), val, writer, false)
+				.unwrap();
+		});
+		index
+	}
+
+	#[test]
+	fn prefetched_multipart_reads() {
+		let dir = tempdir().unwrap();
+		let mut table = new_table(&dir, None, &rc_options());
+		table.set_prefetch_depth(4);
+		let log = new_log(&dir);
+		let val = value(40000);
+		let index = write_scattered_chain(&table, &log, &val);
+
+		let mut parts = vec![index];
+		while let Some(next) = table.read_next_part(*parts.last().unwrap(), log.overlays()).unwrap()
+		{
+			parts.push(next);
+		}
+		assert_eq!(parts.len(), 10);
+		assert!(parts.windows(2).any(|w| w[1] != w[0] + 1));
+
+		let key = TableKey::Partial(key(u32::MAX));
+		assert_eq!(table.get(&key, index, log.overlays()).unwrap(), Some((val.clone(), false)));
+		table.set_prefetch_depth(0);
+		assert_eq!(table.get(&key, index, log.overlays()).unwrap(), Some((val, false)));
+	}
+
+	#[cfg(feature = "bench")]
+	fn bench_query_internal(b: &mut Bencher, reuse_read_buffer: bool) {
+		let dir = tempdir().unwrap();
+		let table = ValueTable::open(
+			Arc::new(dir.path().to_path_buf()),
+			TableId::new(0, 0),
+			Some(ENTRY_SIZE),
+			&rc_options(),
+			TableConfig { reuse_read_buffer, ..Default::default() },
+		)
+		.unwrap();
+		let log = new_log(&dir);
+		let val = value(20);
+		write_ops(&table, &log, |writer| {
+			for k in 0..1000 {
+				table
+					.write_insert_plan(&TableKey::Partial(key(k)), &val, writer, false)
+					.unwrap();
+			}
+		});
+
+		let mut k = 0;
+		b.iter(|| {
+			let index = k as u64 + 1;
+			assert!(table
+				.get(&TableKey::Partial(key(k)), index, log.overlays())
+				.unwrap()
+				.is_some());
+			k = (k + 1) % 1000;
+		});
+	}
+
+	#[cfg(feature = "bench")]
+	#[bench]
+	fn bench_query_stack_buffer(b: &mut Bencher) {
+		bench_query_internal(b, false)
+	}
+
+	#[cfg(feature = "bench")]
+	#[bench]
+	fn bench_query_reused_buffer(b: &mut Bencher) {
+		bench_query_internal(b, true)
+	}
+
+	#[cfg(feature = "bench")]
+	fn bench_query_scattered_chain(b: &mut Bencher, prefetch_depth: u32) {
+		let dir = tempdir().unwrap();
+		let mut table = new_table(&dir, None, &rc_options());
+		table.set_prefetch_depth(prefetch_depth);
+		let log = new_log(&dir);
+		let index = write_scattered_chain(&table, &log, &value(1_000_000));
+		table.flush().unwrap();
+
+		let key = TableKey::Partial(key(u32::MAX));
+		b.iter(|| {
+			assert!(table.get(&key, index, log.overlays()).unwrap().is_some());
+		});
+	}
+
+	#[cfg(feature = "bench")]
+	#[bench]
+	fn bench_query_scattered_chain_no_prefetch(b: &mut Bencher) {
+		bench_query_scattered_chain(b, 0)
+	}
+
+	#[cfg(feature = "bench")]
+	#[bench]
+	fn bench_query_scattered_chain_prefetch(b: &mut Bencher) {
+		bench_query_scattered_chain(b, 4)
+	}
+
+	// Reads entries of a table much larger than read ahead windows in random order. With the
+	// `Sequential` pattern each read pulls neighbouring entries into the page cache as well.
+	#[cfg(feature = "bench")]
+	fn bench_random_reads(b: &mut Bencher, pattern: crate::file::AccessPattern) {
+		const ENTRIES: u32 = 100_000;
+		let dir = tempdir().unwrap();
+		let mut table = new_table(&dir, Some(ENTRY_SIZE), &rc_options());
+		table.set_access_pattern(pattern).unwrap();
+		let log = new_log(&dir);
+		let val = value(20);
+		write_ops(&table, &log, |writer| {
+			for k in 0..ENTRIES {
+				table
+					.write_insert_plan(&TableKey::Partial(key(k)), &val, writer, false)
+					.unwrap();
+			}
+		});
+		table.flush().unwrap();
+
+		let mut k: u32 = 0;
+		b.iter(|| {
+			// Multiplicative hash to spread reads over the whole file.
+			let index = k.wrapping_mul(2_654_435_761) % ENTRIES;
+			assert!(table
+				.get(&TableKey::Partial(key(index)), index as u64 + 1, log.overlays())
+				.unwrap()
+				.is_some());
+			k = k.wrapping_add(1);
+		});
+	}
+
+	#[cfg(feature = "bench")]
+	#[bench]
+	fn bench_random_reads_random_pattern(b: &mut Bencher) {
+		bench_random_reads(b, crate::file::AccessPattern::Random)
+	}
+
+	#[cfg(feature = "bench")]
+	#[bench]
+	fn bench_random_reads_sequential_pattern(b: &mut Bencher) {
+		bench_random_reads(b, crate::file::AccessPattern::Sequential)
+	}
+
+	// Random reads of a table that fits in the page cache, with `pread` or from a memory map.
+	#[cfg(feature = "bench")]
+	fn bench_random_reads_mmap(b: &mut Bencher, mmap: bool) {
+		const ENTRIES: u32 = 100_000;
+		let dir = tempdir().unwrap();
+		let mut table = new_table(&dir, Some(ENTRY_SIZE), &rc_options());
+		if mmap {
+			table.enable_mmap_reads().unwrap();
+		}
+		let log = new_log(&dir);
+		let val = value(20);
+		write_ops(&table, &log, |writer| {
+			for k in 0..ENTRIES {
+				table
+					.write_insert_plan(&TableKey::Partial(key(k)), &val, writer, false)
+					.unwrap();
+			}
+		});
+		table.flush().unwrap();
+
+		let mut k: u32 = 0;
+		b.iter(|| {
+			// Multiplicative hash to spread reads over the whole file.
+			let index = k.wrapping_mul(2_654_435_761) % ENTRIES;
+			assert!(table
+				.get(&TableKey::Partial(key(index)), index as u64 + 1, log.overlays())
+				.unwrap()
+				.is_some());
+			k = k.wrapping_add(1);
+		});
+	}
+
+	#[cfg(feature = "bench")]
+	#[bench]
+	fn bench_random_reads_pread(b: &mut Bencher) {
+		bench_random_reads_mmap(b, false)
+	}
+
+	#[cfg(feature = "bench")]
+	#[bench]
+	fn bench_random_reads_mmap_reads(b: &mut Bencher) {
+		bench_random_reads_mmap(b, true)
+	}
+
+	// Loads 100k entries. Larger growth steps resize the file less often.
+	#[cfg(feature = "bench")]
+	fn bench_insert_grow_step(b: &mut Bencher, grow_step: u64) {
+		const ENTRIES: u32 = 100_000;
+		let val = value(20);
+		let keys: Vec<_> = (0..ENTRIES).map(|k| simple_key(key(k))).collect();
+		b.iter(|| {
+			let dir = tempdir().unwrap();
+			let mut table = new_table(&dir, Some(ENTRY_SIZE), &rc_options());
+			let log = new_log(&dir);
+			table.set_grow_step(grow_step);
+			insert_all(&table, &log, &keys, &val);
+			assert_eq!(
+				table.file.grows.load(Ordering::Relaxed),
+				(ENTRIES as u64 + 1).div_ceil(table.file.grow_entries(ENTRY_SIZE))
+			);
+		});
+	}
+
+	#[cfg(feature = "bench")]
+	#[bench]
+	fn bench_insert_default_grow_step(b: &mut Bencher) {
+		bench_insert_grow_step(b, crate::file::GROW_SIZE_BYTES)
+	}
+
+	#[cfg(feature = "bench")]
+	#[bench]
+	fn bench_insert_large_grow_step(b: &mut Bencher) {
+		bench_insert_grow_step(b, 64 * 1024 * 1024)
+	}
+
+	#[cfg(feature = "bench")]
+	fn bench_get_batch(b: &mut Bencher, batch: bool) {
+		const ENTRIES: u32 = 100_000;
+		let dir = tempdir().unwrap();
+		let table = new_table(&dir, Some(ENTRY_SIZE), &rc_options());
+		let log = new_log(&dir);
+		let val = value(20);
+		let keys: Vec<_> = (0..ENTRIES).map(|k| simple_key(key(k))).collect();
+		insert_all(&table, &log, &keys, &val);
+		table.flush().unwrap();
+
+		// 1000 distinct random keys, value `k` is at index `k + 1`.
+		let mut rng = rand::thread_rng();
+		let requests: Vec<_> = rand::seq::index::sample(&mut rng, ENTRIES as usize, 1000)
+			.into_iter()
+			.map(|k| (simple_key(key(k as u32)), k as u64 + 1))
+			.collect();
+		b.iter(|| {
+			if batch {
+				assert!(table
+					.get_many(&requests, log.overlays())
+					.unwrap()
+					.iter()
+					.all(|v| v.is_some()));
+			} else {
+				for (key, index) in &requests {
+					assert!(table.get(key, *index, log.overlays()).unwrap().is_some());
+				}
+			}
+		});
+	}
+
+	#[cfg(feature = "bench")]
+	#[bench]
+	fn bench_get_batch_individual(b: &mut Bencher) {
+		bench_get_batch(b, false)
+	}
+
+	#[cfg(feature = "bench")]
+	#[bench]
+	fn bench_get_batch_many(b: &mut Bencher) {
+		bench_get_batch(b, true)
+	}
+
+	#[test]
+	fn deterministic_encryption() {
+		for deterministic in [false, true] {
+			let dir = tempdir().unwrap();
+			let encryption = crate::EncryptionOptions { key: [1; 32], deterministic };
+			let table = ValueTable::open(
+				Arc::new(dir.path().to_path_buf()),
+				TableId::new(0, 0),
+				None,
+				&rc_options(),
+				TableConfig {
+					cipher: Some(crate::encrypt::Cipher::new(&encryption)),
+					..Default::default()
+				},
+			)
+			.unwrap();
+			let log = new_log(&dir);
+
+			let key = &TableKey::NoHash;
+			let val = value(10000);
+			write_ops(&table, &log, |writer| {
+				assert_eq!(table.write_insert_plan(key, &val, writer, false).unwrap(), 1);
+				assert_eq!(table.write_insert_plan(key, &val, writer, false).unwrap(), 4);
+			});
+
+			let stored = |index| {
+				let mut result = Vec::new();
+				table
+					.for_parts(&mut TableKeyQuery::Check(key), index, log.overlays(), |buf| {
+						result.extend_from_slice(buf);
+						true
+					})
+					.unwrap();
+				result
+			};
+			let (stored1, stored2) = (stored(1), stored(4));
+			assert_eq!(stored1.len(), val.len() + crate::encrypt::OVERHEAD);
+			assert!(!stored1.windows(16).any(|w| w == &val[..16]));
+			assert_eq!(stored1 == stored2, deterministic);
+
+			assert_eq!(table.get(key, 1, log.overlays()).unwrap(), Some((val.clone(), false)));
+			assert_eq!(table.get(key, 4, log.overlays()).unwrap(), Some((val.clone(), false)));
+			assert_eq!(
+				table.size(key, 4, log.overlays()).unwrap(),
+				Some((val.len() as u32, false))
+			);
+		}
+	}
+
 	#[test]
 	fn ref_underflow() {
 		let dir = tempdir().unwrap();